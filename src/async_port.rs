@@ -0,0 +1,290 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// async commands for use inside a tokio runtime
+use crate::error::RelayError;
+use crate::frame::{Frame, Framer};
+use crate::port::{RelayState, Wiring};
+use crate::protocol::Protocol;
+use anyhow::{bail, Context, Result};
+use log::debug;
+use std::fs::File;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time;
+
+pub(crate) trait AsyncReadWrite: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T> AsyncReadWrite for T where T: AsyncRead + AsyncWrite + Unpin + Send {}
+
+/// async variant of [`Port`](crate::Port), opened with
+/// [`OpenOptions::open_async`](crate::OpenOptions::open_async)
+///
+/// the frames are the same as sent by the blocking port, the delays are
+/// awaited instead of blocking the thread
+pub struct AsyncPort {
+    port: Box<dyn AsyncReadWrite>,
+    path: String,
+    protocol: Protocol,
+    channel: u8,
+    wiring: Wiring,
+    timeout: Duration,
+    frame_delay: Duration,
+    clamp: bool,
+    /// held while the port is open, like the blocking port's lock
+    _lock: Option<File>,
+}
+
+impl AsyncPort {
+    pub(crate) fn new(
+        port: Box<dyn AsyncReadWrite>,
+        path: String,
+        timeout: Duration,
+        frame_delay: Duration,
+        lock: Option<File>,
+    ) -> AsyncPort {
+        AsyncPort {
+            port,
+            path,
+            protocol: Protocol::default(),
+            channel: 1,
+            wiring: Wiring::default(),
+            timeout,
+            frame_delay,
+            clamp: false,
+            _lock: lock,
+        }
+    }
+
+    fn framer(&self) -> Framer<'_> {
+        Framer {
+            protocol: &self.protocol,
+            channel: self.channel,
+            wiring: self.wiring,
+        }
+    }
+
+    async fn write(&mut self, command: Frame) -> Result<()> {
+        let command = self.protocol.seal(command);
+
+        debug!("{}: write {:02X?}", self.path, command);
+        self.port
+            .write_all(&command)
+            .await
+            .with_context(|| format!("failed to write {}", self.path))?;
+        self.port.flush().await?;
+
+        time::sleep(self.frame_delay).await;
+        Ok(())
+    }
+
+    async fn write_frames(&mut self, frames: &[Frame]) -> Result<()> {
+        for frame in frames {
+            self.write(*frame).await?;
+        }
+        Ok(())
+    }
+
+    async fn read(&mut self) -> Result<Frame> {
+        let mut response = [0; 4];
+
+        match time::timeout(self.timeout, self.port.read_exact(&mut response)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => return Err(e).with_context(|| format!("failed to read {}", self.path)),
+            Err(_) => bail!(RelayError::Timeout(format!(
+                "{}: no response from relay",
+                self.path
+            ))),
+        }
+
+        debug!("{}: read {:02X?}", self.path, response);
+
+        if !self.protocol.is_valid(&response) {
+            bail!(RelayError::Io(format!(
+                "{}: checksum mismatch in response {:02X?}",
+                self.path, response
+            )));
+        }
+
+        Ok(response)
+    }
+
+    /// port path
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// set the frame layout, resets the channel to 1
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+        self.channel = 1;
+    }
+
+    /// cap timers above [`Protocol::max_timer`] instead of rejecting them
+    pub fn set_clamp(&mut self, clamp: bool) {
+        self.clamp = clamp;
+    }
+
+    /// set the relay contact the load is wired to
+    pub fn set_wiring(&mut self, wiring: Wiring) {
+        self.wiring = wiring;
+    }
+
+    /// select the relay channel (1-based) for the following commands
+    pub fn set_channel(&mut self, channel: u8) -> Result<(), RelayError> {
+        if channel == 0 || channel > self.protocol.channels {
+            return Err(RelayError::InvalidArgument(format!(
+                "invalid channel {}, board has {} channel(s)",
+                channel, self.protocol.channels
+            )));
+        }
+
+        self.channel = channel;
+        Ok(())
+    }
+
+    /// start immediately
+    pub async fn on(&mut self) -> Result<(), RelayError> {
+        debug!("on command");
+        let frames = self.framer().on()?;
+        Ok(self.write_frames(&frames).await?)
+    }
+
+    /// stop immediately
+    pub async fn off(&mut self) -> Result<(), RelayError> {
+        debug!("off command");
+        let frames = self.framer().off()?;
+        Ok(self.write_frames(&frames).await?)
+    }
+
+    /// start after n seconds
+    pub async fn timed_on(&mut self, timeout: u16) -> Result<(), RelayError> {
+        debug!("on after {} seconds", timeout);
+        let timer = self.protocol.timer(timeout, self.clamp)?;
+        let frames = self.framer().timed_on(timer)?;
+        Ok(self.write_frames(&frames).await?)
+    }
+
+    /// stop after n seconds
+    pub async fn timed_off(&mut self, timeout: u16) -> Result<(), RelayError> {
+        debug!("off after {} seconds", timeout);
+        let timer = self.protocol.timer(timeout, self.clamp)?;
+        let frames = self.framer().timed_off(timer)?;
+        Ok(self.write_frames(&frames).await?)
+    }
+
+    /// toggle power
+    pub async fn toggle(&mut self) -> Result<(), RelayError> {
+        debug!("toggle command");
+        let frames = self.framer().toggle()?;
+        Ok(self.write_frames(&frames).await?)
+    }
+
+    /// quick toggle power
+    pub async fn jog(&mut self) -> Result<(), RelayError> {
+        debug!("jog command");
+        let frames = self.framer().jog()?;
+        Ok(self.write_frames(&frames).await?)
+    }
+
+    /// software restart
+    pub async fn restart(&mut self) -> Result<(), RelayError> {
+        debug!("restart command");
+        self.off().await?;
+        time::sleep(Duration::from_secs(1)).await;
+        self.on().await
+    }
+
+    /// read the current relay state
+    pub async fn status(&mut self) -> Result<RelayState, RelayError> {
+        debug!("status command");
+        let query = self.framer().query()?;
+        self.write(query).await?;
+
+        let response = self.read().await?;
+        match self.framer().state(response) {
+            Some(state) => Ok(state),
+            None => Err(RelayError::Io(format!(
+                "{}: unexpected response from relay {:02X?}",
+                self.path, response
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, DuplexStream};
+
+    fn create_stub_port() -> (AsyncPort, DuplexStream) {
+        let (port, device) = duplex(64);
+        let mut port = AsyncPort::new(
+            Box::new(port),
+            "stub".to_string(),
+            Duration::from_millis(10),
+            Duration::ZERO,
+            None,
+        );
+        port.set_wiring(Wiring::NormallyOpen);
+        (port, device)
+    }
+
+    async fn received(device: &mut DuplexStream, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        device.read_exact(&mut buf).await.unwrap();
+        buf
+    }
+
+    #[tokio::test]
+    async fn test_on() {
+        let (mut port, mut device) = create_stub_port();
+
+        port.on().await.unwrap();
+
+        assert_eq!(
+            received(&mut device, 8).await,
+            [0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x01, 0x53]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timed_off() {
+        let (mut port, mut device) = create_stub_port();
+
+        port.timed_off(5).await.unwrap();
+
+        assert_eq!(
+            received(&mut device, 12).await,
+            [0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x01, 0x53, 0xF0, 0x00, 0x05, 0x57]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let (mut port, mut device) = create_stub_port();
+        device.write_all(&[0xF0, 0xA0, 0x00, 0x53]).await.unwrap();
+
+        assert_eq!(port.status().await.unwrap(), RelayState::Off);
+        assert_eq!(received(&mut device, 4).await, [0xF0, 0xA0, 0x0C, 0x56]);
+    }
+
+    #[tokio::test]
+    async fn test_status_timeout() {
+        let (mut port, _device) = create_stub_port();
+
+        assert!(port.status().await.is_err());
+    }
+}
@@ -0,0 +1,95 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// last autodetected port path, repeated invocations open it without
+/// enumeration while its USB ids still match
+use log::debug;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// cached port path if it was found with the same `key` and still exists
+pub(crate) fn load(cache: &Path, key: &str) -> Option<String> {
+    let port = load_value(cache, key)?;
+    if !Path::new(&port).exists() {
+        debug!("cached port {} is gone", port);
+        return None;
+    }
+
+    Some(port)
+}
+
+/// cached value if it was stored with the same `key`
+pub(crate) fn load_value(cache: &Path, key: &str) -> Option<String> {
+    let content = fs::read_to_string(cache).ok()?;
+    let mut lines = content.lines();
+
+    if lines.next()? != key {
+        debug!("cache {} was written for other device", cache.display());
+        return None;
+    }
+
+    lines.next().map(str::to_string)
+}
+
+/// remember the port (or other value) found with `key`
+pub(crate) fn store(cache: &Path, key: &str, port: &str) -> io::Result<()> {
+    if let Some(dir) = cache.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    fs::write(cache, format!("{}\n{}\n", key, port))
+}
+
+/// forget the cached port
+pub(crate) fn invalidate(cache: &Path) {
+    match fs::remove_file(cache) {
+        Ok(()) => debug!("port cache {} was invalidated", cache.display()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => debug!("failed to remove port cache {}: {}", cache.display(), e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_cache() {
+        let dir = env::temp_dir().join(format!("tty_relay_cache_{}", std::process::id()));
+        let cache = dir.join("port");
+
+        assert_eq!(load(&cache, "1a86:7523 *"), None);
+
+        store(&cache, "1a86:7523 *", "/dev/null").unwrap();
+        assert_eq!(load(&cache, "1a86:7523 *"), Some("/dev/null".to_string()));
+        assert_eq!(load(&cache, "10c4:ea60 *"), None);
+
+        store(&cache, "1a86:7523 *", "/dev/NOT_FOUND").unwrap();
+        assert_eq!(load(&cache, "1a86:7523 *"), None);
+        assert_eq!(
+            load_value(&cache, "1a86:7523 *"),
+            Some("/dev/NOT_FOUND".to_string())
+        );
+
+        invalidate(&cache);
+        assert!(!cache.exists());
+        invalidate(&cache);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
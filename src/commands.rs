@@ -0,0 +1,786 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// subcommand table: the clap definition of every command next to its parser
+/// and its port action
+use crate::events::Events;
+use crate::{
+    build_app, hex, is_channel, is_count, is_hold, is_milliseconds, is_seconds, is_timer,
+    parse_frame, parse_timer, repl, schedule, script, wiring, Cmd, APPNAME,
+};
+use anyhow::{bail, Result};
+use chrono::Local;
+use clap::{Arg, ArgMatches, Command};
+use std::io::{self, IsTerminal};
+use std::iter;
+use std::path::PathBuf;
+use std::time::Duration;
+use tty_relay::{Port, RelayError, RelayState};
+
+/// builds the command from the matches of its subcommand
+pub type Parser = fn(&ArgMatches) -> Result<Cmd>;
+
+/// runs the command parsed by the same entry on the open port, returning the
+/// state to print
+pub type Action = fn(&mut Port, Cmd) -> Result<Option<RelayState>>;
+
+/// all subcommands in help order
+pub fn table() -> Vec<(Command<'static>, Parser, Action)> {
+    macro_rules! timed_command {
+        ($name:expr) => {
+            Command::new(concat!("timed_", $name))
+                .about(concat!($name, " after n seconds"))
+                .arg(
+                    Arg::new("seconds")
+                        .help("seconds, or a duration like 90m or 1h30m")
+                        .required(true)
+                        .validator(is_timer),
+                )
+        };
+    }
+
+    #[allow(unused_mut)]
+    let mut table: Vec<(Command<'static>, Parser, Action)> = vec![
+        (
+            Command::new("on").about("enable power").arg(mask_arg()),
+            |matches| Ok(switch(matches, RelayState::On).unwrap_or(Cmd::On)),
+            |port, cmd| {
+                match cmd {
+                    Cmd::Switch { channels, state } => port.switch(&channels, state)?,
+                    _ => port.on()?,
+                }
+                Ok(None)
+            },
+        ),
+        (
+            Command::new("off").about("disable power").arg(mask_arg()),
+            |matches| Ok(switch(matches, RelayState::Off).unwrap_or(Cmd::Off)),
+            |port, cmd| {
+                match cmd {
+                    Cmd::Switch { channels, state } => port.switch(&channels, state)?,
+                    _ => port.off()?,
+                }
+                Ok(None)
+            },
+        ),
+        (
+            Command::new("set")
+                .about("switch to the state unless the relay already reports it, unlike toggle repeating it is safe")
+                .arg(
+                    Arg::new("state")
+                        .required(true)
+                        .possible_values(["on", "off"]),
+                ),
+            |matches| {
+                Ok(Cmd::Set(match matches.value_of("state") {
+                    Some("on") => RelayState::On,
+                    _ => RelayState::Off,
+                }))
+            },
+            |port, cmd| {
+                let Cmd::Set(state) = cmd else { unreachable!() };
+                if !port.set(state)? {
+                    log::info!("{}: already {}", port.path(), state);
+                }
+                Ok(Some(state))
+            },
+        ),
+        (
+            Command::new("toggle").about("toggle power").arg(
+                Arg::new("report")
+                    .long("report")
+                    .help("read and print the resulting state"),
+            ),
+            |matches| {
+                Ok(Cmd::Toggle {
+                    report: matches.is_present("report"),
+                })
+            },
+            |port, cmd| {
+                port.toggle()?;
+                if matches!(cmd, Cmd::Toggle { report: true }) {
+                    match port.status() {
+                        Ok(state) => return Ok(Some(state)),
+                        Err(e) => log::warn!("failed to read the state after toggle: {:#}", e),
+                    }
+                }
+                Ok(None)
+            },
+        ),
+        (
+            Command::new("jog").about("quick toggle power").arg(
+                Arg::new("hold")
+                    .long("hold")
+                    .value_name("MS")
+                    .help("connect for this long, timed by the host instead of the firmware (blocks)")
+                    .takes_value(true)
+                    .validator(is_hold),
+            ),
+            |matches| match matches.value_of("hold") {
+                Some(_) => Ok(Cmd::JogHold(matches.value_of_t("hold")?)),
+                None => Ok(Cmd::Jog),
+            },
+            |port, cmd| {
+                match cmd {
+                    Cmd::JogHold(ms) => port.jog_hold(Duration::from_millis(ms))?,
+                    _ => port.jog()?,
+                }
+                Ok(None)
+            },
+        ),
+        (
+            Command::new("restart").about("software restart"),
+            |_| Ok(Cmd::Restart),
+            |port, _| {
+                port.restart()?;
+                Ok(None)
+            },
+        ),
+        (
+            Command::new("reset").about("leave jog mode: control mode with power disabled"),
+            |_| Ok(Cmd::Reset),
+            |port, _| {
+                port.reset()?;
+                Ok(None)
+            },
+        ),
+        (
+            Command::new("status").about("show power state"),
+            |_| Ok(Cmd::Status),
+            |port, _| Ok(Some(port.status()?)),
+        ),
+        (
+            Command::new("info").about("show firmware revision and channel count"),
+            |_| Ok(Cmd::Info),
+            |port, _| {
+                info(port)?;
+                Ok(None)
+            },
+        ),
+        (
+            Command::new("probe")
+                .about("check that the board is reachable without switching it, for health checks")
+                .arg(
+                    Arg::new("ack")
+                        .long("ack")
+                        .help("only count the board as reachable when it echoes the frame back"),
+                ),
+            |matches| {
+                Ok(Cmd::Probe {
+                    ack: matches.is_present("ack"),
+                })
+            },
+            |port, cmd| {
+                let Cmd::Probe { ack } = cmd else { unreachable!() };
+                let latency = port.probe(ack)?;
+                println!("{}: reachable in {}ms", port.path(), latency.as_millis());
+                Ok(None)
+            },
+        ),
+        (
+            Command::new("verify-wiring")
+                .about("cycle the relay and ask whether power followed, to check --normally-closed"),
+            |_| Ok(Cmd::VerifyWiring),
+            |port, _| {
+                verify_wiring(port)?;
+                Ok(None)
+            },
+        ),
+        (
+            Command::new("capabilities")
+                .about("print the version, commands, protocols and features of this build as JSON"),
+            |_| Ok(Cmd::Capabilities),
+            |_, _| unreachable!("capabilities does not open the port"),
+        ),
+        (
+            Command::new("raw")
+                .about("send a hand-crafted frame of 4 hex bytes, like F0 A0 0C 54")
+                .arg(
+                    Arg::new("frame")
+                        .required(true)
+                        .multiple_values(true)
+                        .value_name("hexbytes"),
+                )
+                .arg(
+                    Arg::new("read")
+                        .long("read")
+                        .help("print the frame the board answers with"),
+                ),
+            |matches| {
+                let words: Vec<&str> = matches.values_of("frame").unwrap_or_default().collect();
+                Ok(Cmd::Raw {
+                    frame: parse_frame(&words.join(" ")).map_err(RelayError::InvalidArgument)?,
+                    read: matches.is_present("read"),
+                })
+            },
+            |port, cmd| {
+                let Cmd::Raw { frame, read } = cmd else { unreachable!() };
+                raw(port, frame, read)?;
+                Ok(None)
+            },
+        ),
+        (
+            Command::new("list").about("list USB serial devices"),
+            |_| Ok(Cmd::List),
+            |_, _| unreachable!("list does not open the port"),
+        ),
+        (
+            Command::new("show")
+                .about("print the frames a command sends, without a device")
+                .arg(
+                    Arg::new("command")
+                        .required(true)
+                        .multiple_values(true)
+                        .allow_hyphen_values(true),
+                ),
+            parse_show,
+            |_, _| unreachable!("show does not open the port"),
+        ),
+        (
+            Command::new("restore").about("re-apply the state saved in --state-file"),
+            |_| Ok(Cmd::Restore),
+            |_, _| unreachable!("restore is handled together with the state file"),
+        ),
+        (
+            Command::new("run")
+                .about("run a script like \"on; sleep 2; off\" on one open port")
+                .arg(Arg::new("script").required(true)),
+            |matches| {
+                let script = matches.value_of("script").unwrap_or_default();
+                Ok(Cmd::Run(script::parse(script).map_err(|e| {
+                    RelayError::InvalidArgument(format!("{:#}", e))
+                })?))
+            },
+            |port, cmd| {
+                let Cmd::Run(steps) = cmd else { unreachable!() };
+                script::run(port, steps)?;
+                Ok(None)
+            },
+        ),
+        (
+            Command::new("pipe")
+                .about("run the commands read from stdin line by line, keeping the port open"),
+            |_| Ok(Cmd::Pipe),
+            |port, _| {
+                script::run_stream(port, io::stdin().lock())?;
+                Ok(None)
+            },
+        ),
+        (
+            Command::new("repl").about("read commands from stdin, keeping the port open"),
+            |_| Ok(Cmd::Repl),
+            |port, _| {
+                let stdin = io::stdin();
+                let prompt = stdin.is_terminal();
+                repl::run(port, stdin.lock(), io::stdout(), prompt)?;
+                Ok(None)
+            },
+        ),
+        (
+            Command::new("pulse")
+                .about("enable power for n milliseconds (blocks for the duration)")
+                .arg(Arg::new("ms").required(true).validator(is_milliseconds))
+                .arg(
+                    Arg::new("invert")
+                        .long("invert")
+                        .help("disable power for the duration instead"),
+                ),
+            |matches| {
+                Ok(Cmd::Pulse {
+                    ms: matches.value_of_t("ms")?,
+                    invert: matches.is_present("invert"),
+                })
+            },
+            |port, cmd| {
+                let Cmd::Pulse { ms, invert } = cmd else { unreachable!() };
+                match invert {
+                    false => port.pulse(Duration::from_millis(ms))?,
+                    true => port.inverted_pulse(Duration::from_millis(ms))?,
+                }
+                Ok(None)
+            },
+        ),
+        (
+            Command::new("blink")
+                .about("toggle power on and off n times (Ctrl-C leaves power disabled)")
+                .arg(
+                    Arg::new("count")
+                        .long("count")
+                        .takes_value(true)
+                        .default_value("1")
+                        .validator(is_count),
+                )
+                .arg(
+                    Arg::new("on ms")
+                        .long("on-ms")
+                        .takes_value(true)
+                        .default_value("500")
+                        .validator(is_milliseconds),
+                )
+                .arg(
+                    Arg::new("off ms")
+                        .long("off-ms")
+                        .takes_value(true)
+                        .default_value("500")
+                        .validator(is_milliseconds),
+                ),
+            |matches| {
+                Ok(Cmd::Blink {
+                    count: matches.value_of_t("count")?,
+                    on_ms: matches.value_of_t("on ms")?,
+                    off_ms: matches.value_of_t("off ms")?,
+                })
+            },
+            |port, cmd| {
+                let Cmd::Blink {
+                    count,
+                    on_ms,
+                    off_ms,
+                } = cmd
+                else {
+                    unreachable!()
+                };
+                port.blink(
+                    count,
+                    Duration::from_millis(on_ms),
+                    Duration::from_millis(off_ms),
+                )?;
+                Ok(None)
+            },
+        ),
+        (
+            Command::new("watchdog")
+                .about(
+                    "keep power enabled while running, the relay disconnects itself once stopped",
+                )
+                .arg(
+                    Arg::new("interval")
+                        .long("interval")
+                        .value_name("SECS")
+                        .help("refresh period, must be shorter than the armed timer")
+                        .takes_value(true)
+                        .required(true)
+                        .validator(is_seconds),
+                )
+                .arg(
+                    Arg::new("margin")
+                        .long("margin")
+                        .value_name("SECS")
+                        .help("extra seconds added to the armed timer")
+                        .takes_value(true)
+                        .default_value("5")
+                        .validator(is_seconds),
+                )
+                .arg(events_arg()),
+            |matches| {
+                Ok(Cmd::Watchdog {
+                    interval: matches.value_of_t("interval")?,
+                    margin: matches.value_of_t("margin")?,
+                    events: events(matches),
+                })
+            },
+            |port, cmd| {
+                let Cmd::Watchdog {
+                    interval,
+                    margin,
+                    events,
+                } = cmd
+                else {
+                    unreachable!()
+                };
+                let events = events.as_deref().map(Events::bind).transpose()?;
+                port.watchdog_with(interval, margin, |_| {
+                    if let Some(events) = &events {
+                        events.publish("watchdog", Some(RelayState::On));
+                    }
+                })?;
+                Ok(None)
+            },
+        ),
+        (
+            Command::new("schedule")
+                .about("wait until the given time, then run the action")
+                .arg(
+                    Arg::new("at")
+                        .long("at")
+                        .value_name("time")
+                        .help("RFC3339 timestamp (local time without offset) or +<duration> like +30m")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("action")
+                        .required(true)
+                        .possible_values(["on", "off", "toggle", "jog", "restart"]),
+                ),
+            parse_schedule,
+            |_, _| unreachable!("schedule is resolved before the port is opened"),
+        ),
+        (
+            Command::new("arm-failsafe")
+                .about("enable power, disconnecting after n seconds unless armed again (NO wiring)")
+                .arg(Arg::new("seconds").required(true).validator(is_timer)),
+            |matches| Ok(Cmd::ArmFailsafe(timer(matches)?)),
+            |port, cmd| {
+                let Cmd::ArmFailsafe(secs) = cmd else { unreachable!() };
+                port.arm_failsafe(secs)?;
+                Ok(None)
+            },
+        ),
+        (
+            timed_command!("start"),
+            |matches| Ok(Cmd::TimedOn(timer(matches)?)),
+            |port, cmd| {
+                let Cmd::TimedOn(secs) = cmd else { unreachable!() };
+                port.timed_on(secs)?;
+                Ok(None)
+            },
+        ),
+        (
+            timed_command!("stop"),
+            |matches| Ok(Cmd::TimedOff(timer(matches)?)),
+            |port, cmd| {
+                let Cmd::TimedOff(secs) = cmd else { unreachable!() };
+                port.timed_off(secs)?;
+                Ok(None)
+            },
+        ),
+    ];
+
+    #[cfg(feature = "mqtt")]
+    table.push((
+        Command::new("daemon")
+            .about("control the relay via MQTT messages (on, off, toggle, jog)")
+            .arg(
+                Arg::new("mqtt")
+                    .long("mqtt")
+                    .value_name("broker-url")
+                    .help("MQTT broker, like mqtt://localhost:1883")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("topic")
+                    .long("topic")
+                    .help("topic with the commands")
+                    .takes_value(true)
+                    .default_value("relay/cmd"),
+            )
+            .arg(
+                Arg::new("state topic")
+                    .long("state-topic")
+                    .help("topic the resulting relay state is published to")
+                    .takes_value(true)
+                    .default_value("relay/state"),
+            )
+            .arg(events_arg()),
+        |matches| {
+            Ok(Cmd::Daemon {
+                broker: matches.value_of_t("mqtt")?,
+                topic: matches.value_of_t("topic")?,
+                state_topic: matches.value_of_t("state topic")?,
+                events: events(matches),
+            })
+        },
+        |_, _| unreachable!("daemon manages the port itself"),
+    ));
+
+    #[cfg(feature = "http")]
+    table.push((
+        Command::new("serve")
+            .about("control the relay via HTTP (POST /on, /off, /toggle, /jog, GET /status)")
+            .arg(
+                Arg::new("listen")
+                    .long("listen")
+                    .value_name("address")
+                    .help("address to listen on")
+                    .takes_value(true)
+                    .default_value("127.0.0.1:8080"),
+            )
+            .arg(events_arg()),
+        |matches| {
+            Ok(Cmd::Serve {
+                listen: matches.value_of_t("listen")?,
+                events: events(matches),
+            })
+        },
+        |_, _| unreachable!("serve manages the port itself"),
+    ));
+
+    table
+}
+
+/// `--events-socket` of the long running modes
+fn events_arg() -> Arg<'static> {
+    Arg::new("events socket")
+        .long("events-socket")
+        .value_name("path")
+        .help("publish every state change as a JSON line on this unix socket")
+        .takes_value(true)
+        .allow_invalid_utf8(true)
+}
+
+fn events(matches: &ArgMatches) -> Option<PathBuf> {
+    matches.value_of_os("events socket").map(PathBuf::from)
+}
+
+/// `--channels 1,3,5` of on/off, sent as one bitmask frame
+fn mask_arg() -> Arg<'static> {
+    Arg::new("mask")
+        .long("channels")
+        .value_name("list")
+        .help("switch these channels at once with one bitmask frame (boards with up to 8 relays)")
+        .takes_value(true)
+        .use_value_delimiter(true)
+        .validator(is_channel)
+}
+
+fn switch(matches: &ArgMatches, state: RelayState) -> Option<Cmd> {
+    let channels = matches.values_of("mask")?;
+    Some(Cmd::Switch {
+        channels: channels.map(|channel| channel.parse().unwrap()).collect(),
+        state,
+    })
+}
+
+/// the `seconds` argument of the timed commands
+fn timer(matches: &ArgMatches) -> Result<u16> {
+    let val = matches.value_of("seconds").unwrap_or_default();
+    Ok(parse_timer(val).map_err(RelayError::InvalidArgument)?)
+}
+
+fn parse_show(matches: &ArgMatches) -> Result<Cmd> {
+    let args = matches.values_of("command").unwrap_or_default();
+    let action_matches = build_app().try_get_matches_from(iter::once(APPNAME).chain(args))?;
+    let action = parse(&action_matches)?;
+    match action {
+        Cmd::Show(_)
+        | Cmd::List
+        | Cmd::Capabilities
+        | Cmd::Repl
+        | Cmd::Pipe
+        | Cmd::Run(_)
+        | Cmd::Watchdog { .. }
+        | Cmd::Schedule { .. } => {
+            bail!(RelayError::InvalidArgument(format!(
+                "show does not support {}",
+                action_matches.subcommand_name().unwrap_or_default()
+            )))
+        }
+        #[cfg(feature = "mqtt")]
+        Cmd::Daemon { .. } => bail!(RelayError::InvalidArgument(
+            "show does not support daemon".to_string()
+        )),
+        #[cfg(feature = "http")]
+        Cmd::Serve { .. } => bail!(RelayError::InvalidArgument(
+            "show does not support serve".to_string()
+        )),
+        action => Ok(Cmd::Show(Box::new(action))),
+    }
+}
+
+fn parse_schedule(matches: &ArgMatches) -> Result<Cmd> {
+    let now = Local::now();
+    let at = schedule::parse_time(matches.value_of("at").unwrap(), now)?;
+
+    if at <= now {
+        bail!(RelayError::InvalidArgument(format!(
+            "scheduled time {} is in the past",
+            at.to_rfc3339()
+        )));
+    }
+
+    let action = match matches.value_of("action").unwrap() {
+        "on" => Cmd::On,
+        "off" => Cmd::Off,
+        "toggle" => Cmd::Toggle { report: false },
+        "jog" => Cmd::Jog,
+        "restart" => Cmd::Restart,
+        action => bail!(RelayError::InvalidArgument(format!(
+            "unknown action {}",
+            action
+        ))),
+    };
+
+    Ok(Cmd::Schedule {
+        at,
+        action: Box::new(action),
+    })
+}
+
+/// look the subcommand up in the table and parse it
+pub fn parse(matches: &ArgMatches) -> Result<Cmd> {
+    let (name, sub_matches) = matches
+        .subcommand()
+        .ok_or_else(|| RelayError::InvalidArgument("no subcommand given".to_string()))?;
+
+    match table()
+        .into_iter()
+        .find(|(command, _, _)| command.get_name() == name)
+    {
+        Some((_, parse, _)) => parse(sub_matches),
+        None => bail!(RelayError::InvalidArgument(format!(
+            "unknown command {}",
+            name
+        ))),
+    }
+}
+
+/// look the command up in the table and run its action
+pub fn run(port: &mut Port, cmd: Cmd) -> Result<Option<RelayState>> {
+    let name = cmd.name();
+    match table()
+        .into_iter()
+        .find(|(command, _, _)| command.get_name() == name)
+    {
+        Some((_, _, action)) => action(port, cmd),
+        None => unreachable!("{} has no table entry", name),
+    }
+}
+
+/// print the port with its vid:pid and the identity reported by the board
+fn info(port: &mut Port) -> Result<()> {
+    let identity = port.identify()?;
+    let usb_id = Port::list_devices()
+        .ok()
+        .and_then(|devices| devices.into_iter().find(|d| d.port == port.path()))
+        .map_or("unknown".to_string(), |d| {
+            format!("{:04x}:{:04x}", d.vid, d.pid)
+        });
+
+    println!("port: {}\nvid:pid: {}\n{}", port.path(), usb_id, identity);
+    Ok(())
+}
+
+/// send the frame, printing the answer with `--read`
+fn raw(port: &mut Port, frame: [u8; 4], read: bool) -> Result<()> {
+    port.write_raw(frame)?;
+    if !read {
+        return Ok(());
+    }
+
+    match port.read_raw() {
+        Ok(response) => println!("{}", hex(&response)),
+        Err(RelayError::Timeout(_)) => {
+            log::warn!("{}: no response to {}", port.path(), hex(&frame))
+        }
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+/// ask the user what the load did while the relay is cycled
+fn verify_wiring(port: &mut Port) -> Result<()> {
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        bail!(RelayError::InvalidArgument(
+            "verify-wiring is interactive, run it from a terminal".to_string()
+        ));
+    }
+
+    wiring::run(port, stdin.lock(), io::stderr())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unique_names() {
+        let mut names: Vec<String> = table()
+            .iter()
+            .map(|(command, _, _)| command.get_name().to_string())
+            .collect();
+        let count = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), count);
+    }
+
+    /// the action is found through the name of the parsed command
+    #[test]
+    fn test_names() {
+        for args in [
+            &["on", "--channels", "1,3"][..],
+            &["off"],
+            &["jog", "--hold", "100"],
+            &["timed_start", "5"],
+            &["arm-failsafe", "5"],
+            &["pulse", "--invert", "200"],
+            &["raw", "F0", "A0", "0C", "58"],
+            &["run", "on; sleep 1; off"],
+            &["verify-wiring"],
+        ] {
+            let matches = build_app().get_matches_from(iter::once(APPNAME).chain(args.to_vec()));
+            assert_eq!(
+                parse(&matches).unwrap().name(),
+                matches.subcommand_name().unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let matches = build_app().get_matches_from([APPNAME, "pulse", "--invert", "200"]);
+        assert!(matches!(
+            parse(&matches),
+            Ok(Cmd::Pulse {
+                ms: 200,
+                invert: true
+            })
+        ));
+
+        let matches =
+            build_app().get_matches_from([APPNAME, "--channels", "8", "on", "--channels", "1,3"]);
+        assert!(matches!(
+            parse(&matches),
+            Ok(Cmd::Switch { channels, state: RelayState::On }) if channels == [1, 3]
+        ));
+
+        let matches = build_app().get_matches_from([APPNAME, "jog", "--hold", "1500"]);
+        assert!(matches!(parse(&matches), Ok(Cmd::JogHold(1500))));
+        let result = build_app().try_get_matches_from([APPNAME, "jog", "--hold", "0"]);
+        assert!(result.is_err());
+
+        let matches = build_app().get_matches_from([APPNAME, "probe", "--ack"]);
+        assert!(matches!(parse(&matches), Ok(Cmd::Probe { ack: true })));
+
+        let matches =
+            build_app().get_matches_from([APPNAME, "raw", "F0", "A0", "0C", "58", "--read"]);
+        assert!(matches!(
+            parse(&matches),
+            Ok(Cmd::Raw {
+                frame: [0xF0, 0xA0, 0x0C, 0x58],
+                read: true
+            })
+        ));
+        let matches = build_app().get_matches_from([APPNAME, "raw", "F0A00C"]);
+        assert!(parse(&matches).is_err());
+
+        let matches = build_app().get_matches_from([APPNAME, "set", "off"]);
+        assert!(matches!(parse(&matches), Ok(Cmd::Set(RelayState::Off))));
+
+        let matches = build_app().get_matches_from([APPNAME, "timed_stop", "1h30m"]);
+        assert!(matches!(parse(&matches), Ok(Cmd::TimedOff(5400))));
+
+        let matches = build_app().get_matches_from([APPNAME, "arm-failsafe", "30"]);
+        assert!(matches!(parse(&matches), Ok(Cmd::ArmFailsafe(30))));
+
+        let matches = build_app().get_matches_from([APPNAME, "--dry-run"]);
+        assert!(parse(&matches).is_err());
+    }
+}
@@ -0,0 +1,363 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// targets of `--generate`: the clap_complete shells plus Nushell and Carapace,
+/// which clap_complete doesn't generate for. clap_complete_nushell and
+/// carapace-spec-clap both need clap 4 while this crate is on clap 3, so those
+/// two are emitted by hand from the same `Command` tree
+use clap::{Arg, Command, ValueEnum};
+use clap_complete::generator::utils::possible_values;
+use clap_complete::{Generator, Shell};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Clap(Shell),
+    Nushell,
+    Carapace,
+}
+
+impl Target {
+    /// in the order of `--generate list`
+    pub fn all() -> Vec<Target> {
+        Shell::value_variants()
+            .iter()
+            .map(|&shell| Target::Clap(shell))
+            .chain([Target::Nushell, Target::Carapace])
+            .collect()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::Clap(shell) => shell
+                .to_possible_value()
+                .expect("every shell has a name")
+                .get_name(),
+            Target::Nushell => "nushell",
+            Target::Carapace => "carapace",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Target> {
+        Target::all()
+            .into_iter()
+            .find(|target| target.name() == name)
+    }
+
+    pub fn generate(self, app: &mut Command, bin_name: &str, out: &mut dyn Write) {
+        match self {
+            Target::Clap(shell) => clap_complete::generate(shell, app, bin_name, out),
+            Target::Nushell => clap_complete::generate(Nushell, app, bin_name, out),
+            Target::Carapace => clap_complete::generate(Carapace, app, bin_name, out),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// `export extern` definitions, `use` the file from config.nu
+pub struct Nushell;
+
+impl Generator for Nushell {
+    fn file_name(&self, name: &str) -> String {
+        format!("{}.nu", name)
+    }
+
+    fn generate(&self, cmd: &Command, buf: &mut dyn Write) {
+        let mut script = String::new();
+        nu_extern(&mut script, cmd);
+        buf.write_all(script.as_bytes())
+            .expect("failed to write completion file");
+    }
+}
+
+fn nu_extern(out: &mut String, cmd: &Command) {
+    let path = cmd.get_bin_name().unwrap_or_else(|| cmd.get_name());
+    let args: Vec<&Arg> = cmd
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set())
+        .collect();
+
+    for arg in &args {
+        if let Some(values) = possible_values(arg) {
+            let values: Vec<String> = values
+                .iter()
+                .filter(|value| !value.is_hide_set())
+                .map(|value| format!("{:?}", value.get_name()))
+                .collect();
+            out.push_str(&format!(
+                "def \"nu-complete {} {}\" [] {{\n  [ {} ]\n}}\n\n",
+                path,
+                nu_name(arg),
+                values.join(" ")
+            ));
+        }
+    }
+
+    if let Some(about) = cmd.get_about() {
+        out.push_str(&format!("# {}\n", first_line(about)));
+    }
+    out.push_str(&format!("export extern \"{}\" [\n", path));
+    for arg in &args {
+        let mut param = if arg.is_positional() {
+            let rest = arg.is_multiple_values_set() || arg.is_multiple_occurrences_set();
+            let optional = if arg.is_required_set() { "" } else { "?" };
+            match rest {
+                true => format!("...{}", nu_name(arg)),
+                false => format!("{}{}", nu_name(arg), optional),
+            }
+        } else {
+            match (arg.get_long(), arg.get_short()) {
+                (Some(long), Some(short)) => format!("--{}(-{})", long, short),
+                (Some(long), None) => format!("--{}", long),
+                (None, short) => format!("-{}", short.expect("options have a name")),
+            }
+        };
+        if arg.is_positional() || arg.is_takes_value_set() {
+            param.push_str(": string");
+            if possible_values(arg).is_some() {
+                param.push_str(&format!("@\"nu-complete {} {}\"", path, nu_name(arg)));
+            }
+        }
+        match arg.get_help() {
+            Some(help) => out.push_str(&format!("  {}  # {}\n", param, first_line(help))),
+            None => out.push_str(&format!("  {}\n", param)),
+        }
+    }
+    out.push_str("]\n\n");
+
+    for sub in cmd.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        nu_extern(out, sub);
+    }
+}
+
+/// parameter name, nushell takes identifiers only
+fn nu_name(arg: &Arg) -> String {
+    let name = match (arg.get_long(), arg.get_value_names()) {
+        (Some(long), _) => long,
+        (None, Some([name, ..])) if arg.is_positional() => name,
+        _ => arg.get_id(),
+    };
+    name.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+}
+
+/// spec for carapace-spec, placed in ~/.config/carapace/specs
+pub struct Carapace;
+
+impl Generator for Carapace {
+    fn file_name(&self, name: &str) -> String {
+        format!("{}.yaml", name)
+    }
+
+    fn generate(&self, cmd: &Command, buf: &mut dyn Write) {
+        let spec = crate::yaml::to_string(&carapace_spec(cmd)).expect("spec is plain data");
+        buf.write_all(spec.as_bytes())
+            .expect("failed to write completion file");
+    }
+}
+
+fn carapace_spec(cmd: &Command) -> Value {
+    let mut spec = Map::new();
+    spec.insert("name".into(), cmd.get_name().into());
+    if let Some(about) = cmd.get_about() {
+        spec.insert("description".into(), first_line(about).into());
+    }
+
+    let mut flags = Map::new();
+    let mut flag_values = Map::new();
+    let mut positional = Vec::new();
+    for arg in cmd.get_arguments().filter(|arg| !arg.is_hide_set()) {
+        let values: Option<Value> = possible_values(arg).map(|values| {
+            values
+                .iter()
+                .filter(|value| !value.is_hide_set())
+                .map(|value| Value::from(value.get_name()))
+                .collect()
+        });
+
+        if arg.is_positional() {
+            positional.push(values.unwrap_or_else(|| Value::Array(Vec::new())));
+            continue;
+        }
+
+        let mut names: Vec<String> = arg
+            .get_short()
+            .map(|s| format!("-{}", s))
+            .into_iter()
+            .collect();
+        names.extend(arg.get_long().map(|long| format!("--{}", long)));
+        let mut key = names.join(", ");
+        if arg.is_multiple_occurrences_set() {
+            key.push('*');
+        }
+        if arg.is_takes_value_set() {
+            key.push('=');
+        }
+        flags.insert(
+            key,
+            arg.get_help().map(first_line).unwrap_or_default().into(),
+        );
+
+        if let (Some(values), Some(long)) = (values, arg.get_long()) {
+            flag_values.insert(long.into(), values);
+        }
+    }
+
+    if !flags.is_empty() {
+        spec.insert("flags".into(), flags.into());
+    }
+    let mut completion = Map::new();
+    if !flag_values.is_empty() {
+        completion.insert("flag".into(), flag_values.into());
+    }
+    // trailing positionals without values need no entry
+    while positional
+        .last()
+        .is_some_and(|values| values.as_array().is_some_and(Vec::is_empty))
+    {
+        positional.pop();
+    }
+    if !positional.is_empty() {
+        completion.insert("positional".into(), positional.into());
+    }
+    if !completion.is_empty() {
+        spec.insert("completion".into(), completion.into());
+    }
+
+    let commands: Vec<Value> = cmd
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set())
+        .map(carapace_spec)
+        .collect();
+    if !commands.is_empty() {
+        spec.insert("commands".into(), commands.into());
+    }
+    spec.into()
+}
+
+fn first_line(help: &str) -> &str {
+    help.lines().next().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app() -> Command<'static> {
+        Command::new("relay")
+            .about("switch the relay")
+            .arg(
+                Arg::new("format")
+                    .long("format")
+                    .short('f')
+                    .help("output format")
+                    .possible_values(["human", "json"]),
+            )
+            .arg(Arg::new("quiet").short('q').help("only errors"))
+            .subcommand(
+                Command::new("set").about("switch to a state").arg(
+                    Arg::new("state")
+                        .required(true)
+                        .possible_values(["on", "off"]),
+                ),
+            )
+    }
+
+    fn generated(target: Target) -> String {
+        let mut out = Vec::new();
+        target.generate(&mut app(), "relay", &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_targets() {
+        let names: Vec<&str> = Target::all().into_iter().map(Target::name).collect();
+        assert_eq!(
+            names,
+            [
+                "bash",
+                "elvish",
+                "fish",
+                "powershell",
+                "zsh",
+                "nushell",
+                "carapace"
+            ]
+        );
+        assert_eq!(Target::parse("zsh"), Some(Target::Clap(Shell::Zsh)));
+        assert_eq!(Target::parse("nushell"), Some(Target::Nushell));
+        assert_eq!(Target::parse("tcsh"), None);
+    }
+
+    #[test]
+    fn test_nushell() {
+        let script = generated(Target::Nushell);
+        assert!(script.starts_with(
+            "def \"nu-complete relay format\" [] {\n  [ \"human\" \"json\" ]\n}\n\n\
+             # switch the relay\nexport extern \"relay\" [\n"
+        ));
+        assert!(script.contains(
+            "  --format(-f): string@\"nu-complete relay format\"  # output format\n  -q  # only errors\n]\n"
+        ));
+        assert!(script.contains(
+            "export extern \"relay set\" [\n  state: string@\"nu-complete relay set state\"\n"
+        ));
+    }
+
+    /// parses the script with `nu` when it is installed, skips otherwise
+    #[test]
+    fn test_nushell_parses() {
+        let path = std::env::temp_dir().join(format!("relay-{}.nu", std::process::id()));
+        std::fs::write(&path, generated(Target::Nushell)).unwrap();
+        let output = std::process::Command::new("nu")
+            .arg("--commands")
+            .arg(format!("source '{}'", path.display()))
+            .output();
+        std::fs::remove_file(&path).unwrap();
+
+        match output {
+            Ok(output) => assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                eprintln!("nu not installed, skipping")
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn test_carapace() {
+        let spec = generated(Target::Carapace);
+        assert!(spec.starts_with("name: relay\ndescription: \"switch the relay\"\nflags:\n"));
+        assert!(
+            spec.contains("  \"-f, --format=\": \"output format\"\n  \"-q\": \"only errors\"\n")
+        );
+        assert!(spec.contains("completion:\n  flag:\n    format:\n      - human\n      - json\n"));
+        assert!(spec.contains("commands:\n  - name: set\n    description: \"switch to a state\"\n"));
+        assert!(spec.contains(
+            "    completion:\n      positional:\n        - - \"on\"\n          - \"off\"\n"
+        ));
+    }
+}
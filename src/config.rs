@@ -0,0 +1,289 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// defaults loaded from the config file
+use anyhow::{bail, Context, Result};
+use log::debug;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tty_relay::{RelayError, MAX_CHANNELS};
+
+/// config file contents, command line flags take precedence
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub tty: Option<String>,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub baud: Option<u32>,
+    pub normally_closed: Option<bool>,
+    pub confirm: Option<Confirm>,
+    pub state_file: Option<PathBuf>,
+    /// board profile used without --profile
+    pub profile: Option<String>,
+    /// user profiles, they take precedence over the built-in ones of the same name
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// settings of a board model, selected with --profile
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub baud: Option<u32>,
+    pub protocol: Option<Revision>,
+    pub prefix: Option<u8>,
+    pub channels: Option<u8>,
+    pub normally_closed: Option<bool>,
+    /// serial read timeout in milliseconds, like --timeout
+    pub timeout: Option<u64>,
+    /// delay after every frame in milliseconds, like --frame-delay
+    pub frame_delay: Option<u64>,
+}
+
+/// firmware protocol revision, see --protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Revision {
+    V1,
+    V2,
+}
+
+/// known-good presets, see the Board profiles section of the README
+pub fn builtin_profiles() -> Vec<(&'static str, Profile)> {
+    let lctech = |channels| Profile {
+        vid: Some(0x1a86),
+        pid: Some(0x7523),
+        baud: Some(9600),
+        protocol: Some(Revision::V1),
+        prefix: Some(0xF0),
+        channels: Some(channels),
+        ..Profile::default()
+    };
+
+    vec![
+        ("lctech-1ch", lctech(1)),
+        ("lctech-2ch", lctech(2)),
+        ("lctech-4ch", lctech(4)),
+        // a single relay board behind an HC-05 bluetooth serial module
+        (
+            "hc-05",
+            Profile {
+                baud: Some(9600),
+                protocol: Some(Revision::V1),
+                prefix: Some(0xF0),
+                channels: Some(1),
+                timeout: Some(100),
+                frame_delay: Some(150),
+                ..Profile::default()
+            },
+        ),
+    ]
+}
+
+/// when to ask before disconnecting power
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Confirm {
+    #[default]
+    Never,
+    /// only when stdin is a terminal
+    Auto,
+    Always,
+}
+
+impl FromStr for Confirm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(Confirm::Never),
+            "auto" => Ok(Confirm::Auto),
+            "always" => Ok(Confirm::Always),
+            _ => Err(format!("unknown confirm mode {}", s)),
+        }
+    }
+}
+
+impl Config {
+    fn default_path() -> Option<PathBuf> {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+        Some(config_home.join(crate::APPNAME).join("config.toml"))
+    }
+
+    /// the profile named by --profile or the config file, empty if none is selected
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile> {
+        let name = match name.or(self.profile.as_deref()) {
+            Some(name) => name,
+            None => return Ok(Profile::default()),
+        };
+
+        if let Some(profile) = self.profiles.get(name) {
+            if profile.channels.is_some_and(|n| n == 0 || n > MAX_CHANNELS) {
+                bail!(RelayError::InvalidArgument(format!(
+                    "profile {}: channels must be between 1 and {}",
+                    name, MAX_CHANNELS
+                )));
+            }
+            return Ok(profile.clone());
+        }
+
+        let builtin = builtin_profiles();
+        match builtin.iter().find(|(builtin, _)| *builtin == name) {
+            Some((_, profile)) => Ok(profile.clone()),
+            None => {
+                let mut known: Vec<&str> = builtin.iter().map(|(name, _)| *name).collect();
+                for name in self.profiles.keys() {
+                    if !known.contains(&name.as_str()) {
+                        known.push(name);
+                    }
+                }
+                bail!(RelayError::InvalidArgument(format!(
+                    "unknown profile {}, known profiles: {}",
+                    name,
+                    known.join(", ")
+                )))
+            }
+        }
+    }
+
+    fn parse(content: &str) -> Result<Config> {
+        Ok(toml::from_str(content)?)
+    }
+
+    /// load the config from `path`, or from the default location if it exists
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Self::default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+
+        debug!("load config from {}", path.display());
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+
+        Self::parse(&content).with_context(|| format!("malformed config {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            r#"
+            tty = "/dev/ttyUSB0"
+            vid = 0x1a86
+            pid = 0x7523
+            baud = 115200
+            normally_closed = true
+            confirm = "auto"
+            state_file = "/var/lib/tty_relay/state"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                tty: Some("/dev/ttyUSB0".to_string()),
+                vid: Some(0x1a86),
+                pid: Some(0x7523),
+                baud: Some(115200),
+                normally_closed: Some(true),
+                confirm: Some(Confirm::Auto),
+                state_file: Some(PathBuf::from("/var/lib/tty_relay/state")),
+                ..Config::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_profiles() {
+        let config = Config::parse(
+            r#"
+            profile = "bench"
+
+            [profiles.bench]
+            baud = 115200
+            protocol = "v2"
+            normally_closed = true
+            frame_delay = 80
+
+            [profiles.lctech-1ch]
+            channels = 1
+            "#,
+        )
+        .unwrap();
+
+        let bench = config.profile(None).unwrap();
+        assert_eq!(bench.baud, Some(115200));
+        assert_eq!(bench.protocol, Some(Revision::V2));
+        assert_eq!(bench.normally_closed, Some(true));
+        assert_eq!(bench.frame_delay, Some(80));
+
+        let lctech = config.profile(Some("lctech-4ch")).unwrap();
+        assert_eq!(lctech.channels, Some(4));
+        assert_eq!(lctech.vid, Some(0x1a86));
+
+        // user profiles shadow the built-in ones
+        assert_eq!(config.profile(Some("lctech-1ch")).unwrap().vid, None);
+
+        let err = config.profile(Some("nope")).unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("lctech-1ch, lctech-2ch, lctech-4ch, hc-05, bench"));
+        assert_eq!(Config::default().profile(None).unwrap(), Profile::default());
+        assert!(Config::parse("[profiles.x]\nspeed = 1").is_err());
+
+        let config = Config::parse("[profiles.wide]\nchannels = 200").unwrap();
+        assert!(config.profile(Some("wide")).is_err());
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        assert!(Config::parse("baud = \"fast\"").is_err());
+        assert!(Config::parse("unknown = 1").is_err());
+        assert!(Config::parse("tty = ").is_err());
+        assert!(Config::parse("confirm = \"sometimes\"").is_err());
+    }
+
+    #[test]
+    fn test_load_missing() {
+        assert!(Config::load(Some(Path::new("/NOT_FOUND/config.toml"))).is_err());
+    }
+}
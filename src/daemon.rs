@@ -0,0 +1,273 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// helpers shared by the long running modes
+use crate::events::Events;
+use anyhow::{bail, Result};
+use log::{debug, trace, warn};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tty_relay::{Port, RelayState};
+
+/// long-lived port which is reopened after a failed command
+pub struct LazyPort<F> {
+    port: Option<Port>,
+    open: F,
+    keepalive: Option<Duration>,
+    last_activity: Instant,
+}
+
+impl<F: Fn() -> Result<Port>> LazyPort<F> {
+    pub fn new(open: F) -> Self {
+        LazyPort {
+            port: None,
+            open,
+            keepalive: None,
+            last_activity: Instant::now(),
+        }
+    }
+
+    /// send a keepalive frame once the open port was idle for the interval
+    pub fn keepalive(mut self, interval: Option<Duration>) -> Self {
+        self.keepalive = interval;
+        self
+    }
+
+    /// run `f` on the port, opening it first if needed
+    pub fn run<T>(&mut self, f: impl FnOnce(&mut Port) -> Result<T>) -> Result<T> {
+        self.last_activity = Instant::now();
+
+        if self.port.is_none() {
+            debug!("open the port");
+            self.port = Some((self.open)()?);
+        }
+
+        let result = f(self.port.as_mut().unwrap());
+
+        if result.is_err() {
+            debug!("command failed, the port will be reopened");
+            self.port = None;
+        }
+
+        result
+    }
+
+    /// time until the next keepalive is due, `None` without keepalive
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.keepalive
+            .map(|interval| interval.saturating_sub(self.last_activity.elapsed()))
+    }
+
+    /// send the keepalive if it is due, a closed port is not reopened for it
+    pub fn tick(&mut self) {
+        if self.idle_timeout() != Some(Duration::ZERO) {
+            return;
+        }
+        if self.port.is_none() {
+            self.last_activity = Instant::now();
+            return;
+        }
+
+        trace!(
+            "port idle for {:?}, send keepalive",
+            self.keepalive.unwrap()
+        );
+        if let Err(e) = self.run(|port| Ok(port.keepalive()?)) {
+            warn!("keepalive failed: {:#}", e);
+        }
+    }
+}
+
+/// [`LazyPort`] behind a mutex, cloned into every thread which needs the device
+///
+/// the lock serializes the commands, concurrent requests queue up instead of
+/// opening the port again
+pub struct SharedPort<F> {
+    inner: Arc<Mutex<LazyPort<F>>>,
+    events: Option<Arc<Events>>,
+}
+
+impl<F> Clone for SharedPort<F> {
+    fn clone(&self) -> Self {
+        SharedPort {
+            inner: Arc::clone(&self.inner),
+            events: self.events.clone(),
+        }
+    }
+}
+
+impl<F: Fn() -> Result<Port>> SharedPort<F> {
+    pub fn new(port: LazyPort<F>) -> Self {
+        SharedPort {
+            inner: Arc::new(Mutex::new(port)),
+            events: None,
+        }
+    }
+
+    /// publish the state after every successful command
+    pub fn events(mut self, events: Events) -> Self {
+        self.events = Some(Arc::new(events));
+        self
+    }
+
+    /// a command which panicked is not a reason to stop serving the others
+    fn lock(&self) -> MutexGuard<'_, LazyPort<F>> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// run `f` on the port while holding the lock
+    pub fn run<T>(&self, f: impl FnOnce(&mut Port) -> Result<T>) -> Result<T> {
+        self.lock().run(f)
+    }
+
+    /// lock, run the remote command and release the port
+    ///
+    /// unknown commands are rejected before the port is touched, so a bad
+    /// message can't get the port reopened; the event is published before the
+    /// lock is released, so subscribers see the events in the order the
+    /// commands reached the relay
+    pub fn command(&self, command: &str) -> Result<Option<RelayState>> {
+        if !COMMANDS.contains(&command) {
+            bail!("unknown command {:?}", command);
+        }
+
+        self.run(|port| {
+            let state = execute(port, command)?;
+            if let Some(events) = &self.events {
+                events.publish(command, state);
+            }
+            Ok(state)
+        })
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.lock().idle_timeout()
+    }
+
+    pub fn tick(&self) {
+        self.lock().tick()
+    }
+}
+
+/// commands accepted from remote clients
+pub const COMMANDS: [&str; 4] = ["on", "off", "toggle", "jog"];
+
+/// run a remote command, returns the resulting relay state if known
+pub fn execute(port: &mut Port, command: &str) -> Result<Option<RelayState>> {
+    let state = match command {
+        "on" => port.on().map(|_| Some(RelayState::On)),
+        "off" => port.off().map(|_| Some(RelayState::Off)),
+        "toggle" => port.toggle().map(|_| port.status().ok()),
+        "jog" => port.jog().map(|_| port.status().ok()),
+        _ => bail!("unknown command {:?}", command),
+    };
+    Ok(state?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tty_relay::{OpenOptions, Recorder};
+
+    #[test]
+    fn test_execute() {
+        let mut port = OpenOptions::new().dry_run(true).open().unwrap();
+
+        assert_eq!(execute(&mut port, "on").unwrap(), Some(RelayState::On));
+        assert_eq!(execute(&mut port, "off").unwrap(), Some(RelayState::Off));
+        assert!(execute(&mut port, "explode").is_err());
+    }
+
+    #[test]
+    fn test_lazy_port() {
+        let mut port = LazyPort::new(|| Ok(OpenOptions::new().dry_run(true).open()?));
+
+        assert!(port.run(|port| Ok(port.on()?)).is_ok());
+        assert!(port.run(|port| Ok(port.status()?)).is_err());
+        assert!(port.port.is_none());
+        assert!(port.run(|port| Ok(port.off()?)).is_ok());
+
+        let mut port = LazyPort::new(|| Ok(OpenOptions::new().tty("/dev/NOT_FOUND").open()?));
+
+        assert!(port.run(|port| Ok(port.on()?)).is_err());
+    }
+
+    #[test]
+    fn test_shared_port() {
+        let recorder = Recorder::new();
+        let opened = Mutex::new(0);
+        let port = SharedPort::new(LazyPort::new(|| {
+            *opened.lock().unwrap() += 1;
+            Ok(Port::recording(&recorder))
+        }));
+
+        std::thread::scope(|scope| {
+            for command in ["on", "off", "on", "off"] {
+                let port = port.clone();
+                scope.spawn(move || port.command(command).unwrap());
+            }
+        });
+
+        // opened once, every command got its frames out without interleaving
+        assert_eq!(*opened.lock().unwrap(), 1);
+        let frames = recorder.frames();
+        assert_eq!(frames.len(), 8);
+        for pair in frames.chunks(2) {
+            assert_eq!(pair[0], [0xF0, 0xA0, 0x0C, 0x54]);
+            assert_eq!(&pair[1][..2], &[0xF0, 0xA0]);
+        }
+
+        assert!(port.command("explode").is_err());
+        assert_eq!(port.command("on").unwrap(), Some(RelayState::On));
+        assert_eq!(
+            *opened.lock().unwrap(),
+            1,
+            "an unknown command does not touch the port"
+        );
+
+        // the recorder never answers the status query
+        assert!(port.run(|port| Ok(port.status()?)).is_err());
+        assert_eq!(
+            port.command("on").unwrap(),
+            Some(RelayState::On),
+            "the port is reopened after a failure"
+        );
+        assert_eq!(*opened.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_keepalive() {
+        let recorder = Recorder::new();
+        let open = || Ok(Port::recording(&recorder));
+
+        let mut port = LazyPort::new(open).keepalive(Some(Duration::ZERO));
+        // nothing to keep warm before the port is opened
+        port.tick();
+        assert!(recorder.frames().is_empty());
+
+        port.run(|port| Ok(port.on()?)).unwrap();
+        port.tick();
+        assert_eq!(recorder.frames().last(), Some(&[0xF0, 0xA0, 0x0C, 0x54]));
+        assert_eq!(recorder.frames().len(), 3);
+
+        let mut port = LazyPort::new(open).keepalive(Some(Duration::from_secs(3600)));
+        port.run(|port| Ok(port.on()?)).unwrap();
+        port.tick();
+        assert_eq!(recorder.frames().len(), 5);
+        assert!(port.idle_timeout().unwrap() > Duration::from_secs(3500));
+        assert!(LazyPort::new(open).idle_timeout().is_none());
+    }
+}
@@ -0,0 +1,216 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use anyhow::{Context, Result};
+use serialport::SerialPortType::UsbPort;
+use std::fs;
+use std::path::Path;
+
+/// USB serial device found on the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// tty port path
+    pub port: String,
+    /// USB vendor id
+    pub vid: u16,
+    /// USB product id
+    pub pid: u16,
+    /// USB serial number
+    pub serial: Option<String>,
+    /// USB manufacturer string
+    pub manufacturer: Option<String>,
+    /// USB product string
+    pub product: Option<String>,
+}
+
+impl DeviceInfo {
+    /// default CH340 vendor id
+    pub(crate) const VID: u16 = 0x1a86;
+    /// default CH340 product id
+    pub(crate) const PID: u16 = 0x7523;
+
+    /// whether the device has the default relay board vid:pid
+    pub fn is_compatible(&self) -> bool {
+        self.vid == Self::VID && self.pid == Self::PID
+    }
+
+    /// whether the device has the given ids, a missing one matches any
+    pub fn has_ids(&self, vid: Option<u16>, pid: Option<u16>) -> bool {
+        vid.is_none_or(|vid| self.vid == vid) && pid.is_none_or(|pid| self.pid == pid)
+    }
+
+    /// port name for humans, `\\.\COM10` is shown as `COM10`
+    pub fn display_name(&self) -> &str {
+        self.port
+            .strip_prefix(DEVICE_NAMESPACE)
+            .unwrap_or(&self.port)
+    }
+}
+
+/// windows device namespace prefix, needed to open COM10 and above
+const DEVICE_NAMESPACE: &str = "\\\\.\\";
+
+/// whether the name is a windows COM port (`COM3`, `\\.\COM10`), these are not filesystem paths
+pub fn is_com_port(name: &str) -> bool {
+    let name = name.strip_prefix(DEVICE_NAMESPACE).unwrap_or(name);
+    match name.get(..3) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("com") => {
+            name.len() > 3 && name[3..].bytes().all(|b| b.is_ascii_digit())
+        }
+        _ => false,
+    }
+}
+
+/// enumerate the USB serial devices
+pub(crate) fn available_devices() -> Result<Vec<DeviceInfo>> {
+    let ports = serialport::available_ports().context("failed to enumerate serial ports")?;
+
+    let devices = ports
+        .into_iter()
+        .filter_map(|port| match port.port_type {
+            UsbPort(usb_port) => Some(DeviceInfo {
+                port: port.port_name,
+                vid: usb_port.vid,
+                pid: usb_port.pid,
+                serial: usb_port.serial_number,
+                manufacturer: usb_port.manufacturer,
+                product: usb_port.product,
+            }),
+            _ => None,
+        })
+        .collect();
+
+    Ok(devices)
+}
+
+/// linux sysfs class directory, each tty links to its device
+#[cfg_attr(test, allow(dead_code))]
+pub(crate) const SYS_CLASS_TTY: &str = "/sys/class/tty";
+
+/// USB ids of the tty at `port` read from sysfs under `class_dir`, this
+/// looks at one device without enumerating them all
+///
+/// `None` for non USB ttys and where there is no sysfs
+pub(crate) fn sysfs_device(class_dir: &Path, port: &str) -> Option<DeviceInfo> {
+    let real = fs::canonicalize(port).ok()?;
+    let mut dir = fs::canonicalize(class_dir.join(real.file_name()?).join("device")).ok()?;
+
+    // the tty sits on an interface of the USB device, which has the ids
+    loop {
+        let read = |name: &str| {
+            fs::read_to_string(dir.join(name))
+                .ok()
+                .map(|value| value.trim().to_string())
+        };
+        let id = |name: &str| read(name).and_then(|id| u16::from_str_radix(&id, 16).ok());
+
+        if let (Some(vid), Some(pid)) = (id("idVendor"), id("idProduct")) {
+            return Some(DeviceInfo {
+                port: port.to_string(),
+                vid,
+                pid,
+                serial: read("serial"),
+                manufacturer: read("manufacturer"),
+                product: read("product"),
+            });
+        }
+        if !dir.pop() {
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(vid: u16, pid: u16) -> DeviceInfo {
+        DeviceInfo {
+            port: "stub".to_string(),
+            vid,
+            pid,
+            serial: None,
+            manufacturer: None,
+            product: None,
+        }
+    }
+
+    #[test]
+    fn test_is_compatible() {
+        assert!(device(0x1a86, 0x7523).is_compatible());
+        assert!(!device(0x1a86, 0x5523).is_compatible());
+        assert!(!device(0x0403, 0x7523).is_compatible());
+    }
+
+    #[test]
+    fn test_has_ids() {
+        let ftdi = device(0x0403, 0x6001);
+        assert!(ftdi.has_ids(Some(0x0403), Some(0x6001)));
+        assert!(ftdi.has_ids(None, Some(0x6001)));
+        assert!(ftdi.has_ids(None, None));
+        assert!(!ftdi.has_ids(Some(0x0403), Some(0x6015)));
+    }
+
+    #[test]
+    fn test_com_port() {
+        assert!(is_com_port("COM3"));
+        assert!(is_com_port("com12"));
+        assert!(is_com_port(r"\\.\COM10"));
+        assert!(!is_com_port("COM"));
+        assert!(!is_com_port("COMX"));
+        assert!(!is_com_port("/dev/ttyUSB0"));
+        assert!(!is_com_port("ttyUSB0"));
+
+        let mut com = device(0x1a86, 0x7523);
+        com.port = r"\\.\COM10".to_string();
+        assert_eq!(com.display_name(), "COM10");
+        com.port = "/dev/ttyUSB0".to_string();
+        assert_eq!(com.display_name(), "/dev/ttyUSB0");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sysfs_device() {
+        let root = std::env::temp_dir().join(format!("tty_relay_sysfs_{}", std::process::id()));
+        let usb = root.join("devices/1-1");
+        let class = root.join("class/tty/ttyUSB0");
+        fs::create_dir_all(usb.join("1-1:1.0/ttyUSB0")).unwrap();
+        fs::create_dir_all(&class).unwrap();
+        fs::write(usb.join("idVendor"), "1a86\n").unwrap();
+        fs::write(usb.join("idProduct"), "7523\n").unwrap();
+        fs::write(usb.join("serial"), "A5\n").unwrap();
+        std::os::unix::fs::symlink(usb.join("1-1:1.0"), class.join("device")).unwrap();
+
+        let tty = root.join("ttyUSB0");
+        fs::write(&tty, "").unwrap();
+        let tty = tty.display().to_string();
+
+        let found = sysfs_device(&root.join("class/tty"), &tty).unwrap();
+        assert_eq!(found.port, tty);
+        assert!(found.is_compatible());
+        assert_eq!(found.serial.as_deref(), Some("A5"));
+        assert_eq!(found.manufacturer, None);
+
+        let other = root.join("ttyS0");
+        fs::write(&other, "").unwrap();
+        assert_eq!(
+            sysfs_device(&root.join("class/tty"), &other.display().to_string()),
+            None
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use thiserror::Error;
+
+/// error of the [`Port`](crate::Port) and [`OpenOptions`](crate::OpenOptions)
+/// methods, match the variant to tell the failures apart
+///
+/// the message carries the whole context, like `{:#}` of an `anyhow::Error`;
+/// transport errors of the tty or socket are [`RelayError::Io`]
+#[derive(Debug, Error)]
+pub enum RelayError {
+    /// no matching tty device was found
+    #[error("{0}")]
+    DeviceNotFound(String),
+    /// several devices match, the caller has to pick one
+    #[error("{0}")]
+    AmbiguousDevice(String),
+    /// the tty device could not be opened
+    #[error("{0}")]
+    OpenFailed(String),
+    /// communication with the relay failed
+    #[error("{0}")]
+    Io(String),
+    /// the relay answered a frame with something else than its echo, see
+    /// [`Port::set_verify`](crate::Port::set_verify); no answer at all is a timeout
+    #[error("{0}")]
+    NoAcknowledgement(String),
+    /// the relay did not answer within the read timeout
+    #[error("{0}")]
+    Timeout(String),
+    /// another process holds the lock of the device, see
+    /// [`OpenOptions::lock_wait`](crate::OpenOptions::lock_wait)
+    #[error("{0}")]
+    Busy(String),
+    /// invalid argument passed to the relay
+    #[error("{0}")]
+    InvalidArgument(String),
+    /// the command was interrupted (Ctrl-C) before all frames were sent
+    #[error("{0}")]
+    Interrupted(String),
+}
+
+impl RelayError {
+    /// the failure class of an error of this crate passed on as `anyhow::Error`
+    ///
+    /// ```
+    /// use tty_relay::{Port, RelayError};
+    ///
+    /// let mut port = Port::open_mock();
+    /// let err = anyhow::Error::from(port.set_channel(9).unwrap_err()).context("setup");
+    /// assert!(matches!(RelayError::find(&err), Some(RelayError::InvalidArgument(_))));
+    /// ```
+    pub fn find(error: &anyhow::Error) -> Option<&RelayError> {
+        error.chain().find_map(|cause| cause.downcast_ref())
+    }
+
+    /// the same failure class with another message
+    fn with_message(&self, message: String) -> RelayError {
+        match self {
+            RelayError::DeviceNotFound(_) => RelayError::DeviceNotFound(message),
+            RelayError::AmbiguousDevice(_) => RelayError::AmbiguousDevice(message),
+            RelayError::OpenFailed(_) => RelayError::OpenFailed(message),
+            RelayError::Io(_) => RelayError::Io(message),
+            RelayError::NoAcknowledgement(_) => RelayError::NoAcknowledgement(message),
+            RelayError::Timeout(_) => RelayError::Timeout(message),
+            RelayError::Busy(_) => RelayError::Busy(message),
+            RelayError::InvalidArgument(_) => RelayError::InvalidArgument(message),
+            RelayError::Interrupted(_) => RelayError::Interrupted(message),
+        }
+    }
+}
+
+/// the failure class found in the chain with all the context as message,
+/// errors without one failed on the transport
+impl From<anyhow::Error> for RelayError {
+    fn from(error: anyhow::Error) -> Self {
+        let message = format!("{:#}", error);
+        match RelayError::find(&error) {
+            Some(relay) => relay.with_message(message),
+            None => RelayError::Io(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_find() {
+        let err = Err::<(), _>(RelayError::Timeout("no response".to_string()))
+            .context("status")
+            .unwrap_err();
+        assert!(matches!(
+            RelayError::find(&err),
+            Some(RelayError::Timeout(_))
+        ));
+
+        let err = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::BrokenPipe));
+        assert!(RelayError::find(&err).is_none());
+    }
+
+    #[test]
+    fn test_from_anyhow() {
+        let err = Err::<(), _>(RelayError::Timeout("no response".to_string()))
+            .context("status")
+            .unwrap_err();
+        let err = RelayError::from(err);
+        assert!(matches!(err, RelayError::Timeout(_)));
+        assert_eq!(err.to_string(), "status: no response");
+
+        let err = Err::<(), _>(std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+            .context("failed to write /dev/ttyUSB0")
+            .unwrap_err();
+        let err = RelayError::from(err);
+        assert!(matches!(err, RelayError::Io(_)));
+        assert!(err
+            .to_string()
+            .starts_with("failed to write /dev/ttyUSB0: "));
+    }
+}
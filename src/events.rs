@@ -0,0 +1,222 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// relay state changes broadcast on a unix socket, see `--events-socket`
+use anyhow::Result;
+use chrono::{Local, SecondsFormat};
+use serde::Serialize;
+use std::path::Path;
+use tty_relay::RelayState;
+
+#[cfg(unix)]
+use {
+    anyhow::{bail, Context},
+    log::{debug, warn},
+    std::fs,
+    std::io::Write,
+    std::os::unix::fs::FileTypeExt,
+    std::os::unix::net::{UnixListener, UnixStream},
+    std::path::PathBuf,
+    std::sync::{Arc, Mutex},
+    std::thread,
+    std::time::Duration,
+};
+
+/// one line of newline delimited JSON per successful command
+#[derive(Serialize)]
+struct Event<'a> {
+    command: &'a str,
+    state: Option<String>,
+    ts: String,
+}
+
+impl Event<'_> {
+    fn line(command: &str, state: Option<RelayState>) -> Result<String> {
+        let event = Event {
+            command,
+            state: state.map(|state| state.to_string()),
+            ts: Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+        };
+        Ok(serde_json::to_string(&event)? + "\n")
+    }
+}
+
+/// the listening socket and everyone connected to it
+///
+/// subscribers which stop reading are dropped, the socket file is removed again
+/// when the events are dropped
+pub struct Events {
+    #[cfg(unix)]
+    path: PathBuf,
+    #[cfg(unix)]
+    subscribers: Arc<Mutex<Vec<UnixStream>>>,
+}
+
+#[cfg(unix)]
+impl Events {
+    /// a subscriber which does not read for that long is dropped
+    const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// listen on `path`, a stale socket left by a killed process is replaced
+    ///
+    /// anything else found at `path` is left alone and fails the bind
+    pub fn bind(path: &Path) -> Result<Events> {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                bail!(tty_relay::RelayError::InvalidArgument(format!(
+                    "{} exists and is not a socket",
+                    path.display()
+                )));
+            }
+            if UnixStream::connect(path).is_ok() {
+                bail!(tty_relay::RelayError::InvalidArgument(format!(
+                    "events socket {} is in use by another process",
+                    path.display()
+                )));
+            }
+            fs::remove_file(path)
+                .with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+        }
+
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("failed to listen on {}", path.display()))?;
+        let subscribers = Arc::new(Mutex::new(Vec::new()));
+
+        let accepted = Arc::clone(&subscribers);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream.and_then(|stream| {
+                    stream.set_write_timeout(Some(Self::WRITE_TIMEOUT))?;
+                    Ok(stream)
+                }) {
+                    Ok(stream) => {
+                        debug!("events subscriber connected");
+                        accepted.lock().unwrap().push(stream);
+                    }
+                    Err(e) => warn!("failed to accept events subscriber: {}", e),
+                }
+            }
+        });
+
+        Ok(Events {
+            path: path.to_path_buf(),
+            subscribers,
+        })
+    }
+
+    /// send the event to every subscriber, dropping the ones which went away
+    pub fn publish(&self, command: &str, state: Option<RelayState>) {
+        let line = match Event::line(command, state) {
+            Ok(line) => line,
+            Err(e) => return warn!("failed to serialize event: {:#}", e),
+        };
+
+        self.subscribers.lock().unwrap().retain_mut(|stream| {
+            let sent = stream.write_all(line.as_bytes()).is_ok();
+            if !sent {
+                debug!("events subscriber disconnected");
+            }
+            sent
+        });
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Events {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(not(unix))]
+impl Events {
+    pub fn bind(path: &Path) -> Result<Events> {
+        anyhow::bail!(tty_relay::RelayError::InvalidArgument(format!(
+            "--events-socket {} needs unix domain sockets",
+            path.display()
+        )))
+    }
+
+    pub fn publish(&self, _command: &str, _state: Option<RelayState>) {}
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::time::Instant;
+
+    /// wait for the accept thread to register the subscribers
+    fn wait_for_subscribers(events: &Events, count: usize) {
+        let start = Instant::now();
+        while events.subscribers.lock().unwrap().len() != count {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        let path =
+            std::env::temp_dir().join(format!("tty_relay-events-{}.sock", std::process::id()));
+        let events = Events::bind(&path).unwrap();
+
+        let first = UnixStream::connect(&path).unwrap();
+        let second = UnixStream::connect(&path).unwrap();
+        wait_for_subscribers(&events, 2);
+
+        events.publish("on", Some(RelayState::On));
+        for stream in [&first, &second] {
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            let event: serde_json::Value = serde_json::from_str(&line).unwrap();
+            assert_eq!(event["command"], "on");
+            assert_eq!(event["state"], "on");
+            assert!(event["ts"].is_string());
+        }
+
+        // a closed subscriber is dropped, the other one keeps receiving
+        drop(second);
+        events.publish("off", Some(RelayState::Off));
+        events.publish("toggle", None);
+        wait_for_subscribers(&events, 1);
+
+        let mut reader = BufReader::new(&first);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.contains("\"state\":\"off\""));
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.contains("\"state\":null"));
+
+        assert!(Events::bind(&path).is_err(), "the socket is in use");
+        drop(events);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_bind_not_socket() {
+        let path =
+            std::env::temp_dir().join(format!("tty_relay-events-{}.txt", std::process::id()));
+        fs::write(&path, "keep me").unwrap();
+
+        let err = Events::bind(&path).err().unwrap();
+        assert!(err.to_string().ends_with("is not a socket"), "{}", err);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "keep me");
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,160 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// frame construction shared by the blocking and async ports
+use crate::error::RelayError;
+use crate::port::{Identity, RelayState, Wiring};
+use crate::protocol::Protocol;
+
+/// a single command frame
+pub(crate) type Frame = [u8; 4];
+
+#[derive(Clone, Copy)]
+pub(crate) enum Action {
+    Connect,
+    Disconnect,
+}
+
+impl Action {
+    fn enable_byte(self, wiring: Wiring) -> u8 {
+        match (wiring, self) {
+            (Wiring::NormallyOpen, Action::Connect) => 0x01,
+            (Wiring::NormallyOpen, Action::Disconnect) => 0x00,
+            (Wiring::NormallyClosed, Action::Connect) => 0x00,
+            (Wiring::NormallyClosed, Action::Disconnect) => 0x01,
+        }
+    }
+}
+
+/// builds the frames for one relay, before sealing by the protocol
+pub(crate) struct Framer<'a> {
+    pub protocol: &'a Protocol,
+    pub channel: u8,
+    pub wiring: Wiring,
+}
+
+impl Framer<'_> {
+    /// add the channel to the address byte, a channel past the byte is an error
+    fn address(&self, mut frame: Frame) -> Result<Frame, RelayError> {
+        let byte = self.protocol.channel_byte;
+        frame[byte] = self
+            .channel
+            .checked_sub(1)
+            .and_then(|offset| frame[byte].checked_add(offset))
+            .ok_or_else(|| {
+                RelayError::InvalidArgument(format!(
+                    "channel {} can't be addressed in {:02X?}",
+                    self.channel, frame
+                ))
+            })?;
+        Ok(frame)
+    }
+
+    pub fn control_mode(&self) -> Result<Frame, RelayError> {
+        self.address([self.protocol.prefix, 0xA0, 0x0C, 0x54])
+    }
+
+    pub fn jog_mode(&self) -> Result<Frame, RelayError> {
+        self.address([self.protocol.prefix, 0xA0, 0x0C, 0x55])
+    }
+
+    /// the timer frame has no address byte: the firmware times the relay of the
+    /// mode frame sent before it, so every timed command starts with the
+    /// control or jog mode frame of its channel
+    pub fn timer(&self, timeout: u16) -> Frame {
+        let timeout = timeout.to_ne_bytes();
+        [self.protocol.prefix, timeout[1], timeout[0], 0x57]
+    }
+
+    pub fn action(&self, action: Action) -> Result<Frame, RelayError> {
+        self.address([
+            self.protocol.prefix,
+            0xA0,
+            action.enable_byte(self.wiring),
+            0x53,
+        ])
+    }
+
+    /// action for several relays at once, bit n-1 of the mask selects relay n
+    pub fn mask_action(&self, mask: u8, action: Action) -> Frame {
+        [
+            self.protocol.prefix,
+            mask,
+            action.enable_byte(self.wiring),
+            0x53,
+        ]
+    }
+
+    pub fn query(&self) -> Result<Frame, RelayError> {
+        self.address([self.protocol.prefix, 0xA0, 0x0C, 0x56])
+    }
+
+    pub fn identify(&self) -> Frame {
+        [self.protocol.prefix, 0xA0, 0x0C, 0x58]
+    }
+
+    pub fn on(&self) -> Result<[Frame; 2], RelayError> {
+        Ok([self.control_mode()?, self.action(Action::Connect)?])
+    }
+
+    pub fn off(&self) -> Result<[Frame; 2], RelayError> {
+        Ok([self.control_mode()?, self.action(Action::Disconnect)?])
+    }
+
+    pub fn timed_on(&self, timeout: u16) -> Result<[Frame; 3], RelayError> {
+        let [control_mode, disconnect] = self.off()?;
+        Ok([control_mode, disconnect, self.timer(timeout)])
+    }
+
+    pub fn timed_off(&self, timeout: u16) -> Result<[Frame; 3], RelayError> {
+        let [control_mode, connect] = self.on()?;
+        Ok([control_mode, connect, self.timer(timeout)])
+    }
+
+    pub fn toggle(&self) -> Result<[Frame; 2], RelayError> {
+        Ok([self.control_mode()?, self.timer(0)])
+    }
+
+    pub fn jog(&self) -> Result<[Frame; 2], RelayError> {
+        Ok([self.jog_mode()?, self.action(Action::Connect)?])
+    }
+
+    /// decode the answer to [`Framer::identify`], `[prefix, firmware, channels, 58]`
+    pub fn identity(&self, response: Frame) -> Option<Identity> {
+        // v2 boards carry the checksum in the last byte
+        let code = self.protocol.checksum || response[3] == 0x58;
+        (response[0] == self.protocol.prefix && code).then_some(Identity {
+            firmware: Some(response[1]),
+            channels: Some(response[2]),
+        })
+    }
+
+    /// decode the answer to [`Framer::query`]
+    pub fn state(&self, response: Frame) -> Option<RelayState> {
+        let sealed = |action| {
+            self.action(action)
+                .ok()
+                .map(|frame| self.protocol.seal(frame))
+        };
+        if sealed(Action::Connect) == Some(response) {
+            Some(RelayState::On)
+        } else if sealed(Action::Disconnect) == Some(response) {
+            Some(RelayState::Off)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,225 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// relay control via HTTP requests
+use crate::daemon::SharedPort;
+use anyhow::{anyhow, Result};
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::thread;
+use tiny_http::{Header, Method, Response, Server};
+use tty_relay::{Port, RelayState};
+
+/// counters exposed on /metrics in the Prometheus text format
+#[derive(Default)]
+struct Metrics {
+    commands: BTreeMap<String, u64>,
+    failures: BTreeMap<String, u64>,
+    state: Option<RelayState>,
+}
+
+impl Metrics {
+    fn record<T>(&mut self, command: &str, result: &Result<T>) {
+        *self.commands.entry(command.to_string()).or_default() += 1;
+        if result.is_err() {
+            *self.failures.entry(command.to_string()).or_default() += 1;
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let mut counter = |name: &str, help: &str, values: &BTreeMap<String, u64>| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for (command, value) in values {
+                let _ = writeln!(out, "{}{{command=\"{}\"}} {}", name, command, value);
+            }
+        };
+        counter(
+            "relay_commands_total",
+            "Commands issued to the relay.",
+            &self.commands,
+        );
+        counter(
+            "relay_command_failures_total",
+            "Commands which failed.",
+            &self.failures,
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP relay_state Relay state after the last successful on/off, 1 is on."
+        );
+        let _ = writeln!(out, "# TYPE relay_state gauge");
+        if let Some(state) = self.state {
+            let _ = writeln!(out, "relay_state {}", u8::from(state == RelayState::On));
+        }
+
+        out
+    }
+}
+
+fn error(status: u16, message: impl ToString) -> (u16, Value) {
+    (status, json!({ "ok": false, "error": message.to_string() }))
+}
+
+/// route the request, a string body is sent as plain text
+fn handle<F>(
+    port: &SharedPort<F>,
+    metrics: &Mutex<Metrics>,
+    method: &Method,
+    url: &str,
+) -> (u16, Value)
+where
+    F: Fn() -> Result<Port>,
+{
+    let route = url.split('?').next().unwrap_or_default();
+
+    match (method, route) {
+        (Method::Post, "/on" | "/off" | "/toggle" | "/jog") => {
+            let command = &route[1..];
+            let result = port.command(command);
+            metrics.lock().unwrap().record(command, &result);
+            match result {
+                Ok(state) => {
+                    if matches!(command, "on" | "off") {
+                        metrics.lock().unwrap().state = state;
+                    }
+                    (
+                        200,
+                        json!({ "ok": true, "command": command, "state": state.map(|s| s.to_string()) }),
+                    )
+                }
+                Err(e) => error(503, format!("{:#}", e)),
+            }
+        }
+        (Method::Get, "/status") => {
+            let result = port.run(|port| Ok(port.status()?));
+            metrics.lock().unwrap().record("status", &result);
+            match result {
+                Ok(state) => (200, json!({ "ok": true, "state": state.to_string() })),
+                Err(e) => error(503, format!("{:#}", e)),
+            }
+        }
+        (Method::Get, "/metrics") => (200, Value::String(metrics.lock().unwrap().render())),
+        (_, "/on" | "/off" | "/toggle" | "/jog" | "/status" | "/metrics") => {
+            error(405, "method not allowed")
+        }
+        _ => error(404, "not found"),
+    }
+}
+
+/// serve the HTTP API on `listen` until the process is stopped
+///
+/// every request is handled on its own thread, the commands queue up on the shared
+/// port which stays open between requests; requests fail with 503 while it is
+/// unavailable, an idle port gets a keepalive frame, see
+/// [`LazyPort::keepalive`](crate::daemon::LazyPort::keepalive)
+pub fn run<F>(listen: &str, port: SharedPort<F>) -> Result<()>
+where
+    F: Fn() -> Result<Port> + Send,
+{
+    let server =
+        Server::http(listen).map_err(|e| anyhow!("failed to listen on {}: {}", listen, e))?;
+    let json = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let text = Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+    let metrics = Mutex::new(Metrics::default());
+
+    info!("listening on {}", listen);
+
+    thread::scope(|scope| loop {
+        port.tick();
+        let request = match port.idle_timeout() {
+            Some(timeout) => match server.recv_timeout(timeout)? {
+                Some(request) => request,
+                None => continue,
+            },
+            None => server.recv()?,
+        };
+
+        let (port, metrics, json, text) = (port.clone(), &metrics, json.clone(), text.clone());
+        scope.spawn(move || {
+            let (status, body) = handle(&port, metrics, request.method(), request.url());
+            debug!("{} {} -> {}", request.method(), request.url(), status);
+
+            let response = match body {
+                Value::String(body) => Response::from_string(body).with_header(text),
+                body => Response::from_string(body.to_string()).with_header(json),
+            }
+            .with_status_code(status);
+            if let Err(e) = request.respond(response) {
+                warn!("failed to send response: {}", e);
+            }
+        });
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::LazyPort;
+    use tty_relay::OpenOptions;
+
+    #[test]
+    fn test_routes() {
+        let port = SharedPort::new(LazyPort::new(|| {
+            Ok(OpenOptions::new().dry_run(true).open()?)
+        }));
+        let metrics = Mutex::default();
+
+        let (status, body) = handle(&port, &metrics, &Method::Post, "/on");
+        assert_eq!(status, 200);
+        assert_eq!(body["state"], "on");
+
+        assert_eq!(handle(&port, &metrics, &Method::Get, "/on").0, 405);
+        assert_eq!(handle(&port, &metrics, &Method::Post, "/explode").0, 404);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let port = SharedPort::new(LazyPort::new(|| {
+            Ok(OpenOptions::new().dry_run(true).open()?)
+        }));
+        let metrics = Mutex::default();
+
+        handle(&port, &metrics, &Method::Post, "/on");
+        handle(&port, &metrics, &Method::Post, "/on");
+        handle(&port, &metrics, &Method::Get, "/status");
+
+        let (status, body) = handle(&port, &metrics, &Method::Get, "/metrics");
+        assert_eq!(status, 200);
+        let text = body.as_str().unwrap();
+        assert!(text.contains("relay_commands_total{command=\"on\"} 2\n"));
+        assert!(text.contains("relay_commands_total{command=\"status\"} 1\n"));
+        assert!(text.contains("relay_command_failures_total{command=\"status\"} 1\n"));
+        assert!(text.contains("relay_state 1\n"));
+    }
+
+    #[test]
+    fn test_unavailable() {
+        let port = SharedPort::new(LazyPort::new(|| {
+            Ok(OpenOptions::new().tty("/dev/NOT_FOUND").open()?)
+        }));
+        let metrics = Mutex::default();
+
+        let (status, body) = handle(&port, &metrics, &Method::Post, "/off");
+        assert_eq!(status, 503);
+        assert_eq!(body["ok"], false);
+    }
+}
@@ -0,0 +1,207 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// minimum time between relay state changes, kept in a file across runs
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+use log::{debug, info, warn};
+use std::fs::{self, File};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tty_relay::{Port, RelayError};
+
+/// `--min-interval`: state changes of a port closer than `interval` apart are
+/// rejected, or delayed with `wait`
+pub struct MinInterval {
+    pub file: PathBuf,
+    pub interval: Duration,
+    pub wait: bool,
+}
+
+impl MinInterval {
+    /// time left until `port` may change its state again
+    fn remaining(&self, changes: &Changes, port: &str, now: DateTime<Local>) -> Option<Duration> {
+        let last = changes.last(port)?;
+        // a clock set back counts as a change right now
+        let elapsed = (now - last).to_std().unwrap_or_default();
+        self.interval
+            .checked_sub(elapsed)
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// run the state change `change`, rejected or delayed when it comes too early
+    ///
+    /// the file stays locked from the check until the change is recorded, so
+    /// concurrent runs can't both pass the check; a change which fails is not recorded
+    pub fn run<T>(
+        &self,
+        port: &mut Port,
+        change: impl FnOnce(&mut Port) -> Result<T>,
+    ) -> Result<T> {
+        let mut changes = match Changes::open(&self.file) {
+            Ok(changes) => changes,
+            Err(e) => {
+                warn!("{:#}, --min-interval is not enforced", e);
+                return change(port);
+            }
+        };
+
+        if let Some(remaining) = self.remaining(&changes, port.path(), Local::now()) {
+            if !self.wait {
+                bail!(RelayError::InvalidArgument(format!(
+                    "{} changed state less than {} seconds ago, retry in {} seconds or pass --wait",
+                    port.path(),
+                    self.interval.as_secs(),
+                    remaining.as_secs() + 1
+                )));
+            }
+
+            info!("{}: wait {:?} for --min-interval", port.path(), remaining);
+            port.sleep(remaining)?;
+        }
+
+        let result = change(port)?;
+
+        // the command already succeeded, failures are only logged
+        if let Err(e) = changes.set(port.path(), Local::now()) {
+            warn!(
+                "failed to update state change file {}: {:#}",
+                self.file.display(),
+                e
+            );
+        }
+
+        Ok(result)
+    }
+}
+
+/// the state change file, `<port> <RFC3339 time>` lines, one per port
+///
+/// locked exclusively until dropped
+struct Changes {
+    file: File,
+    lines: Vec<(String, String)>,
+}
+
+impl Changes {
+    fn open(path: &Path) -> Result<Changes> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut file = File::options()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("failed to open state change file {}", path.display()))?;
+        file.lock()
+            .with_context(|| format!("failed to lock state change file {}", path.display()))?;
+
+        let mut content = String::new();
+        if let Err(e) = file.read_to_string(&mut content) {
+            warn!("failed to read state change file {}: {}", path.display(), e);
+            content.clear();
+        }
+
+        let lines = content
+            .lines()
+            .filter_map(|line| line.rsplit_once(' '))
+            .map(|(port, at)| (port.to_string(), at.to_string()))
+            .collect();
+
+        Ok(Changes { file, lines })
+    }
+
+    fn last(&self, port: &str) -> Option<DateTime<Local>> {
+        let (_, at) = self.lines.iter().find(|(p, _)| p == port)?;
+        match DateTime::parse_from_rfc3339(at) {
+            Ok(at) => Some(at.with_timezone(&Local)),
+            Err(e) => {
+                debug!("ignoring corrupt state change time {:?}: {}", at, e);
+                None
+            }
+        }
+    }
+
+    fn set(&mut self, port: &str, at: DateTime<Local>) -> Result<()> {
+        self.lines.retain(|(p, _)| p != port);
+        self.lines.push((port.to_string(), at.to_rfc3339()));
+
+        let content: String = self
+            .lines
+            .iter()
+            .map(|(p, at)| format!("{} {}\n", p, at))
+            .collect();
+        self.file.set_len(0)?;
+        self.file.rewind()?;
+        self.file.write_all(content.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_min_interval() {
+        let dir = env::temp_dir().join(format!("tty_relay_interval_{}", std::process::id()));
+        let guard = MinInterval {
+            file: dir.join("last_change"),
+            interval: Duration::from_secs(10),
+            wait: false,
+        };
+        let mut port = Port::open_mock();
+        let now = Local::now();
+
+        let mut changes = Changes::open(&guard.file).unwrap();
+        assert_eq!(guard.remaining(&changes, "mock", now), None);
+
+        changes.set("mock", now).unwrap();
+        changes.set("/dev/ttyUSB1", now).unwrap();
+        assert_eq!(
+            guard.remaining(&changes, "mock", now + chrono::Duration::seconds(4)),
+            Some(Duration::from_secs(6))
+        );
+        assert_eq!(
+            guard.remaining(&changes, "mock", now + chrono::Duration::seconds(10)),
+            None
+        );
+        drop(changes);
+        assert!(guard.run(&mut port, |_| Ok(())).is_err());
+
+        // other ports are not affected
+        let changes = Changes::open(&guard.file).unwrap();
+        assert_eq!(changes.lines.len(), 2);
+        assert_eq!(guard.remaining(&changes, "/dev/ttyUSB0", now), None);
+        drop(changes);
+
+        // a failed change is not recorded
+        let other = MinInterval {
+            file: dir.join("other"),
+            ..guard
+        };
+        assert!(other.run::<()>(&mut port, |_| bail!("unplugged")).is_err());
+        other.run(&mut port, |_| Ok(())).unwrap();
+        assert!(other.run(&mut port, |_| Ok(())).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! power management via CH340 tty relay boards
+//!
+//! ```no_run
+//! use tty_relay::Port;
+//!
+//! let mut port = Port::open(None)?;
+//! port.on()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+#![warn(missing_docs)]
+
+#[cfg(feature = "async")]
+mod async_port;
+mod cache;
+mod clock;
+mod device;
+mod error;
+mod frame;
+mod lock;
+mod port;
+mod protocol;
+mod recorder;
+
+#[cfg(feature = "async")]
+pub use async_port::AsyncPort;
+pub use clock::{Clock, SystemClock, VirtualClock};
+pub use device::{is_com_port, DeviceInfo};
+pub use error::RelayError;
+pub use port::{Identity, OpenOptions, Port, PowerGuard, ReadWrite, RelayState, Wiring};
+pub use protocol::{Protocol, TimerUnit, MAX_CHANNELS, MAX_TIMER_SECONDS, PREFIX};
+pub use recorder::Recorder;
@@ -0,0 +1,168 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// advisory lock of a tty device, so concurrent processes don't interleave frames
+use crate::error::RelayError;
+use anyhow::{bail, Context, Result};
+use log::debug;
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// lock files are kept in /run/lock, which services with `PrivateTmp=` share
+/// with everyone else unlike the temp directory; the temp directory is the
+/// fallback where /run/lock is missing or not writable
+fn lock_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if cfg!(unix) && Path::new("/run/lock").is_dir() {
+        dirs.push(PathBuf::from("/run/lock"));
+    }
+    dirs.push(env::temp_dir());
+    dirs
+}
+
+/// lock file of the device in `dir`, the device itself is not opened for
+/// locking since opening a tty toggles DTR on many adapters
+///
+/// symlinks are resolved, so udev aliases of a device share its lock
+fn lock_path(device: &str, dir: &Path) -> PathBuf {
+    let device = fs::canonicalize(device)
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| device.to_string());
+    let name: String = device
+        .trim_start_matches('/')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    dir.join(format!("tty_relay-{}.lock", name))
+}
+
+fn open(path: &PathBuf) -> io::Result<File> {
+    match File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+    {
+        // created by another user, a read-only handle can hold the lock as well
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => File::open(path),
+        result => result,
+    }
+}
+
+/// lock the device for this process, released when the file is dropped
+///
+/// fails with [`RelayError::Busy`] when another process holds the lock for longer than `wait`
+pub(crate) fn acquire(device: &str, wait: Duration) -> Result<File> {
+    let mut dirs = lock_dirs().into_iter().peekable();
+    let (path, file) = loop {
+        let path = lock_path(device, &dirs.next().unwrap());
+        match open(&path) {
+            Ok(file) => break (path, file),
+            Err(e) if dirs.peek().is_some() => {
+                debug!("failed to open lock file {}: {}", path.display(), e);
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to open lock file {}", path.display()))
+            }
+        }
+    };
+    let deadline = Instant::now() + wait;
+
+    loop {
+        match file.try_lock() {
+            Ok(()) => {
+                debug!("{} locked via {}", device, path.display());
+                return Ok(file);
+            }
+            Err(fs::TryLockError::WouldBlock) if Instant::now() < deadline => {
+                thread::sleep(POLL_INTERVAL);
+            }
+            Err(fs::TryLockError::WouldBlock) => bail!(RelayError::Busy(format!(
+                "{}: device busy, locked by another process ({})",
+                device,
+                path.display()
+            ))),
+            Err(fs::TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("failed to lock {}", path.display()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_path() {
+        let dir = env::temp_dir();
+        assert_eq!(
+            lock_path("/dev/NOT_FOUND", &dir),
+            dir.join("tty_relay-dev_NOT_FOUND.lock")
+        );
+        assert_eq!(lock_path("COM3", &dir), dir.join("tty_relay-COM3.lock"));
+        assert_eq!(*lock_dirs().last().unwrap(), dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_lock_path_alias() {
+        let dir = env::temp_dir().join(format!("tty_relay_lock_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let device = dir.join("ttyUSB0");
+        fs::write(&device, "").unwrap();
+        let alias = dir.join("relay");
+        std::os::unix::fs::symlink(&device, &alias).unwrap();
+
+        assert_eq!(
+            lock_path(alias.to_str().unwrap(), &dir),
+            lock_path(device.to_str().unwrap(), &dir)
+        );
+
+        // the alias and the device are the same lock
+        let lock = acquire(alias.to_str().unwrap(), Duration::ZERO).unwrap();
+        assert!(acquire(device.to_str().unwrap(), Duration::ZERO).is_err());
+        drop(lock);
+
+        for lock_dir in lock_dirs() {
+            let _ = fs::remove_file(lock_path(device.to_str().unwrap(), &lock_dir));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_acquire() {
+        let device = format!("/dev/tty_relay_test_{}", std::process::id());
+
+        let lock = acquire(&device, Duration::ZERO).unwrap();
+        let err = acquire(&device, Duration::from_millis(100)).unwrap_err();
+        assert!(matches!(RelayError::find(&err), Some(RelayError::Busy(_))));
+
+        drop(lock);
+        let lock = acquire(&device, Duration::ZERO).unwrap();
+        drop(lock);
+        for dir in lock_dirs() {
+            let _ = fs::remove_file(lock_path(&device, &dir));
+        }
+    }
+}
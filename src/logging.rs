@@ -0,0 +1,146 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// logger setup, the log file records which command touched which port
+use anyhow::{Context as _, Result};
+use chrono::{Local, SecondsFormat};
+use flexi_logger::{
+    Cleanup, Criterion, DeferredNow, Duplicate, FileSpec, Logger, LoggerHandle, Naming, Record,
+};
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// rotate the log file once it grows over 1 MiB, keeping 5 old ones
+const ROTATE_SIZE: u64 = 1 << 20;
+const KEEP_FILES: usize = 5;
+
+struct Context {
+    command: String,
+    port: String,
+}
+
+static CONTEXT: Mutex<Context> = Mutex::new(Context {
+    command: String::new(),
+    port: String::new(),
+});
+
+/// command name added to the log file lines
+pub fn set_command(command: &str) {
+    CONTEXT.lock().unwrap().command = command.to_string();
+}
+
+/// resolved port path added to the log file lines
+pub fn set_port(port: &str) {
+    CONTEXT.lock().unwrap().port = port.to_string();
+}
+
+fn or_dash(s: &str) -> &str {
+    if s.is_empty() {
+        "-"
+    } else {
+        s
+    }
+}
+
+fn console_format(
+    w: &mut dyn io::Write,
+    _now: &mut DeferredNow,
+    record: &Record,
+) -> io::Result<()> {
+    write!(
+        w,
+        "{} [{}] {}",
+        record.level(),
+        record.module_path().unwrap_or("<unnamed>"),
+        record.args()
+    )
+}
+
+fn file_format(w: &mut dyn io::Write, _now: &mut DeferredNow, record: &Record) -> io::Result<()> {
+    let context = CONTEXT.lock().unwrap();
+    write!(
+        w,
+        "{} {} command={} port={} [{}] {}",
+        Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+        record.level(),
+        or_dash(&context.command),
+        or_dash(&context.port),
+        record.module_path().unwrap_or("<unnamed>"),
+        record.args()
+    )
+}
+
+/// start the logger, `spec` overrides RUST_LOG and `default` is used without both
+///
+/// the log file gets at least info messages while the console keeps its level
+pub fn init(spec: Option<&str>, default: &str, log_file: Option<&Path>) -> Result<LoggerHandle> {
+    let logger = match (spec, log_file) {
+        (Some(spec), _) => Logger::try_with_str(spec),
+        (None, Some(_)) => Logger::try_with_env_or_str("info"),
+        (None, None) => Logger::try_with_env_or_str(default),
+    }?
+    .format_for_stderr(console_format);
+
+    let logger = match log_file {
+        Some(path) => {
+            let duplicate = match spec.unwrap_or(default) {
+                "error" => Duplicate::Error,
+                "info" => Duplicate::Info,
+                "debug" => Duplicate::Debug,
+                "trace" => Duplicate::All,
+                _ => Duplicate::Warn,
+            };
+            logger
+                .log_to_file(FileSpec::try_from(path)?)
+                .format_for_files(file_format)
+                .append()
+                .rotate(
+                    Criterion::Size(ROTATE_SIZE),
+                    Naming::Numbers,
+                    Cleanup::KeepLogFiles(KEEP_FILES),
+                )
+                .duplicate_to_stderr(duplicate)
+        }
+        None => logger,
+    };
+
+    logger.start().context("failed to start the logger")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn test_file_format() {
+        set_command("on");
+        set_port("/dev/ttyUSB0");
+
+        let mut buf = Vec::new();
+        let record = Record::builder()
+            .level(Level::Info)
+            .module_path(Some("tty_relay"))
+            .args(format_args!("hello"))
+            .build();
+        file_format(&mut buf, &mut DeferredNow::new(), &record).unwrap();
+
+        let line = String::from_utf8(buf).unwrap();
+        assert!(line.ends_with(" INFO command=on port=/dev/ttyUSB0 [tty_relay] hello"));
+        assert!(chrono::DateTime::parse_from_rfc3339(line.split(' ').next().unwrap()).is_ok());
+    }
+}
@@ -15,149 +15,3049 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 /// tty relay manager
-use anyhow::Result;
-use clap::{crate_authors, crate_version, Arg, ArgMatches, ColorChoice, Command};
-use clap_complete::{
-    generate,
-    shells::{Bash, Elvish, Fish, PowerShell, Zsh},
-};
-use port::Port;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+use clap::{crate_authors, crate_version, Arg, ArgMatches, ColorChoice, Command, ValueSource};
+use completions::Target;
+use config::{Config, Confirm, Profile, Revision};
+#[cfg(any(feature = "mqtt", feature = "http"))]
+use daemon::{LazyPort, SharedPort};
+#[cfg(any(feature = "mqtt", feature = "http"))]
+use events::Events;
+use interval::MinInterval;
+use serde::Serialize;
 use std::env;
-use std::io;
-use std::path::Path;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, OnceLock};
+use std::thread;
+use std::time::Duration;
+use tty_relay::{
+    is_com_port, DeviceInfo, OpenOptions, Port, Protocol, Recorder, RelayError, RelayState,
+    TimerUnit, Wiring, MAX_CHANNELS,
+};
 
 const APPNAME: &str = "tty_relay";
+/// `--generate` value printing the supported shells
+const LIST_SHELLS: &str = "list";
 
-mod port;
+mod commands;
+mod completions;
+mod config;
+#[cfg(any(feature = "mqtt", feature = "http"))]
+mod daemon;
+mod events;
+#[cfg(feature = "http")]
+mod http;
+mod interval;
+mod logging;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod repl;
+mod schedule;
+mod script;
+mod state;
+mod wiring;
+mod yaml;
 
+#[derive(Clone)]
 enum Cmd {
     On,
     Off,
-    Toggle,
+    Toggle {
+        report: bool,
+    },
     Jog,
+    /// `jog --hold`: connected for the milliseconds, timed by the host
+    JogHold(u64),
+    /// on/off only when the state differs
+    Set(RelayState),
+    /// several channels at once via a bitmask frame
+    Switch {
+        channels: Vec<u8>,
+        state: RelayState,
+    },
+    /// interactive NO/NC check
+    VerifyWiring,
+    Reset,
     Restart,
     TimedOn(u16),
     TimedOff(u16),
-    Unknown,
+    ArmFailsafe(u16),
+    Pulse {
+        ms: u64,
+        invert: bool,
+    },
+    Blink {
+        count: u32,
+        on_ms: u64,
+        off_ms: u64,
+    },
+    Watchdog {
+        interval: u16,
+        margin: u16,
+        events: Option<PathBuf>,
+    },
+    Schedule {
+        at: DateTime<Local>,
+        action: Box<Cmd>,
+    },
+    /// `--off-after`: the action followed by the firmware timer
+    OffAfter {
+        action: Box<Cmd>,
+        secs: u16,
+    },
+    Status,
+    Info,
+    List,
+    Capabilities,
+    /// reachability check, `ack` waits for the echoed frame
+    Probe {
+        ack: bool,
+    },
+    /// hand-crafted frame, optionally followed by reading the answer
+    Raw {
+        frame: [u8; 4],
+        read: bool,
+    },
+    Repl,
+    Pipe,
+    Restore,
+    Run(Vec<(String, script::Step)>),
+    Show(Box<Cmd>),
+    #[cfg(feature = "mqtt")]
+    Daemon {
+        broker: String,
+        topic: String,
+        state_topic: String,
+        events: Option<PathBuf>,
+    },
+    #[cfg(feature = "http")]
+    Serve {
+        listen: String,
+        events: Option<PathBuf>,
+    },
 }
 
-fn parse_command(matches: &ArgMatches) -> Cmd {
-    let subcommand = matches.subcommand_name().unwrap();
+impl Cmd {
+    /// name of the table entry the command was parsed by
+    fn name(&self) -> &'static str {
+        match self {
+            Cmd::On
+            | Cmd::Switch {
+                state: RelayState::On,
+                ..
+            } => "on",
+            Cmd::Off
+            | Cmd::Switch {
+                state: RelayState::Off,
+                ..
+            } => "off",
+            Cmd::Toggle { .. } => "toggle",
+            Cmd::Jog | Cmd::JogHold(_) => "jog",
+            Cmd::Set(_) => "set",
+            Cmd::VerifyWiring => "verify-wiring",
+            Cmd::Reset => "reset",
+            Cmd::Restart => "restart",
+            Cmd::TimedOn(_) => "timed_start",
+            Cmd::TimedOff(_) => "timed_stop",
+            Cmd::ArmFailsafe(_) => "arm-failsafe",
+            Cmd::Pulse { .. } => "pulse",
+            Cmd::Blink { .. } => "blink",
+            Cmd::Watchdog { .. } => "watchdog",
+            Cmd::Schedule { .. } => "schedule",
+            Cmd::OffAfter { action, .. } => action.name(),
+            Cmd::Status => "status",
+            Cmd::Info => "info",
+            Cmd::List => "list",
+            Cmd::Capabilities => "capabilities",
+            Cmd::Probe { .. } => "probe",
+            Cmd::Raw { .. } => "raw",
+            Cmd::Repl => "repl",
+            Cmd::Pipe => "pipe",
+            Cmd::Restore => "restore",
+            Cmd::Run(_) => "run",
+            Cmd::Show(_) => "show",
+            #[cfg(feature = "mqtt")]
+            Cmd::Daemon { .. } => "daemon",
+            #[cfg(feature = "http")]
+            Cmd::Serve { .. } => "serve",
+        }
+    }
+}
 
-    if subcommand == "on" {
-        Cmd::On
-    } else if subcommand == "off" {
-        Cmd::Off
-    } else if subcommand == "toggle" {
-        Cmd::Toggle
-    } else if subcommand == "jog" {
-        Cmd::Jog
-    } else if subcommand == "restart" {
-        Cmd::Restart
-    } else if let Some(sub_matches) = matches.subcommand_matches("timed_start") {
-        let seconds = sub_matches.value_of("seconds").unwrap().parse().unwrap();
-        Cmd::TimedOn(seconds)
-    } else if let Some(sub_matches) = matches.subcommand_matches("timed_stop") {
-        let seconds = sub_matches.value_of("seconds").unwrap().parse().unwrap();
-        Cmd::TimedOff(seconds)
+/// machine readable `--format`, both print the same structs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    Yaml,
+}
+
+impl Format {
+    /// `None` for the human readable output
+    fn of(matches: &ArgMatches) -> Option<Format> {
+        match matches.value_of("format") {
+            Some("json") => Some(Format::Json),
+            Some("yaml") => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+
+    fn print(self, value: &impl Serialize) -> Result<()> {
+        match self {
+            Format::Json => println!("{}", serde_json::to_string(value)?),
+            Format::Yaml => print!("{}", yaml::to_string(value)?),
+        }
+        Ok(())
+    }
+}
+
+/// resolved port printed by `--print-port --format json`
+#[derive(Serialize)]
+struct PrintedPort<'a> {
+    port: &'a str,
+}
+
+/// command result printed with `--format json`
+#[derive(Serialize)]
+struct Report<'a> {
+    command: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    port: Option<String>,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// frames sent to the --mock port
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frames: Option<Vec<String>>,
+}
+
+/// `name` in the cache directory of the app, $XDG_CACHE_HOME/tty_relay
+fn cache_path(name: &str) -> Option<PathBuf> {
+    let cache_home = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+
+    Some(cache_home.join(APPNAME).join(name))
+}
+
+fn open_port(matches: &ArgMatches, config: &Config) -> Result<Port> {
+    open_device(matches, config, None)
+}
+
+/// whether a --devices entry names a tty rather than a serial number
+fn is_tty_name(device: &str) -> bool {
+    is_com_port(device) || device.contains('/') || Path::new("/dev").join(device).exists()
+}
+
+/// open the port selected by the arguments, or the --devices entry `device`
+fn open_device(matches: &ArgMatches, config: &Config, device: Option<&str>) -> Result<Port> {
+    if matches.is_present("mock") {
+        let mut port = Port::open_mock();
+        if matches.is_present("simulate latency") {
+            let latency = matches.value_of_t("simulate latency")?;
+            port.set_frame_delay(Duration::from_millis(latency));
+        }
+        logging::set_port(port.path());
+        configure_port(&mut port, matches, config)?;
+        open_capture(&mut port, matches)?;
+        return Ok(port);
+    }
+
+    let options = open_options(matches, config, device)?.dry_run(matches.is_present("dry run"));
+    let mut port = if matches.is_present("baud autodetect") {
+        let (port, baud) = options.detect_baud(|port| {
+            configure_link(port, matches, config)?;
+            configure_port(port, matches, config)?;
+            port.probe(true)?;
+            Ok(())
+        })?;
+        if !matches.is_present("quiet") {
+            eprintln!("{}: detected {} baud", port.path(), baud);
+        }
+        port
+    } else {
+        let mut port = options.open()?;
+        configure_link(&mut port, matches, config)?;
+        port
+    };
+    logging::set_port(port.path());
+    log::info!("opened {}", port.path());
+    configure_port(&mut port, matches, config)?;
+    open_capture(&mut port, matches)?;
+
+    Ok(port)
+}
+
+/// --vid and --pid, then the profile, then the config file
+fn usb_ids(matches: &ArgMatches, config: &Config) -> Result<(Option<u16>, Option<u16>)> {
+    let (vid, pid) = default_usb_ids(matches, config)?;
+    let (flag_vid, flag_pid) = flag_usb_ids(matches);
+
+    Ok((flag_vid.or(vid), flag_pid.or(pid)))
+}
+
+/// --vid and --pid
+fn flag_usb_ids(matches: &ArgMatches) -> (Option<u16>, Option<u16>) {
+    let id = |id| matches.value_of(id).map(|id| parse_usb_id(id).unwrap());
+    (id("vid"), id("pid"))
+}
+
+/// the ids of the profile, then the config file
+fn default_usb_ids(matches: &ArgMatches, config: &Config) -> Result<(Option<u16>, Option<u16>)> {
+    let profile = config.profile(matches.value_of("profile"))?;
+    Ok((profile.vid.or(config.vid), profile.pid.or(config.pid)))
+}
+
+/// port path selection and transport options of the arguments
+fn open_options(
+    matches: &ArgMatches,
+    config: &Config,
+    device: Option<&str>,
+) -> Result<OpenOptions> {
+    let mut options = OpenOptions::new();
+
+    let autodetect = matches.is_present("serial")
+        || matches.is_present("vid")
+        || matches.is_present("pid")
+        || matches.is_present("remote");
+    let port_env = env::var_os(OpenOptions::PORT_ENV).is_some();
+    let tty = match (device, matches.value_of("tty port")) {
+        (Some(device), _) => is_tty_name(device).then_some(device),
+        (None, Some(tty)) => Some(tty),
+        (None, None) if !autodetect && !port_env => config.tty.as_deref(),
+        (None, None) => None,
+    };
+
+    if let Some(tty) = tty {
+        options = options.tty(tty);
+    }
+
+    if let Some(remote) = matches.value_of("remote") {
+        options = options.remote(remote);
+    }
+
+    let serial = match device {
+        Some(device) => (!is_tty_name(device)).then_some(device),
+        None => matches.value_of("serial"),
+    };
+    if let Some(serial) = serial {
+        options = options.serial(serial);
+    }
+
+    let profile = config.profile(matches.value_of("profile"))?;
+
+    // only the flags take precedence over TTY_RELAY_PORT, the profile and
+    // config ids just select what autodetection looks for
+    let (vid, pid) = flag_usb_ids(matches);
+    if let Some(vid) = vid {
+        options = options.vid(vid);
+    }
+    if let Some(pid) = pid {
+        options = options.pid(pid);
+    }
+    let (vid, pid) = default_usb_ids(matches, config)?;
+    options = options.default_ids(vid, pid);
+
+    let baud = matches.value_of("baud").map(str::parse).transpose()?;
+    if let Some(baud) = baud.or(profile.baud).or(config.baud) {
+        options = options.baud(baud);
+    }
+
+    let (timeout, frame_delay) = link_timing(matches, &profile, tty)?;
+
+    if matches.is_present("baud autodetect") && !matches.is_present("no cache") {
+        if let Some(path) = cache_path("baud") {
+            options = options.baud_cache(path);
+        }
+    }
+
+    // the cache remembers a single device
+    if !matches.is_present("no cache") && device.is_none() {
+        if let Some(path) = cache_path("port") {
+            options = options.cache(path);
+        }
+    }
+
+    if matches.is_present("wait for device") {
+        let timeout = match matches.value_of("device timeout") {
+            Some(_) => Some(Duration::from_secs(matches.value_of_t("device timeout")?)),
+            None => None,
+        };
+        options = options.wait_for_device(
+            Duration::from_millis(matches.value_of_t("poll interval")?),
+            timeout,
+        );
+    }
+
+    Ok(options
+        .retries(
+            matches.value_of_t("retries")?,
+            Duration::from_millis(matches.value_of_t("retry delay")?),
+        )
+        .timeout(timeout)
+        .frame_delay(frame_delay)
+        .lock(!matches.is_present("no lock"))
+        .lock_wait(Duration::from_secs(matches.value_of_t("lock wait")?)))
+}
+
+/// read timeout and frame delay: explicit flags, then the profile, then the link defaults
+fn link_timing(
+    matches: &ArgMatches,
+    profile: &Profile,
+    tty: Option<&str>,
+) -> Result<(Duration, Duration)> {
+    let rfcomm = tty.is_some_and(is_rfcomm);
+    let millis = |id, profile: Option<u64>, rfcomm_default| -> Result<Duration> {
+        Ok(Duration::from_millis(match profile {
+            Some(ms) if !explicit(matches, id) => ms,
+            None if rfcomm && !explicit(matches, id) => rfcomm_default,
+            _ => matches.value_of_t(id)?,
+        }))
+    };
+
+    let timeout = millis("timeout", profile.timeout, RFCOMM_TIMEOUT)?;
+    let frame_delay = if matches.is_present("no sleep") {
+        Duration::ZERO
+    } else {
+        millis("frame delay", profile.frame_delay, RFCOMM_FRAME_DELAY)?
+    };
+    Ok((timeout, frame_delay))
+}
+
+/// timing of the opened port, decided on the resolved path: a tty taken from
+/// `TTY_RELAY_PORT` or the cache may be an rfcomm link as well
+fn configure_link(port: &mut Port, matches: &ArgMatches, config: &Config) -> Result<()> {
+    let profile = config.profile(matches.value_of("profile"))?;
+    let (timeout, frame_delay) = link_timing(matches, &profile, Some(port.path()))?;
+    if matches.is_present("no sleep") {
+        log::warn!("--no-sleep: frames are sent back to back, some boards drop them");
+    }
+
+    port.set_timeout(timeout);
+    port.set_frame_delay(frame_delay);
+    Ok(())
+}
+
+/// bluetooth serial links (`/dev/rfcomm0`) answer much slower than USB
+const RFCOMM_TIMEOUT: u64 = 100;
+const RFCOMM_FRAME_DELAY: u64 = 150;
+
+/// `rfcomm<n>`, bare or in /dev
+fn is_rfcomm(tty: &str) -> bool {
+    tty.strip_prefix("/dev/")
+        .unwrap_or(tty)
+        .strip_prefix("rfcomm")
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// --keepalive interval of the daemon modes, 0 disables it
+#[cfg(any(feature = "mqtt", feature = "http"))]
+fn keepalive(matches: &ArgMatches) -> Result<Option<Duration>> {
+    let secs: u16 = matches.value_of_t("keepalive")?;
+    Ok((secs > 0).then(|| Duration::from_secs(secs.into())))
+}
+
+/// share the daemon port, publishing its state changes on --events-socket
+#[cfg(any(feature = "mqtt", feature = "http"))]
+fn shared_port<F>(port: LazyPort<F>, events: &Option<PathBuf>) -> Result<SharedPort<F>>
+where
+    F: Fn() -> Result<Port>,
+{
+    let port = SharedPort::new(port);
+    Ok(match events {
+        Some(path) => port.events(Events::bind(path)?),
+        None => port,
+    })
+}
+
+/// the --min-interval guard, dry-run and mock frames don't change a relay
+fn min_interval(matches: &ArgMatches) -> Result<Option<MinInterval>> {
+    let secs: u16 = match matches.value_of("min interval") {
+        Some(_) => matches.value_of_t("min interval")?,
+        None => return Ok(None),
+    };
+    if secs == 0 || matches.is_present("dry run") || matches.is_present("mock") {
+        return Ok(None);
+    }
+
+    let file = cache_path("last_change").ok_or_else(|| {
+        RelayError::InvalidArgument(
+            "--min-interval needs $XDG_CACHE_HOME or $HOME for its state file".to_string(),
+        )
+    })?;
+
+    Ok(Some(MinInterval {
+        file,
+        interval: Duration::from_secs(secs.into()),
+        wait: matches.is_present("wait"),
+    }))
+}
+
+/// print the port the arguments select, without opening it
+fn print_port(matches: &ArgMatches, config: &Config) -> Result<()> {
+    let path = if matches.is_present("mock") {
+        Port::open_mock().path().to_string()
+    } else {
+        open_options(matches, config, None)?.resolve()?
+    };
+
+    if let Some(format) = Format::of(matches) {
+        format.print(&PrintedPort { port: &path })?;
+    } else {
+        println!("{}", path);
+    }
+
+    Ok(())
+}
+
+/// append the frames sent to the port to the --capture file
+fn open_capture(port: &mut Port, matches: &ArgMatches) -> Result<()> {
+    if let Some(path) = matches.value_of("capture") {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open capture file {}", path))?;
+        port.set_capture(Box::new(file));
+    }
+
+    Ok(())
+}
+
+/// whether the argument was given on the command line rather than defaulted
+fn explicit(matches: &ArgMatches, id: &str) -> bool {
+    matches.value_source(id) == Some(ValueSource::CommandLine)
+}
+
+/// frame layout, channel and wiring options
+fn configure_port(port: &mut Port, matches: &ArgMatches, config: &Config) -> Result<()> {
+    // explicit flags win over the profile, which wins over the defaults
+    let profile = config.profile(matches.value_of("profile"))?;
+    let channels = match profile.channels {
+        Some(channels) if !explicit(matches, "channels") => channels,
+        _ => matches.value_of_t("channels")?,
+    };
+    let checksum = match profile.protocol {
+        Some(revision) if !explicit(matches, "protocol") => revision == Revision::V2,
+        _ => matches.value_of("protocol") == Some("v2"),
+    };
+    let prefix = match profile.prefix {
+        Some(prefix) if !explicit(matches, "prefix") => prefix,
+        _ => parse_prefix(matches.value_of("prefix").unwrap()).map_err(anyhow::Error::msg)?,
+    };
+
+    port.set_protocol(Protocol {
+        channels,
+        checksum,
+        prefix,
+        max_timer: matches.value_of_t("max timer")?,
+        timer_unit: match matches.value_of("timer unit") {
+            Some("deciseconds") => TimerUnit::Deciseconds,
+            _ => TimerUnit::Seconds,
+        },
+        ..Protocol::default()
+    });
+    port.set_clamp(matches.is_present("clamp"));
+    port.set_channel(matches.value_of_t("channel")?)?;
+    port.set_verify(matches.is_present("verify"));
+    port.set_drain(matches.is_present("drain"));
+    port.set_write_retries(matches.value_of_t("write retries")?);
+    port.set_invert(matches.is_present("invert"));
+
+    let normally_closed = if matches.is_present("normally closed") {
+        Some(true)
+    } else if matches.is_present("normally open") {
+        Some(false)
     } else {
-        Cmd::Unknown
+        profile.normally_closed.or(config.normally_closed)
+    };
+    match normally_closed {
+        Some(true) => port.set_wiring(Wiring::NormallyClosed),
+        Some(false) => port.set_wiring(Wiring::NormallyOpen),
+        None => {}
+    }
+
+    Ok(())
+}
+
+/// print the frames of the command without a device
+fn show(matches: &ArgMatches, config: &Config, cmd: Cmd) -> Result<()> {
+    let recorder = Recorder::new();
+    let mut port = Port::recording(&recorder);
+    configure_port(&mut port, matches, config)?;
+    // nothing would acknowledge the frames
+    port.set_verify(false);
+
+    let result = dispatch(&mut port, cmd);
+
+    for frame in recorder.frames() {
+        println!("{}", hex(&frame));
+    }
+
+    result.map(|_| ())
+}
+
+fn hex(frame: &[u8]) -> String {
+    frame
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// device printed by `list --format json`
+#[derive(Serialize)]
+struct DeviceEntry<'a> {
+    port: &'a str,
+    vid: String,
+    pid: String,
+    serial: Option<&'a str>,
+    manufacturer: Option<&'a str>,
+    product: Option<&'a str>,
+    compatible: bool,
+    /// autodetection would consider it, see [`Matcher`]
+    matching: bool,
+}
+
+impl<'a> DeviceEntry<'a> {
+    fn new(device: &'a DeviceInfo, matcher: &Matcher) -> Self {
+        DeviceEntry {
+            port: &device.port,
+            vid: format!("{:04x}", device.vid),
+            pid: format!("{:04x}", device.pid),
+            serial: device.serial.as_deref(),
+            manufacturer: device.manufacturer.as_deref(),
+            product: device.product.as_deref(),
+            compatible: device.is_compatible(),
+            matching: matcher.matches(device),
+        }
     }
 }
 
-fn autocomplete(matches: &ArgMatches, app: &mut Command) {
-    if let Some(generator) = matches.value_of("generator") {
-        eprintln!("Generating completion file for {}...", generator);
-        match generator {
-            "bash" => generate(Bash, app, APPNAME, &mut io::stdout()),
-            "elvish" => generate(Elvish, app, APPNAME, &mut io::stdout()),
-            "fish" => generate(Fish, app, APPNAME, &mut io::stdout()),
-            "powershell" => generate(PowerShell, app, APPNAME, &mut io::stdout()),
-            "zsh" => generate(Zsh, app, APPNAME, &mut io::stdout()),
-            _ => panic!("Unknown generator"),
+/// the configured --vid/--pid, or the default CH340 ids without them
+struct Matcher(Option<u16>, Option<u16>);
+
+impl Matcher {
+    fn matches(&self, device: &DeviceInfo) -> bool {
+        match self {
+            Matcher(None, None) => device.is_compatible(),
+            Matcher(vid, pid) => device.has_ids(*vid, *pid),
         }
+    }
+}
 
-        process::exit(0);
+/// print the USB serial devices, ports without USB metadata are not listed
+fn list(format: Option<Format>, matcher: Matcher) -> Result<()> {
+    let devices = Port::list_devices()?;
+
+    if let Some(format) = format {
+        let entries: Vec<DeviceEntry> = devices
+            .iter()
+            .map(|device| DeviceEntry::new(device, &matcher))
+            .collect();
+        return format.print(&entries);
+    }
+
+    if devices.is_empty() {
+        println!("no devices");
+        return Ok(());
     }
+
+    print!("{}", device_table(&devices, &matcher));
+    Ok(())
 }
 
-fn is_number(val: &str) -> Result<(), String> {
-    let _: i32 = val
-        .parse()
-        .map_err(|e: std::num::ParseIntError| e.to_string())?;
+/// `list` output, devices autodetection would consider are marked with `*`
+fn device_table(devices: &[DeviceInfo], matcher: &Matcher) -> String {
+    let text = |s: &Option<String>| s.clone().unwrap_or_else(|| "-".to_string());
+
+    let header = ["", "PORT", "VID:PID", "SERIAL", "MANUFACTURER", "PRODUCT"];
+    let mut rows = vec![header.map(str::to_string).to_vec()];
+    rows.extend(devices.iter().map(|device| {
+        vec![
+            if matcher.matches(device) { "*" } else { "" }.to_string(),
+            device.display_name().to_string(),
+            format!("{:04x}:{:04x}", device.vid, device.pid),
+            text(&device.serial),
+            text(&device.manufacturer),
+            text(&device.product),
+        ]
+    }));
+    table(&rows)
+}
+
+/// align the cells in columns as wide as their widest cell
+fn table(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or_default();
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            rows.iter()
+                .filter_map(|row| row.get(column))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let mut out = String::new();
+    for row in rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        out.push_str(line.join(" ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// stop the port on Ctrl-C instead of terminating mid-sequence, the handler is installed once
+fn interrupt_on_ctrlc(port: &mut Port) -> Result<()> {
+    static INTERRUPT: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+    let interrupt = match INTERRUPT.get() {
+        Some(interrupt) => interrupt.clone(),
+        None => {
+            let interrupt = Arc::new(AtomicBool::new(false));
+            let handler = interrupt.clone();
+            ctrlc::set_handler(move || handler.store(true, Ordering::SeqCst))
+                .context("failed to install Ctrl-C handler")?;
+            INTERRUPT.get_or_init(|| interrupt).clone()
+        }
+    };
+    port.set_interrupt(interrupt);
 
     Ok(())
 }
 
-fn main() -> Result<()> {
-    flexi_logger::Logger::try_with_env()
-        .unwrap()
-        .start()
-        .unwrap();
+/// interactive commands keep the default Ctrl-C, which ends the session
+fn reads_stdin(cmd: &Cmd) -> bool {
+    matches!(cmd, Cmd::Repl | Cmd::Pipe | Cmd::VerifyWiring)
+}
 
-    let generator_args = || {
-        Arg::new("generator")
-            .long("generate")
-            .value_name("shell")
-            .possible_values(&["bash", "elvish", "fish", "powershell", "zsh"])
+/// whether the command was stopped by Ctrl-C
+fn is_interrupted(error: &anyhow::Error) -> bool {
+    matches!(RelayError::find(error), Some(RelayError::Interrupted(_)))
+}
+
+/// runs a command on an open port, see [`execute`]
+type Runner = Arc<dyn Fn(&mut Port, Cmd) -> Result<Option<RelayState>> + Send + Sync>;
+
+/// --command-timeout, rejected for the commands which run until they are stopped
+fn command_timeout(matches: &ArgMatches, cmd: &Cmd) -> Result<Option<Duration>> {
+    if matches.value_of("command timeout").is_none() {
+        return Ok(None);
+    }
+
+    if reads_stdin(cmd) || matches!(cmd, Cmd::Watchdog { .. }) {
+        bail!(RelayError::InvalidArgument(format!(
+            "--command-timeout does not apply to {}",
+            matches.subcommand_name().unwrap_or_default()
+        )));
+    }
+
+    Ok(Some(Duration::from_millis(
+        matches.value_of_t("command timeout")?,
+    )))
+}
+
+/// run the command, with a timeout on a worker thread which owns the port
+///
+/// a write blocked in a wedged adapter can't be cancelled: on timeout the worker
+/// is abandoned together with the port, which is closed once the worker returns
+/// or the process exits. the port is handed back when the command finished
+fn run_with_timeout(
+    mut port: Port,
+    cmd: Cmd,
+    timeout: Option<Duration>,
+    runner: &Runner,
+) -> (Option<Port>, Result<Option<RelayState>>) {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => {
+            let result = runner(&mut port, cmd);
+            return (Some(port), result);
+        }
     };
 
-    let tty_port_arg = || {
-        Arg::new("tty port")
-            .long("tty")
-            .short('t')
-            .help("manually select tty port")
-            .takes_value(true)
-            .validator(|s| {
-                let path = Path::new(s);
+    let path = port.path().to_string();
+    let (sender, receiver) = mpsc::channel();
+    let runner = Arc::clone(runner);
+    thread::spawn(move || {
+        let result = runner(&mut port, cmd);
+        // nobody waits after a timeout, the port is dropped with the message
+        let _ = sender.send((port, result));
+    });
 
-                if path.exists() {
-                    Ok(())
-                } else {
-                    Err("Invalid path")
-                }
-            })
+    match receiver.recv_timeout(timeout) {
+        Ok((port, result)) => (Some(port), result),
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            // the worker still holds the port, it stays open until the blocked
+            // call returns or the process exits
+            log::warn!("{}: command timed out, abandoning the port", path);
+            (
+                None,
+                Err(RelayError::Timeout(format!(
+                    "{}: command did not finish within {}ms",
+                    path,
+                    timeout.as_millis()
+                ))
+                .into()),
+            )
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+            (None, Err(anyhow::anyhow!("{}: command panicked", path)))
+        }
+    }
+}
+
+/// run the command on every --devices entry, `stagger` apart
+///
+/// failures don't stop the remaining devices, all results are printed at the end
+fn run_devices(
+    matches: &ArgMatches,
+    config: &Config,
+    cmd: Cmd,
+    devices: Vec<&str>,
+    stagger: Duration,
+    timeout: Option<Duration>,
+    runner: &Runner,
+) -> Result<()> {
+    let mut reports = Vec::new();
+    let mut first_error = None;
+
+    for (i, device) in devices.iter().enumerate() {
+        let result = open_device(matches, config, Some(device)).and_then(|mut port| {
+            interrupt_on_ctrlc(&mut port)?;
+            if i > 0 {
+                port.sleep(stagger)?;
+            }
+            run_with_timeout(port, cmd.clone(), timeout, runner).1
+        });
+        let interrupted = result.as_ref().err().is_some_and(is_interrupted);
+
+        reports.push(Report {
+            command: matches.subcommand_name().unwrap_or_default(),
+            port: Some(device.to_string()),
+            ok: result.is_ok(),
+            state: match &result {
+                Ok(Some(state)) => Some(state.to_string()),
+                _ => None,
+            },
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+            frames: None,
+        });
+        if let Err(e) = result {
+            first_error.get_or_insert(e);
+        }
+
+        // leave the remaining devices untouched
+        if interrupted {
+            break;
+        }
+    }
+
+    if let Some(format) = Format::of(matches) {
+        format.print(&reports)?;
+    } else {
+        for report in &reports {
+            let port = report.port.as_deref().unwrap_or_default();
+            match (&report.error, &report.state) {
+                (Some(error), _) => println!("{}: error: {}", port, error),
+                (None, Some(state)) => println!("{}: {}", port, state),
+                (None, None) => println!("{}: ok", port),
+            }
+        }
+    }
+
+    match first_error {
+        Some(e) => {
+            let failed = reports.iter().filter(|report| !report.ok).count();
+            Err(e.context(format!("{} of {} devices failed", failed, devices.len())))
+        }
+        None => Ok(()),
+    }
+}
+
+/// run the command `repeat` times, `interval` apart
+///
+/// every repetition is a state change checked against `min_interval`
+fn repeat(
+    port: &mut Port,
+    cmd: Cmd,
+    repeat: u32,
+    interval: Duration,
+    min_interval: Option<&MinInterval>,
+) -> Result<Option<RelayState>> {
+    let run = |port: &mut Port, cmd: Cmd| match min_interval {
+        Some(min_interval) => min_interval.run(port, |port| dispatch(port, cmd)),
+        None => dispatch(port, cmd),
+    };
+
+    for i in 1..repeat {
+        log::debug!("repetition {}/{}", i, repeat);
+        run(port, cmd.clone())?;
+        port.sleep(interval)?;
+    }
+
+    log::debug!("repetition {}/{}", repeat, repeat);
+    run(port, cmd)
+}
+
+/// seconds of --off-after, which only applies to `on` and `jog`
+fn off_after(matches: &ArgMatches, action: &Cmd) -> Result<Option<u16>> {
+    if matches.value_of("off after").is_none() {
+        return Ok(None);
+    }
+
+    if !matches!(action, Cmd::On | Cmd::Jog) {
+        bail!(RelayError::InvalidArgument(
+            "--off-after only applies to on and jog".to_string()
+        ));
+    }
+
+    Ok(Some(matches.value_of_t("off after")?))
+}
+
+/// whether the command enables power, see --power-on-safe
+fn energizes(cmd: &Cmd) -> bool {
+    matches!(
+        cmd,
+        Cmd::On
+            | Cmd::Toggle { .. }
+            | Cmd::Jog
+            | Cmd::JogHold(_)
+            | Cmd::VerifyWiring
+            | Cmd::Set(RelayState::On)
+            | Cmd::Switch {
+                state: RelayState::On,
+                ..
+            }
+            | Cmd::Restart
+            | Cmd::TimedOn(_)
+            | Cmd::ArmFailsafe(_)
+            | Cmd::Pulse { .. }
+            | Cmd::Blink { .. }
+            | Cmd::Run(_)
+            | Cmd::Pipe
+            | Cmd::OffAfter { .. }
+    )
+}
+
+/// run the command and keep the state file up to date
+///
+/// with `power_on_safe` a failing command which energizes the relay disables power again
+fn execute(
+    port: &mut Port,
+    cmd: Cmd,
+    repetitions: u32,
+    interval: Duration,
+    state_file: Option<&Path>,
+    power_on_safe: bool,
+    min_interval: Option<&MinInterval>,
+) -> Result<Option<RelayState>> {
+    if let Cmd::Restore = cmd {
+        let path = state_file
+            .ok_or_else(|| RelayError::InvalidArgument("restore needs --state-file".to_string()))?;
+        return state::restore(port, path);
+    }
+
+    let min_interval =
+        min_interval.filter(|_| matches!(cmd, Cmd::On | Cmd::Off | Cmd::Toggle { .. }));
+
+    let tracked = matches!(
+        cmd,
+        Cmd::On | Cmd::Off | Cmd::Reset | Cmd::Toggle { .. } | Cmd::Set(_)
+    )
+    .then(|| cmd.clone());
+    let energizing = energizes(&cmd);
+    let result = if power_on_safe && energizing {
+        let mut guard = port.power_guard();
+        let result = repeat(&mut guard, cmd, repetitions, interval, min_interval);
+        if result.is_ok() {
+            guard.disarm();
+        }
+        result
+    } else {
+        repeat(port, cmd, repetitions, interval, min_interval)
     };
 
-    macro_rules! timed_command {
-        ($name:expr) => {
-            Command::new(concat!("timed_", $name))
-                .about(concat!($name, " after n seconds"))
-                .arg(Arg::new("seconds").required(true).validator(is_number))
+    let reported = match result {
+        // reading or disabling commands leave the relay as it is
+        Err(e) if is_interrupted(&e) && energizing => {
+            log::warn!("{}: interrupted, disabling power", port.path());
+            if let Err(off) = port.force_off() {
+                log::warn!("{}: failed to disable power: {:#}", port.path(), off);
+            }
+            return Err(e);
+        }
+        result => result?,
+    };
+
+    // dry-run and mock frames don't change a relay
+    let state_file = state_file.filter(|_| !port.is_simulated());
+    if let (Some(cmd), Some(path)) = (tracked, state_file) {
+        let state = match cmd {
+            Cmd::On => Some(RelayState::On),
+            Cmd::Off | Cmd::Reset => Some(RelayState::Off),
+            Cmd::Set(state) => Some(state),
+            // read back the toggled state, or flip the saved one
+            _ => reported.or_else(|| port.status().ok()).or_else(|| {
+                state::load(path).map(|state| match repetitions % 2 {
+                    1 => state::inverted(state),
+                    _ => state,
+                })
+            }),
         };
+        state::update(path, state);
     }
 
-    let mut app = Command::new(APPNAME)
-        .about("tty power management")
-        .author(crate_authors!())
-        .color(ColorChoice::Auto)
-        .arg_required_else_help(true)
-        .arg(generator_args())
-        .arg(tty_port_arg())
-        .subcommand(Command::new("on").about("enable power"))
-        .subcommand(Command::new("off").about("disable power"))
-        .subcommand(Command::new("toggle").about("toggle power"))
-        .subcommand(Command::new("jog").about("quick toggle power"))
-        .subcommand(Command::new("restart").about("software restart"))
-        .subcommand(timed_command!("start"))
-        .subcommand(timed_command!("stop"))
-        .version(crate_version!());
-
-    let matches = app.clone().get_matches();
-
-    autocomplete(&matches, &mut app);
-
-    let mut port = Port::open(matches.value_of("tty port"))?;
-
-    match parse_command(&matches) {
-        Cmd::On => port.on(),
-        Cmd::Off => port.off(),
-        Cmd::Toggle => port.toggle(),
-        Cmd::Jog => port.jog(),
-        Cmd::Restart => port.restart(),
-        Cmd::TimedOn(secs) => port.timed_on(secs),
-        Cmd::TimedOff(secs) => port.timed_off(secs),
-        Cmd::Unknown => panic!("unknown command {:?}", matches),
+    Ok(reported)
+}
+
+/// run the command with the action of its table entry, `--off-after` wraps
+/// such a command and has no entry of its own
+fn dispatch(port: &mut Port, cmd: Cmd) -> Result<Option<RelayState>> {
+    match cmd {
+        Cmd::OffAfter { action, secs } => {
+            dispatch(port, *action)?;
+            port.arm_timer(secs)?;
+            Ok(None)
+        }
+        cmd => commands::run(port, cmd),
+    }
+}
+
+fn should_prompt(mode: Confirm, interactive: bool) -> bool {
+    match mode {
+        Confirm::Never => false,
+        Confirm::Auto => interactive,
+        Confirm::Always => true,
+    }
+}
+
+/// ask a yes/no question, anything but yes is a no
+fn ask(mut input: impl BufRead, mut output: impl Write, question: &str) -> Result<bool> {
+    write!(output, "{} [y/N] ", question)?;
+    output.flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// the commands which disconnect power and ask first with --confirm
+fn disconnects(cmd: &Cmd) -> bool {
+    matches!(
+        cmd,
+        Cmd::Off
+            | Cmd::Switch {
+                state: RelayState::Off,
+                ..
+            }
+            | Cmd::Set(RelayState::Off)
+            | Cmd::Reset
+            | Cmd::TimedOff(_)
+    )
+}
+
+/// ask before a command in [`disconnects`] runs, see --confirm
+fn confirm_disconnect(mode: Confirm, cmd: &Cmd, path: &str) -> Result<()> {
+    if !disconnects(cmd) {
+        return Ok(());
+    }
+
+    let stdin = io::stdin();
+    if !should_prompt(mode, stdin.is_terminal()) {
+        return Ok(());
+    }
+
+    let question = format!("Really disconnect power on {}?", path);
+    if !ask(stdin.lock(), io::stderr(), &question)? {
+        bail!("aborted");
+    }
+
+    Ok(())
+}
+
+/// log level forced by --quiet/--verbose, these win over RUST_LOG
+fn log_spec(matches: &ArgMatches) -> Option<&'static str> {
+    if matches.is_present("quiet") {
+        return Some("error");
+    }
+
+    match matches.occurrences_of("verbose") {
+        0 => None,
+        1 => Some("debug"),
+        _ => Some("trace"),
+    }
+}
+
+/// what this build supports, printed by `capabilities`
+#[derive(Serialize)]
+struct Capabilities {
+    version: &'static str,
+    commands: Vec<String>,
+    protocols: Vec<&'static str>,
+    formats: Vec<&'static str>,
+    features: Vec<&'static str>,
+}
+
+impl Capabilities {
+    /// taken from the command table and the argument definitions, so it can't go stale
+    fn new() -> Self {
+        let app = build_app();
+        let values = |id: &str| -> Vec<&'static str> {
+            app.get_arguments()
+                .find(|arg| arg.get_id() == id)
+                .and_then(|arg| arg.get_possible_values())
+                .map(|values| values.iter().map(|value| value.get_name()).collect())
+                .unwrap_or_default()
+        };
+
+        let features = [
+            ("mqtt", cfg!(feature = "mqtt")),
+            ("http", cfg!(feature = "http")),
+            ("async", cfg!(feature = "async")),
+            ("nc-connected", cfg!(feature = "nc-connected")),
+        ];
+
+        Capabilities {
+            version: crate_version!(),
+            commands: commands::table()
+                .iter()
+                .map(|(command, _, _)| command.get_name().to_string())
+                .collect(),
+            protocols: values("protocol"),
+            formats: values("format"),
+            features: features
+                .into_iter()
+                .filter_map(|(name, enabled)| enabled.then_some(name))
+                .collect(),
+        }
+    }
+}
+
+/// shells --generate knows, in the order of `--generate list`
+fn shell_names() -> Vec<&'static str> {
+    Target::all().into_iter().map(Target::name).collect()
+}
+
+fn autocomplete(matches: &ArgMatches, app: &mut Command) -> Result<()> {
+    if matches.value_of("generator") == Some(LIST_SHELLS) {
+        let shells = shell_names();
+        if let Some(format) = Format::of(matches) {
+            format.print(&shells)?;
+        } else {
+            for shell in shells {
+                println!("{}", shell);
+            }
+        }
+        process::exit(0);
+    }
+
+    if matches.is_present("generator") {
+        let generator = Target::parse(matches.value_of("generator").unwrap())
+            .expect("--generate only takes the listed shells");
+        if !matches.is_present("quiet") {
+            eprintln!("Generating completion file for {}...", generator);
+        }
+
+        let mut out: Box<dyn Write> = match matches.value_of("output") {
+            Some(path) => {
+                let path = Path::new(path);
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)
+                        .with_context(|| format!("failed to create {}", dir.display()))?;
+                }
+                let file = File::create(path)
+                    .with_context(|| format!("failed to create {}", path.display()))?;
+                Box::new(file)
+            }
+            None => Box::new(io::stdout()),
+        };
+
+        generator.generate(app, APPNAME, &mut out);
+        out.flush()?;
+        process::exit(0);
+    }
+
+    Ok(())
+}
+
+fn is_seconds(val: &str) -> Result<(), String> {
+    let _: u16 = val
+        .parse()
+        .map_err(|_| format!("seconds must be between 0 and {}", u16::MAX))?;
+
+    Ok(())
+}
+
+/// timer length in seconds, bare seconds or a duration like `90m` or `1h30m`
+fn parse_timer(val: &str) -> Result<u16, String> {
+    if let Ok(secs) = val.parse() {
+        return Ok(secs);
+    }
+    if val.contains('.') {
+        return Err(format!(
+            "fractional duration {} is not supported, the timer counts whole seconds",
+            val
+        ));
+    }
+
+    let duration = schedule::parse_duration(val).map_err(|e| e.to_string())?;
+    u16::try_from(duration.as_secs()).map_err(|_| {
+        format!(
+            "duration {} exceeds the timer range of {} seconds",
+            val,
+            u16::MAX
+        )
+    })
+}
+
+fn is_timer(val: &str) -> Result<(), String> {
+    parse_timer(val).map(|_| ())
+}
+
+fn parse_usb_id(val: &str) -> Result<u16, String> {
+    let hex = val
+        .strip_prefix("0x")
+        .or_else(|| val.strip_prefix("0X"))
+        .unwrap_or(val);
+
+    u16::from_str_radix(hex, 16).map_err(|_| format!("{} is not a 16-bit hex number", val))
+}
+
+fn parse_prefix(val: &str) -> Result<u8, String> {
+    let hex = val
+        .strip_prefix("0x")
+        .or_else(|| val.strip_prefix("0X"))
+        .unwrap_or(val);
+
+    u8::from_str_radix(hex, 16).map_err(|_| format!("{} is not an 8-bit hex number", val))
+}
+
+/// four hex bytes, like `F0 A0 0C 54` (one or several words) or `F0A00C54`
+fn parse_frame(val: &str) -> Result<[u8; 4], String> {
+    let mut hex = String::new();
+    for word in val.split(|c: char| c.is_whitespace() || c == ',') {
+        let word = word
+            .strip_prefix("0x")
+            .or_else(|| word.strip_prefix("0X"))
+            .unwrap_or(word);
+        // checked before slicing, a multibyte char would split at a byte offset
+        if word.len() % 2 != 0 || !word.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("{} is not a sequence of hex bytes", val));
+        }
+        hex.push_str(word);
+    }
+
+    if hex.len() != 8 {
+        return Err(format!("a frame is exactly 4 bytes, got {:?}", val));
+    }
+
+    let mut frame = [0; 4];
+    for (i, byte) in frame.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+            .map_err(|_| format!("{} is not a sequence of hex bytes", val))?;
+    }
+    Ok(frame)
+}
+
+fn is_prefix(val: &str) -> Result<(), String> {
+    parse_prefix(val).map(|_| ())
+}
+
+fn is_usb_id(val: &str) -> Result<(), String> {
+    parse_usb_id(val).map(|_| ())
+}
+
+fn is_milliseconds(val: &str) -> Result<(), String> {
+    let _: u64 = val
+        .parse()
+        .map_err(|e: std::num::ParseIntError| e.to_string())?;
+
+    Ok(())
+}
+
+fn is_hold(val: &str) -> Result<(), String> {
+    let hold: u64 = val
+        .parse()
+        .map_err(|e: std::num::ParseIntError| e.to_string())?;
+
+    if hold == 0 {
+        return Err("hold must be at least 1ms".to_string());
+    }
+
+    Ok(())
+}
+
+fn is_timeout(val: &str) -> Result<(), String> {
+    let timeout: u64 = val
+        .parse()
+        .map_err(|e: std::num::ParseIntError| e.to_string())?;
+
+    if timeout == 0 {
+        return Err("timeout must be at least 1ms".to_string());
+    }
+
+    Ok(())
+}
+
+fn is_count(val: &str) -> Result<(), String> {
+    let _: u32 = val
+        .parse()
+        .map_err(|e: std::num::ParseIntError| e.to_string())?;
+
+    Ok(())
+}
+
+fn is_baud(val: &str) -> Result<(), String> {
+    let baud: u32 = val
+        .parse()
+        .map_err(|e: std::num::ParseIntError| e.to_string())?;
+
+    if baud == 0 {
+        return Err("baud rate must be positive".to_string());
+    }
+
+    Ok(())
+}
+
+fn is_channel(val: &str) -> Result<(), String> {
+    let channel: u8 = val
+        .parse()
+        .map_err(|e: std::num::ParseIntError| e.to_string())?;
+
+    if channel == 0 {
+        return Err("channels are numbered from 1".to_string());
+    }
+    if channel > MAX_CHANNELS {
+        return Err(format!("boards have at most {} channels", MAX_CHANNELS));
+    }
+
+    Ok(())
+}
+
+fn build_app() -> Command<'static> {
+    let generator_args = || {
+        Arg::new("generator")
+            .long("generate")
+            .value_name("shell")
+            .help("print a completion script, `list` prints the supported shells")
+            .possible_values(shell_names().into_iter().chain(iter::once(LIST_SHELLS)))
+    };
+
+    let output_arg = || {
+        Arg::new("output")
+            .long("output")
+            .value_name("path")
+            .help("write the completion script to a file instead of stdout")
+            .takes_value(true)
+            .requires("generator")
+    };
+
+    let config_arg = || {
+        Arg::new("config")
+            .long("config")
+            .value_name("path")
+            .help("config file, defaults to $XDG_CONFIG_HOME/tty_relay/config.toml")
+            .takes_value(true)
+    };
+
+    let mock_arg = || {
+        Arg::new("mock")
+            .long("mock")
+            .help("send the frames to an in-memory port, for testing without hardware")
+            .conflicts_with_all(&["dry run", "tty port", "remote"])
+    };
+
+    let simulate_latency_arg = || {
+        Arg::new("simulate latency")
+            .long("simulate-latency")
+            .value_name("MS")
+            .help("delay every frame of the --mock port like a board would, in milliseconds")
+            .takes_value(true)
+            .requires("mock")
+            .validator(is_milliseconds)
+    };
+
+    let dry_run_arg = || {
+        Arg::new("dry run")
+            .long("dry-run")
+            .help("log frames instead of sending them to the relay")
+    };
+
+    let verbosity_args = || {
+        [
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .help("only print errors"),
+            Arg::new("verbose")
+                .long("verbose")
+                .short('v')
+                .help("print debug messages, twice for trace")
+                .multiple_occurrences(true)
+                .conflicts_with("quiet"),
+        ]
+    };
+
+    let confirm_arg = || {
+        Arg::new("confirm")
+            .long("confirm")
+            .value_name("when")
+            .help("ask before off, set off, reset and timed_stop, auto only asks on a terminal")
+            .takes_value(true)
+            .min_values(0)
+            .require_equals(true)
+            .possible_values(["never", "auto", "always"])
+            .default_missing_value("auto")
+    };
+
+    let state_file_arg = || {
+        Arg::new("state file")
+            .long("state-file")
+            .value_name("path")
+            .help("remember the state after on, off and toggle, see restore")
+            .takes_value(true)
+    };
+
+    let log_file_arg = || {
+        Arg::new("log file")
+            .long("log-file")
+            .value_name("path")
+            .help("also write timestamped logs to a rotating file")
+            .takes_value(true)
+    };
+
+    let min_interval_args = || {
+        [
+            Arg::new("min interval")
+                .long("min-interval")
+                .value_name("SECS")
+                .help("reject on, off and toggle within n seconds of the last state change")
+                .takes_value(true)
+                .validator(is_seconds),
+            Arg::new("wait")
+                .long("wait")
+                .help("wait for --min-interval to pass instead of failing")
+                .requires("min interval"),
+        ]
+    };
+
+    let profile_arg = || {
+        Arg::new("profile")
+            .long("profile")
+            .value_name("name")
+            .help("board preset (lctech-1ch, lctech-2ch, lctech-4ch, hc-05 or one from the config file)")
+            .takes_value(true)
+    };
+
+    let keepalive_arg = || {
+        Arg::new("keepalive")
+            .long("keepalive")
+            .value_name("SECS")
+            .help("send a harmless frame after n idle seconds in daemon and serve, 0 disables")
+            .takes_value(true)
+            .default_value("0")
+            .validator(is_seconds)
+    };
+
+    let print_port_arg = || {
+        Arg::new("print port")
+            .long("print-port")
+            .help("print the port which would be used and exit, nothing is opened or sent")
+            .conflicts_with("devices")
+    };
+
+    let list_arg = || {
+        Arg::new("list")
+            .long("list")
+            .help("list USB serial devices and exit, the same as the list command")
+            .conflicts_with_all(&["print port", "generator"])
+    };
+
+    let capture_arg = || {
+        Arg::new("capture")
+            .long("capture")
+            .value_name("path")
+            .help("append every sent frame with a timestamp to the file, for bug reports")
+            .takes_value(true)
+    };
+
+    let verify_arg = || {
+        Arg::new("verify")
+            .long("verify")
+            .help("check that the relay acknowledges every frame")
+    };
+
+    let drain_arg = || {
+        Arg::new("drain")
+            .long("drain")
+            .help("discard stale input before reading a status or an acknowledgement")
+    };
+
+    let wiring_args = || {
+        [
+            Arg::new("normally closed")
+                .long("normally-closed")
+                .help("the load is wired to the NC (normally closed) contact"),
+            Arg::new("normally open")
+                .long("normally-open")
+                .help("the load is wired to the NO (normally open) contact, the default")
+                .conflicts_with("normally closed"),
+            Arg::new("invert").long("invert").help(
+                "swap on and off (also the timed commands and status), combines with the wiring",
+            ),
+        ]
+    };
+
+    let format_arg = || {
+        Arg::new("format")
+            .long("format")
+            .help("output format, table is the human one (lists are aligned tables)")
+            .takes_value(true)
+            .possible_values(["human", "table", "json", "yaml"])
+            .default_value("human")
+            .global(true)
+    };
+
+    let tty_port_arg = || {
+        Arg::new("tty port")
+            .long("tty")
+            .short('t')
+            .help("manually select tty port (or COM port), bare names are looked up in /dev")
+            .takes_value(true)
+            .validator(|s| {
+                let path = Path::new(s);
+
+                // COM ports are not filesystem paths, opening them reports errors;
+                // rfcomm nodes may only appear once the bluetooth link connects
+                if is_com_port(s)
+                    || is_rfcomm(s)
+                    || path.exists()
+                    || (!s.contains('/') && Path::new("/dev").join(s).exists())
+                {
+                    Ok(())
+                } else {
+                    Err("Invalid path, expected a tty path, a name in /dev or a COM port")
+                }
+            })
+    };
+
+    let devices_args = || {
+        [
+            Arg::new("devices")
+                .long("devices")
+                .value_name("tty|serial")
+                .help("run the command on several boards, comma separated tty paths or serial numbers")
+                .takes_value(true)
+                .use_value_delimiter(true)
+                .multiple_values(true)
+                .conflicts_with_all(&["tty port", "serial", "remote", "mock"]),
+            Arg::new("stagger")
+                .long("stagger")
+                .value_name("MS")
+                .help("delay between the --devices, e.g. to limit inrush current")
+                .takes_value(true)
+                .requires("devices")
+                .validator(is_milliseconds),
+        ]
+    };
+
+    let remote_arg = || {
+        Arg::new("remote")
+            .long("remote")
+            .value_name("host:port")
+            .help("connect to a network serial port (like ser2net)")
+            .takes_value(true)
+            .conflicts_with_all(&["tty port", "serial", "vid", "pid"])
+    };
+
+    let serial_arg = || {
+        Arg::new("serial")
+            .long("serial")
+            .value_name("SN")
+            .help("select device by USB serial number")
+            .takes_value(true)
+            .conflicts_with("tty port")
+    };
+
+    let usb_id_arg = |name: &'static str, help: &'static str| {
+        Arg::new(name)
+            .long(name)
+            .value_name("hex")
+            .help(help)
+            .takes_value(true)
+            .validator(is_usb_id)
+            .conflicts_with("tty port")
+    };
+
+    let baud_arg = || {
+        Arg::new("baud")
+            .long("baud")
+            .help(
+                "serial baud rate, 9600 by default (commonly 9600, 19200, 38400, 57600 or 115200)",
+            )
+            .takes_value(true)
+            .validator(is_baud)
+    };
+
+    let baud_autodetect_arg = || {
+        Arg::new("baud autodetect")
+            .long("baud-autodetect")
+            .help("try the common baud rates until the board acknowledges a probe, the rate is cached")
+            .conflicts_with_all(&["baud", "remote"])
+    };
+
+    let no_cache_arg = || {
+        Arg::new("no cache")
+            .long("no-cache")
+            .help("always enumerate devices instead of opening the cached port when its USB ids still match")
+    };
+
+    let wait_for_device_args = || {
+        [
+            Arg::new("wait for device")
+                .long("wait-for-device")
+                .help("poll until a matching device is enumerated instead of failing"),
+            Arg::new("device timeout")
+                .long("device-timeout")
+                .value_name("SECS")
+                .help("give up --wait-for-device after n seconds, waits forever by default")
+                .takes_value(true)
+                .requires("wait for device")
+                .validator(is_seconds),
+            Arg::new("poll interval")
+                .long("poll-interval")
+                .value_name("MS")
+                .help("delay between the --wait-for-device polls in milliseconds")
+                .takes_value(true)
+                .default_value("500")
+                .validator(is_milliseconds),
+        ]
+    };
+
+    let retries_arg = || {
+        Arg::new("retries")
+            .long("retries")
+            .value_name("N")
+            .help("retry opening the port n times")
+            .takes_value(true)
+            .default_value("0")
+            .validator(is_count)
+    };
+
+    let write_retries_arg = || {
+        Arg::new("write retries")
+            .long("write-retries")
+            .value_name("N")
+            .help("send a frame again n times after a failed write")
+            .takes_value(true)
+            .default_value("0")
+            .validator(is_count)
+    };
+
+    let retry_delay_arg = || {
+        Arg::new("retry delay")
+            .long("retry-delay")
+            .value_name("MS")
+            .help("delay between open retries in milliseconds")
+            .takes_value(true)
+            .default_value("500")
+            .validator(is_milliseconds)
+    };
+
+    let lock_args = || {
+        [
+            Arg::new("lock wait")
+                .long("lock-wait")
+                .value_name("SECS")
+                .help("wait this long for another tty_relay to release the device instead of failing as busy")
+                .takes_value(true)
+                .default_value("0")
+                .validator(is_seconds),
+            Arg::new("no lock")
+                .long("no-lock")
+                .help("don't lock the device against concurrent tty_relay processes")
+                .conflicts_with("lock wait"),
+        ]
+    };
+
+    let timeout_arg = || {
+        Arg::new("timeout")
+            .long("timeout")
+            .value_name("MS")
+            .help("serial read timeout in milliseconds (at least 1)")
+            .takes_value(true)
+            .default_value("10")
+            .validator(is_timeout)
+    };
+
+    let frame_delay_arg = || {
+        Arg::new("frame delay")
+            .long("frame-delay")
+            .value_name("MS")
+            .help("delay after every frame in milliseconds (boards usually need at least 20)")
+            .takes_value(true)
+            .default_value("50")
+            .validator(is_milliseconds)
+    };
+
+    let command_timeout_arg = || {
+        Arg::new("command timeout")
+            .long("command-timeout")
+            .value_name("MS")
+            .help("abort the command and close the port when it takes longer, e.g. on a wedged adapter")
+            .takes_value(true)
+            .validator(is_timeout)
+    };
+
+    let no_sleep_arg = || {
+        Arg::new("no sleep")
+            .long("no-sleep")
+            .help("send frames back to back without the frame delay, some boards drop frames")
+            .conflicts_with("frame delay")
+    };
+
+    let repeat_args = || {
+        [
+            Arg::new("repeat")
+                .long("repeat")
+                .value_name("N")
+                .help("send the command n times (on, off, toggle and jog only)")
+                .takes_value(true)
+                .default_value("1")
+                .validator(is_count),
+            Arg::new("interval")
+                .long("interval")
+                .value_name("MS")
+                .help("delay between repetitions in milliseconds")
+                .takes_value(true)
+                .default_value("100")
+                .validator(is_milliseconds),
+        ]
+    };
+
+    let channel_arg = || {
+        Arg::new("channel")
+            .long("channel")
+            .short('c')
+            .help("relay channel to operate on (1-8)")
+            .takes_value(true)
+            .default_value("1")
+            .validator(is_channel)
+    };
+
+    let channels_arg = || {
+        Arg::new("channels")
+            .long("channels")
+            .help("number of relay channels on the board (1-8)")
+            .takes_value(true)
+            .default_value("1")
+            .validator(is_channel)
+    };
+
+    let protocol_arg = || {
+        Arg::new("protocol")
+            .long("protocol")
+            .help("firmware protocol, v2 replaces the last frame byte with a checksum")
+            .takes_value(true)
+            .possible_values(["v1", "v2"])
+            .default_value("v1")
+    };
+
+    let prefix_arg = || {
+        Arg::new("prefix")
+            .long("prefix")
+            .value_name("hex")
+            .help("leading byte of every frame, some clones use A0")
+            .takes_value(true)
+            .default_value("0xF0")
+            .validator(is_prefix)
+    };
+
+    let max_timer_arg = || {
+        Arg::new("max timer")
+            .long("max-timer")
+            .help("longest timer in seconds accepted by the firmware")
+            .takes_value(true)
+            .value_name("seconds")
+            .default_value("64800")
+            .validator(is_seconds)
+    };
+
+    let off_after_arg = || {
+        Arg::new("off after")
+            .long("off-after")
+            .value_name("SECS")
+            .help("disconnect power n seconds after on or jog, using the firmware timer")
+            .takes_value(true)
+            .validator(is_seconds)
+    };
+
+    let power_on_safe_arg = || {
+        Arg::new("power on safe")
+            .long("power-on-safe")
+            .help("disable power again when a command which enables it fails")
+    };
+
+    let timer_unit_arg = || {
+        Arg::new("timer unit")
+            .long("timer-unit")
+            .help("unit the firmware counts the timer in")
+            .takes_value(true)
+            .possible_values(["seconds", "deciseconds"])
+            .default_value("seconds")
+    };
+
+    let clamp_arg = || {
+        Arg::new("clamp")
+            .long("clamp")
+            .help("cap timers above --max-timer instead of failing")
+    };
+
+    Command::new(APPNAME)
+        .about("tty power management")
+        .author(crate_authors!())
+        .color(ColorChoice::Auto)
+        .arg_required_else_help(true)
+        .arg(generator_args())
+        .arg(output_arg())
+        .arg(config_arg())
+        .arg(format_arg())
+        .arg(dry_run_arg())
+        .arg(mock_arg())
+        .arg(simulate_latency_arg())
+        .args(verbosity_args())
+        .arg(log_file_arg())
+        .arg(capture_arg())
+        .arg(confirm_arg())
+        .arg(state_file_arg())
+        .arg(verify_arg())
+        .arg(drain_arg())
+        .args(wiring_args())
+        .arg(print_port_arg())
+        .arg(list_arg())
+        .arg(tty_port_arg())
+        .arg(remote_arg())
+        .args(devices_args())
+        .arg(serial_arg())
+        .arg(usb_id_arg("vid", "USB vendor id used for autodetection"))
+        .arg(usb_id_arg("pid", "USB product id used for autodetection"))
+        .arg(profile_arg())
+        .arg(baud_arg())
+        .arg(baud_autodetect_arg())
+        .arg(no_cache_arg())
+        .args(wait_for_device_args())
+        .arg(retries_arg())
+        .arg(retry_delay_arg())
+        .args(lock_args())
+        .arg(write_retries_arg())
+        .arg(timeout_arg())
+        .arg(frame_delay_arg())
+        .arg(no_sleep_arg())
+        .arg(command_timeout_arg())
+        .args(repeat_args())
+        .arg(channel_arg())
+        .arg(channels_arg())
+        .arg(protocol_arg())
+        .arg(prefix_arg())
+        .arg(max_timer_arg())
+        .arg(timer_unit_arg())
+        .arg(clamp_arg())
+        .arg(off_after_arg())
+        .arg(power_on_safe_arg())
+        .args(min_interval_args())
+        .arg(keepalive_arg())
+        .subcommands(commands::table().into_iter().map(|(command, _, _)| command))
+        .version(crate_version!())
+}
+
+/// process exit code for the failure class found in the error chain
+fn exit_code(error: &anyhow::Error) -> i32 {
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<RelayError>() {
+            return match e {
+                RelayError::DeviceNotFound(_) | RelayError::AmbiguousDevice(_) => 2,
+                RelayError::OpenFailed(_)
+                | RelayError::Io(_)
+                | RelayError::NoAcknowledgement(_)
+                | RelayError::Timeout(_) => 3,
+                RelayError::InvalidArgument(_) => 4,
+                RelayError::Busy(_) => 5,
+                // 128 + SIGINT, like a shell reports it
+                RelayError::Interrupted(_) => 130,
+            };
+        }
+        if cause.is::<io::Error>() || cause.is::<serialport::Error>() {
+            return 3;
+        }
+        if cause.is::<clap::Error>() {
+            return 4;
+        }
+    }
+
+    1
+}
+
+fn main() {
+    if let Err(e) = run(env::args_os()) {
+        eprintln!("Error: {:?}", e);
+        process::exit(exit_code(&e));
+    }
+}
+
+fn run(args: impl IntoIterator<Item = OsString>) -> Result<()> {
+    let mut app = build_app();
+    let matches = match app.clone().try_get_matches_from(args) {
+        Ok(matches) => matches,
+        // help and version are not errors and keep their own exit code
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            let _ = e.print();
+            process::exit(4);
+        }
+    };
+
+    // dry-run and mock frames are logged at info level, make them visible by default
+    let default_spec = if matches.is_present("dry run") || matches.is_present("mock") {
+        "info"
+    } else {
+        ""
+    };
+    let _logger = logging::init(
+        log_spec(&matches),
+        default_spec,
+        matches.value_of("log file").map(Path::new),
+    )?;
+
+    autocomplete(&matches, &mut app)?;
+
+    if matches.is_present("print port") {
+        let config = Config::load(matches.value_of("config").map(Path::new))?;
+        return print_port(&matches, &config);
+    }
+
+    if matches.is_present("list") {
+        if let Some(name) = matches.subcommand_name() {
+            bail!(RelayError::InvalidArgument(format!(
+                "--list can't be combined with the {} command",
+                name
+            )));
+        }
+        logging::set_command("list");
+        let config = Config::load(matches.value_of("config").map(Path::new))?;
+        let (vid, pid) = usb_ids(&matches, &config)?;
+        return list(Format::of(&matches), Matcher(vid, pid));
+    }
+
+    // arg_required_else_help does not trigger once any flag is given
+    if matches.subcommand_name().is_none() {
+        app.write_help(&mut io::stderr())?;
+        eprintln!();
+    }
+
+    let mut cmd = commands::parse(&matches)?;
+    logging::set_command(matches.subcommand_name().unwrap_or_default());
+    let config = Config::load(matches.value_of("config").map(Path::new))?;
+
+    if let Cmd::List = cmd {
+        let (vid, pid) = usb_ids(&matches, &config)?;
+        return list(Format::of(&matches), Matcher(vid, pid));
+    }
+
+    if let Cmd::Capabilities = cmd {
+        return Format::of(&matches)
+            .unwrap_or(Format::Json)
+            .print(&Capabilities::new());
+    }
+
+    if let Cmd::Show(action) = cmd {
+        return show(&matches, &config, *action);
+    }
+
+    let repetitions: u32 = matches.value_of_t("repeat")?;
+    let interval = Duration::from_millis(matches.value_of_t("interval")?);
+
+    if repetitions == 0 {
+        bail!(RelayError::InvalidArgument(
+            "--repeat must be at least 1".to_string()
+        ));
+    }
+
+    let action = match &cmd {
+        Cmd::Schedule { action, .. } => action,
+        cmd => cmd,
+    };
+    if repetitions > 1
+        && !matches!(
+            action,
+            Cmd::On | Cmd::Off | Cmd::Toggle { .. } | Cmd::Jog | Cmd::JogHold(_)
+        )
+    {
+        bail!(RelayError::InvalidArgument(
+            "--repeat only applies to on, off, toggle and jog".to_string()
+        ));
+    }
+
+    let off_after = off_after(&matches, action)?;
+
+    // the port is opened only right before the scheduled action
+    if let Cmd::Schedule { at, action } = cmd {
+        let delay = (at - Local::now()).to_std().unwrap_or_default();
+        log::debug!("wait {:?} until {}", delay, at.to_rfc3339());
+        thread::sleep(delay);
+        cmd = *action;
+    }
+
+    if let Some(secs) = off_after {
+        cmd = Cmd::OffAfter {
+            action: Box::new(cmd),
+            secs,
+        };
+    }
+
+    #[cfg(feature = "mqtt")]
+    if let Cmd::Daemon {
+        broker,
+        topic,
+        state_topic,
+        events,
+    } = &cmd
+    {
+        let port = LazyPort::new(|| open_port(&matches, &config)).keepalive(keepalive(&matches)?);
+        return mqtt::run(broker, topic, state_topic, shared_port(port, events)?);
+    }
+
+    #[cfg(feature = "http")]
+    if let Cmd::Serve { listen, events } = &cmd {
+        let port = LazyPort::new(|| open_port(&matches, &config)).keepalive(keepalive(&matches)?);
+        return http::run(listen, shared_port(port, events)?);
+    }
+
+    let confirm = match matches.value_of("confirm") {
+        Some(mode) => mode.parse().map_err(anyhow::Error::msg)?,
+        None => config.confirm.unwrap_or_default(),
+    };
+
+    let state_file = matches
+        .value_of("state file")
+        .map(PathBuf::from)
+        .or_else(|| config.state_file.clone());
+
+    let power_on_safe = matches.is_present("power on safe");
+    let min_interval = min_interval(&matches)?;
+    let timeout = command_timeout(&matches, &cmd)?;
+
+    if let Some(devices) = matches.values_of("devices") {
+        if matches!(
+            cmd,
+            Cmd::Repl | Cmd::Pipe | Cmd::Restore | Cmd::Watchdog { .. }
+        ) {
+            bail!(RelayError::InvalidArgument(format!(
+                "--devices does not support {}",
+                matches.subcommand_name().unwrap_or_default()
+            )));
+        }
+
+        let devices: Vec<&str> = devices.collect();
+        confirm_disconnect(confirm, &cmd, &devices.join(", "))?;
+        let stagger = match matches.value_of("stagger") {
+            Some(_) => Duration::from_millis(matches.value_of_t("stagger")?),
+            None => Duration::ZERO,
+        };
+
+        let runner: Runner = Arc::new(move |port, cmd| {
+            execute(
+                port,
+                cmd,
+                repetitions,
+                interval,
+                None,
+                power_on_safe,
+                min_interval.as_ref(),
+            )
+        });
+        return run_devices(&matches, &config, cmd, devices, stagger, timeout, &runner);
+    }
+
+    let runner: Runner = Arc::new(move |port, cmd| {
+        execute(
+            port,
+            cmd,
+            repetitions,
+            interval,
+            state_file.as_deref(),
+            power_on_safe,
+            min_interval.as_ref(),
+        )
+    });
+
+    if let Some(format) = Format::of(&matches) {
+        let mut report = Report {
+            command: matches.subcommand_name().unwrap_or_default(),
+            port: None,
+            ok: true,
+            state: None,
+            error: None,
+            frames: None,
+        };
+
+        let result = open_port(&matches, &config).and_then(|mut port| {
+            report.port = Some(port.path().to_string());
+            confirm_disconnect(confirm, &cmd, port.path())?;
+            if !reads_stdin(&cmd) {
+                interrupt_on_ctrlc(&mut port)?;
+            }
+            let (port, result) = run_with_timeout(port, cmd, timeout, &runner);
+            report.frames = port
+                .as_ref()
+                .and_then(Port::recorder)
+                .map(|recorder| recorder.frames().iter().map(|frame| hex(frame)).collect());
+            result
+        });
+
+        match &result {
+            Ok(state) => report.state = state.map(|state| state.to_string()),
+            Err(e) => {
+                report.ok = false;
+                report.error = Some(format!("{:#}", e));
+            }
+        }
+
+        format.print(&report)?;
+
+        if let Err(e) = result {
+            process::exit(exit_code(&e));
+        }
+
+        return Ok(());
+    }
+
+    let mut port = open_port(&matches, &config)?;
+    confirm_disconnect(confirm, &cmd, port.path())?;
+    if !reads_stdin(&cmd) {
+        interrupt_on_ctrlc(&mut port)?;
+    }
+
+    if let Some(state) = run_with_timeout(port, cmd, timeout, &runner).1? {
+        println!("{}", state);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap_complete::Shell;
+    use tty_relay::MAX_TIMER_SECONDS;
+
+    #[test]
+    fn test_parse_command() {
+        let matches = build_app().get_matches_from([APPNAME, "on"]);
+        assert!(matches!(commands::parse(&matches), Ok(Cmd::On)));
+
+        let matches = build_app().get_matches_from([APPNAME, "toggle", "--report"]);
+        assert!(matches!(
+            commands::parse(&matches),
+            Ok(Cmd::Toggle { report: true })
+        ));
+
+        let matches = build_app().get_matches_from([APPNAME, "timed_start", "5"]);
+        assert!(matches!(commands::parse(&matches), Ok(Cmd::TimedOn(5))));
+
+        let matches = build_app().get_matches_from([APPNAME, "info"]);
+        assert!(matches!(commands::parse(&matches), Ok(Cmd::Info)));
+
+        let matches = build_app().get_matches_from([APPNAME, "--dry-run"]);
+        assert!(commands::parse(&matches).is_err());
+    }
+
+    #[test]
+    fn test_repeat_interrupted() {
+        let recorder = Recorder::new();
+        let mut port = Port::recording(&recorder);
+        let interrupt = Arc::new(AtomicBool::new(false));
+        port.set_interrupt(interrupt.clone());
+
+        let ctrlc = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            interrupt.store(true, Ordering::SeqCst);
+        });
+        let err = repeat(&mut port, Cmd::On, 3, Duration::from_secs(3600), None)
+            .err()
+            .unwrap();
+        ctrlc.join().unwrap();
+
+        assert!(is_interrupted(&err));
+        assert_eq!(recorder.frames().len(), 2, "only the first repetition ran");
+    }
+
+    #[test]
+    fn test_execute_interrupted() {
+        let run = |cmd: Cmd| {
+            let recorder = Recorder::new();
+            let mut port = Port::recording(&recorder);
+            let interrupt = Arc::new(AtomicBool::new(false));
+            port.set_interrupt(interrupt.clone());
+
+            let ctrlc = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                interrupt.store(true, Ordering::SeqCst);
+            });
+            let result = execute(
+                &mut port,
+                cmd,
+                3,
+                Duration::from_secs(3600),
+                None,
+                false,
+                None,
+            );
+            ctrlc.join().unwrap();
+
+            assert!(is_interrupted(&result.unwrap_err()));
+            recorder.frames()
+        };
+
+        // the relay is disabled again after an interrupted on
+        let frames = run(Cmd::On);
+        assert_eq!(frames.len(), 4);
+        assert_ne!(frames[3], frames[1]);
+
+        // an interrupted off is not followed by another off
+        assert_eq!(run(Cmd::Off).len(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tty_validator() {
+        assert!(build_app()
+            .try_get_matches_from([APPNAME, "--tty", "COM3", "on"])
+            .is_ok());
+        assert!(build_app()
+            .try_get_matches_from([APPNAME, "--tty", "null", "on"])
+            .is_ok());
+        assert!(build_app()
+            .try_get_matches_from([APPNAME, "--tty", "/dev/NOT_FOUND", "on"])
+            .is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_flags_without_subcommand() {
+        let matches = build_app()
+            .try_get_matches_from([APPNAME, "--tty", "/dev/null"])
+            .unwrap();
+        assert!(matches.subcommand_name().is_none());
+
+        let err = commands::parse(&matches).err().unwrap();
+        assert_eq!(format!("{}", err), "no subcommand given");
+        assert!(matches!(
+            RelayError::find(&err),
+            Some(RelayError::InvalidArgument(_))
+        ));
+        assert_eq!(exit_code(&err), 4);
+
+        // a subcommand missing from the table is an error as well, not a panic
+        let matches = Command::new(APPNAME)
+            .subcommand(Command::new("bogus"))
+            .get_matches_from([APPNAME, "bogus"]);
+        let err = commands::parse(&matches).err().unwrap();
+        assert_eq!(format!("{}", err), "unknown command bogus");
+        assert_eq!(exit_code(&err), 4);
+    }
+
+    #[test]
+    fn test_device_entry() {
+        let device = DeviceInfo {
+            port: "/dev/ttyUSB0".to_string(),
+            vid: 0x1a86,
+            pid: 0x7523,
+            serial: None,
+            manufacturer: Some("QinHeng".to_string()),
+            product: None,
+        };
+
+        let entry = || DeviceEntry::new(&device, &Matcher(None, None));
+        let json = serde_json::to_string(&entry()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"port":"/dev/ttyUSB0","vid":"1a86","pid":"7523","serial":null,"manufacturer":"QinHeng","product":null,"compatible":true,"matching":true}"#
+        );
+
+        // same fields in the same order
+        assert_eq!(
+            yaml::to_string(&entry()).unwrap(),
+            "port: \"/dev/ttyUSB0\"\nvid: \"1a86\"\npid: \"7523\"\nserial: null\nmanufacturer: QinHeng\nproduct: null\ncompatible: true\nmatching: true\n"
+        );
+
+        // a configured FTDI bridge is matched instead of the CH340 ids
+        let ftdi = DeviceEntry::new(&device, &Matcher(Some(0x0403), Some(0x6001)));
+        assert!(ftdi.compatible && !ftdi.matching);
+    }
+
+    #[test]
+    fn test_list_format() {
+        let matches = build_app().get_matches_from([APPNAME, "list", "--format", "json"]);
+        assert_eq!(Format::of(&matches), Some(Format::Json));
+
+        let matches = build_app().get_matches_from([APPNAME, "list", "--format", "yaml"]);
+        assert_eq!(Format::of(&matches), Some(Format::Yaml));
+
+        let matches = build_app().get_matches_from([APPNAME, "list"]);
+        assert_eq!(Format::of(&matches), None);
+        let matches = build_app().get_matches_from([APPNAME, "list", "--format", "table"]);
+        assert_eq!(Format::of(&matches), None);
+
+        // the flag takes the same options as the command
+        let matches = build_app().get_matches_from([APPNAME, "--list", "--format", "json"]);
+        assert!(matches.is_present("list"));
+        assert_eq!(Format::of(&matches), Some(Format::Json));
+        assert!(build_app()
+            .try_get_matches_from([APPNAME, "--list", "--print-port"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_device_table() {
+        let device = |port: &str, vid, serial: Option<&str>| DeviceInfo {
+            port: port.to_string(),
+            vid,
+            pid: 0x7523,
+            serial: serial.map(str::to_string),
+            manufacturer: None,
+            product: Some("USB Serial".to_string()),
+        };
+        let devices = [
+            device("/dev/ttyUSB0", 0x1a86, None),
+            device("/dev/ttyUSB10", 0x0403, Some("A50285BI")),
+        ];
+
+        assert_eq!(
+            device_table(&devices, &Matcher(None, None)),
+            "  PORT          VID:PID   SERIAL   MANUFACTURER PRODUCT\n\
+             * /dev/ttyUSB0  1a86:7523 -        -            USB Serial\n  \
+             /dev/ttyUSB10 0403:7523 A50285BI -            USB Serial\n"
+        );
+        assert!(device_table(&devices, &Matcher(Some(0x0403), None)).contains("\n* /dev/ttyUSB10"));
+        assert!(!device_table(&devices, &Matcher(None, Some(0x6001))).contains('*'));
+        assert_eq!(table(&[]), "");
+    }
+
+    #[test]
+    fn test_generator() {
+        for shell in ["bash", "elvish", "fish", "powershell", "zsh"] {
+            let matches = build_app().get_matches_from([APPNAME, "--generate", shell]);
+            assert!(matches.value_of_t::<Shell>("generator").is_ok());
+        }
+
+        let result = build_app().try_get_matches_from([APPNAME, "--generate", "tcsh"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_generator_list() {
+        // every listed shell is accepted by --generate
+        let shells = shell_names();
+        assert_eq!(
+            shells,
+            [
+                "bash",
+                "elvish",
+                "fish",
+                "powershell",
+                "zsh",
+                "nushell",
+                "carapace"
+            ]
+        );
+        for shell in shells {
+            let matches = build_app().get_matches_from([APPNAME, "--generate", shell]);
+            assert!(Target::parse(matches.value_of("generator").unwrap()).is_some());
+        }
+        let matches = build_app().get_matches_from([APPNAME, "--generate", "list"]);
+        assert_eq!(matches.value_of("generator"), Some(LIST_SHELLS));
+    }
+
+    #[test]
+    fn test_log_spec() {
+        let matches = build_app().get_matches_from([APPNAME, "on"]);
+        assert_eq!(log_spec(&matches), None);
+
+        let matches = build_app().get_matches_from([APPNAME, "-q", "on"]);
+        assert_eq!(log_spec(&matches), Some("error"));
+
+        let matches = build_app().get_matches_from([APPNAME, "-v", "on"]);
+        assert_eq!(log_spec(&matches), Some("debug"));
+
+        let matches = build_app().get_matches_from([APPNAME, "-vv", "on"]);
+        assert_eq!(log_spec(&matches), Some("trace"));
+
+        let result = build_app().try_get_matches_from([APPNAME, "-q", "-v", "on"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_run_mock() {
+        let args = [
+            APPNAME,
+            "--mock",
+            "--format",
+            "json",
+            "--no-cache",
+            "timed_start",
+            "5",
+        ];
+        assert!(run(args.map(OsString::from)).is_ok());
+    }
+
+    #[test]
+    fn test_simulate_latency() {
+        let app = || build_app();
+        assert!(app()
+            .try_get_matches_from([APPNAME, "--simulate-latency", "20", "on"])
+            .is_err());
+
+        let matches = app()
+            .try_get_matches_from([APPNAME, "--mock", "--simulate-latency", "20", "on"])
+            .unwrap();
+        let started = std::time::Instant::now();
+        let mut port = open_port(&matches, &Config::default()).unwrap();
+        port.on().unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_print_port() {
+        let matches = build_app()
+            .try_get_matches_from([APPNAME, "--print-port", "--tty", "/dev/null"])
+            .unwrap();
+        let options = open_options(&matches, &Config::default(), None).unwrap();
+        assert_eq!(options.resolve().unwrap(), "/dev/null");
+
+        let matches = build_app()
+            .try_get_matches_from([APPNAME, "--print-port", "--remote", "relay:2000"])
+            .unwrap();
+        let options = open_options(&matches, &Config::default(), None).unwrap();
+        assert_eq!(options.resolve().unwrap(), "relay:2000");
+
+        assert!(build_app()
+            .try_get_matches_from([APPNAME, "--print-port", "--devices", "A,B"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_wait_for_device() {
+        let matches = build_app()
+            .try_get_matches_from([
+                APPNAME,
+                "--wait-for-device",
+                "--device-timeout",
+                "0",
+                "--poll-interval",
+                "1",
+                "--vid",
+                "dead",
+                "on",
+            ])
+            .unwrap();
+        let err = open_options(&matches, &Config::default(), None)
+            .unwrap()
+            .resolve()
+            .unwrap_err();
+        assert_eq!(exit_code(&err.into()), 2);
+
+        assert!(build_app()
+            .try_get_matches_from([APPNAME, "--device-timeout", "5", "on"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_capture() {
+        let path = env::temp_dir().join(format!("tty_relay_capture_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let matches = build_app()
+            .try_get_matches_from([APPNAME, "--mock", "--capture", path.to_str().unwrap(), "on"])
+            .unwrap();
+
+        // every session appends
+        for _ in 0..2 {
+            let mut port = open_port(&matches, &Config::default()).unwrap();
+            port.on().unwrap();
+        }
+
+        let capture = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(capture.lines().count(), 4);
+        assert!(capture.lines().all(|line| line.contains(" mock F0 A0 ")));
+    }
+
+    #[test]
+    fn test_state_file_simulated() {
+        let path = env::temp_dir().join(format!("tty_relay_simulated_{}", std::process::id()));
+        let ports = [
+            Port::open_mock(),
+            OpenOptions::new().dry_run(true).open().unwrap(),
+        ];
+        for mut port in ports {
+            let result = execute(
+                &mut port,
+                Cmd::On,
+                1,
+                Duration::ZERO,
+                Some(&path),
+                false,
+                None,
+            );
+            assert_eq!(result.unwrap(), None);
+            assert!(!path.exists());
+        }
+    }
+
+    #[test]
+    fn test_power_on_safe() {
+        let steps = script::parse("on; status").unwrap();
+        let off = [[0xF0, 0xA0, 0x0C, 0x54], [0xF0, 0xA0, 0x00, 0x53]];
+
+        let recorder = Recorder::new();
+        let mut port = Port::recording(&recorder);
+        port.set_wiring(Wiring::NormallyOpen);
+        let result = execute(
+            &mut port,
+            Cmd::Run(steps.clone()),
+            1,
+            Duration::ZERO,
+            None,
+            true,
+            None,
+        );
+        assert!(result.is_err());
+        assert!(recorder.frames().ends_with(&off));
+
+        let recorder = Recorder::new();
+        let mut port = Port::recording(&recorder);
+        port.set_wiring(Wiring::NormallyOpen);
+        let result = execute(
+            &mut port,
+            Cmd::Run(steps),
+            1,
+            Duration::ZERO,
+            None,
+            false,
+            None,
+        );
+        assert!(result.is_err());
+        assert!(!recorder.frames().ends_with(&off));
+    }
+
+    #[test]
+    fn test_profile() {
+        let config = Config {
+            normally_closed: Some(false),
+            ..Config::default()
+        };
+        let configured = |args: &[&str]| {
+            let matches = build_app()
+                .try_get_matches_from([APPNAME].iter().chain(args).chain(&["on"]))
+                .unwrap();
+            let mut port = Port::open_mock();
+            configure_port(&mut port, &matches, &config).map(|_| port)
+        };
+
+        let mut port = configured(&["--profile", "lctech-4ch"]).unwrap();
+        assert!(port.set_channel(4).is_ok());
+
+        for args in [["--channels", "9"], ["--channel", "9"]] {
+            let result =
+                build_app().try_get_matches_from([APPNAME].iter().chain(&args).chain(&["on"]));
+            assert!(result.is_err());
+        }
+
+        // explicit flags override the profile
+        let mut port = configured(&["--profile", "lctech-4ch", "--channels", "2"]).unwrap();
+        assert!(port.set_channel(4).is_err());
+
+        assert!(configured(&["--profile", "nope"]).is_err());
+    }
+
+    #[test]
+    fn test_min_interval() {
+        let dir = env::temp_dir().join(format!("tty_relay_min_interval_{}", std::process::id()));
+        let min_interval = MinInterval {
+            file: dir.join("last_change"),
+            interval: Duration::from_secs(3600),
+            wait: false,
+        };
+        let run = |cmd| {
+            let mut port = Port::open_mock();
+            execute(
+                &mut port,
+                cmd,
+                1,
+                Duration::ZERO,
+                None,
+                false,
+                Some(&min_interval),
+            )
+        };
+
+        assert!(run(Cmd::On).is_ok());
+        let err = run(Cmd::Off).unwrap_err();
+        assert_eq!(exit_code(&err), 4);
+        // only state changes are limited
+        assert!(run(Cmd::Jog).is_ok());
+        assert!(run(Cmd::TimedOn(5)).is_ok());
+
+        // every repetition is a state change
+        let min_interval = MinInterval {
+            file: dir.join("repeated"),
+            ..min_interval
+        };
+        let recorder = Recorder::new();
+        let mut port = Port::recording(&recorder);
+        let err = execute(
+            &mut port,
+            Cmd::On,
+            3,
+            Duration::ZERO,
+            None,
+            false,
+            Some(&min_interval),
+        )
+        .unwrap_err();
+        assert_eq!(exit_code(&err), 4);
+        assert_eq!(recorder.frames().len(), 2, "only the first repetition ran");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_off_after() {
+        let recorder = Recorder::new();
+        let mut port = Port::recording(&recorder);
+        port.set_wiring(Wiring::NormallyOpen);
+        let cmd = Cmd::OffAfter {
+            action: Box::new(Cmd::On),
+            secs: 60,
+        };
+        dispatch(&mut port, cmd).unwrap();
+        assert_eq!(
+            recorder.frames(),
+            [
+                [0xF0, 0xA0, 0x0C, 0x54],
+                [0xF0, 0xA0, 0x01, 0x53],
+                [0xF0, 0x00, 0x3C, 0x57]
+            ]
+        );
+
+        let matches = build_app().get_matches_from([APPNAME, "--off-after", "5", "jog"]);
+        assert_eq!(off_after(&matches, &Cmd::Jog).unwrap(), Some(5));
+        assert!(off_after(&matches, &Cmd::Off).is_err());
+        assert!(off_after(&matches, &Cmd::TimedOff(5)).is_err());
+        let matches = build_app().get_matches_from([APPNAME, "off"]);
+        assert_eq!(off_after(&matches, &Cmd::Off).unwrap(), None);
+
+        let args = [APPNAME, "--off-after", "70000", "on"];
+        assert!(build_app().try_get_matches_from(args).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_devices() {
+        assert!(is_tty_name("/dev/ttyUSB0"));
+        assert!(is_tty_name("null"));
+        assert!(is_tty_name("COM3"));
+        assert!(!is_tty_name("A50285BI"));
+
+        let matches = build_app().get_matches_from([
+            APPNAME,
+            "--dry-run",
+            "--devices",
+            "/dev/relay0,/dev/relay1",
+            "--stagger",
+            "1",
+            "on",
+        ]);
+        let devices: Vec<&str> = matches.values_of("devices").unwrap().collect();
+        assert_eq!(devices, ["/dev/relay0", "/dev/relay1"]);
+
+        let ports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let opened = Arc::clone(&ports);
+        let runner: Runner = Arc::new(move |port, _| {
+            opened.lock().unwrap().push(port.path().to_string());
+            match port.path() {
+                "/dev/relay0" => bail!("unplugged"),
+                _ => Ok(None),
+            }
+        });
+        let result = run_devices(
+            &matches,
+            &Config::default(),
+            Cmd::On,
+            devices,
+            Duration::from_millis(1),
+            None,
+            &runner,
+        );
+        // the failure of the first device does not stop the second
+        assert_eq!(*ports.lock().unwrap(), ["/dev/relay0", "/dev/relay1"]);
+        assert_eq!(result.err().unwrap().to_string(), "1 of 2 devices failed");
+
+        let result = build_app().try_get_matches_from([APPNAME, "--stagger", "5", "on"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_show() {
+        let matches = build_app().get_matches_from([APPNAME, "show", "timed_start", "5"]);
+        assert!(matches!(
+            commands::parse(&matches),
+            Ok(Cmd::Show(action)) if matches!(*action, Cmd::TimedOn(5))
+        ));
+
+        let matches = build_app().get_matches_from([APPNAME, "show", "show", "on"]);
+        assert!(commands::parse(&matches).is_err());
+
+        let matches = build_app().get_matches_from([APPNAME, "show", "dance"]);
+        assert!(commands::parse(&matches).is_err());
+
+        assert_eq!(hex(&[0xF0, 0xA0, 0x0C, 0x54]), "F0 A0 0C 54");
+    }
+
+    #[test]
+    fn test_timer_limit() {
+        let matches = build_app().get_matches_from([APPNAME, "on"]);
+        assert_eq!(
+            matches.value_of_t::<u16>("max timer").unwrap(),
+            MAX_TIMER_SECONDS
+        );
+
+        let timed = |args: &[&str]| {
+            let matches = build_app().get_matches_from(args);
+            let recorder = Recorder::new();
+            let mut port = Port::recording(&recorder);
+            configure_port(&mut port, &matches, &Config::default()).unwrap();
+            dispatch(&mut port, commands::parse(&matches).unwrap()).map(|_| recorder.frames())
+        };
+
+        assert!(timed(&[APPNAME, "--max-timer", "10", "timed_start", "11"]).is_err());
+        let frames = timed(&[APPNAME, "--max-timer", "10", "--clamp", "timed_start", "11"]);
+        assert_eq!(frames.unwrap()[2], [0xF0, 0x00, 0x0A, 0x57]);
+
+        let frames = timed(&[APPNAME, "timed_start", "5"]);
+        assert_eq!(frames.unwrap()[2], [0xF0, 0x00, 0x05, 0x57]);
+        let frames = timed(&[APPNAME, "--timer-unit", "deciseconds", "timed_start", "5"]);
+        assert_eq!(frames.unwrap()[2], [0xF0, 0x00, 0x32, 0x57]);
+    }
+
+    #[test]
+    fn test_confirm() {
+        assert!(!should_prompt(Confirm::Never, true));
+        assert!(should_prompt(Confirm::Auto, true));
+        assert!(!should_prompt(Confirm::Auto, false));
+        assert!(should_prompt(Confirm::Always, false));
+
+        let mut output = Vec::new();
+        assert!(ask(&b"y\n"[..], &mut output, "Really?").unwrap());
+        assert_eq!(output, b"Really? [y/N] ");
+        assert!(ask(&b"YES\n"[..], io::sink(), "Really?").unwrap());
+        assert!(!ask(&b"\n"[..], io::sink(), "Really?").unwrap());
+        assert!(!ask(&b""[..], io::sink(), "Really?").unwrap());
+
+        let matches = build_app().get_matches_from([APPNAME, "--confirm", "off"]);
+        assert_eq!(matches.value_of("confirm"), Some("auto"));
+        let matches = build_app().get_matches_from([APPNAME, "--confirm=always", "off"]);
+        assert_eq!(matches.value_of("confirm"), Some("always"));
+
+        let disconnecting = |args: &[&str]| {
+            let matches = build_app().get_matches_from([&[APPNAME], args].concat());
+            disconnects(&commands::parse(&matches).unwrap())
+        };
+        assert!(disconnecting(&["off"]));
+        assert!(disconnecting(&[
+            "--channels",
+            "8",
+            "off",
+            "--channels",
+            "1,2"
+        ]));
+        assert!(disconnecting(&["set", "off"]));
+        assert!(!disconnecting(&["set", "on"]));
+        assert!(disconnecting(&["reset"]));
+        assert!(disconnecting(&["timed_stop", "5"]));
+        assert!(!disconnecting(&["on"]));
+        assert!(!disconnecting(&[
+            "--channels",
+            "8",
+            "on",
+            "--channels",
+            "1,2"
+        ]));
+        assert!(!disconnecting(&["jog"]));
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let err = anyhow::Error::new(RelayError::DeviceNotFound("none".to_string()));
+        assert_eq!(exit_code(&err), 2);
+        let err = anyhow::Error::new(RelayError::AmbiguousDevice("two".to_string()));
+        assert_eq!(exit_code(&err), 2);
+
+        let err = anyhow::Error::new(RelayError::Interrupted("stop".to_string())).context("on");
+        assert!(is_interrupted(&err));
+        assert_eq!(exit_code(&err), 130);
+
+        let err = anyhow::Error::new(RelayError::Io("timeout".to_string())).context("status");
+        assert_eq!(exit_code(&err), 3);
+
+        let err = anyhow::Error::new(RelayError::Timeout("no response".to_string()));
+        assert_eq!(exit_code(&err), 3);
+
+        let err = anyhow::Error::new(io::Error::from(io::ErrorKind::BrokenPipe));
+        assert_eq!(exit_code(&err), 3);
+
+        let err = anyhow::Error::new(RelayError::Busy("locked".to_string()));
+        assert_eq!(exit_code(&err), 5);
+
+        let matches = build_app().get_matches_from([APPNAME, "--dry-run"]);
+        let err = commands::parse(&matches).err().unwrap();
+        assert_eq!(exit_code(&err), 4);
+
+        assert_eq!(exit_code(&anyhow::anyhow!("other")), 1);
+    }
+
+    /// adapter which never finishes a write
+    struct Wedged;
+
+    impl io::Read for Wedged {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Ok(0)
+        }
+    }
+
+    impl Write for Wedged {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            thread::sleep(Duration::from_secs(3600));
+            Ok(0)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_command_timeout() {
+        let runner: Runner = Arc::new(dispatch);
+
+        let port = Port::from_stream(Box::new(Wedged), "wedged");
+        let (port, result) =
+            run_with_timeout(port, Cmd::On, Some(Duration::from_millis(50)), &runner);
+        assert!(port.is_none());
+        let err = result.unwrap_err();
+        assert!(matches!(
+            RelayError::find(&err),
+            Some(RelayError::Timeout(_))
+        ));
+        assert_eq!(exit_code(&err), 3);
+
+        let (port, result) = run_with_timeout(
+            Port::open_mock(),
+            Cmd::On,
+            Some(Duration::from_secs(5)),
+            &runner,
+        );
+        assert!(result.is_ok());
+        assert_eq!(port.unwrap().recorder().unwrap().frames().len(), 2);
+
+        let (port, result) = run_with_timeout(Port::open_mock(), Cmd::Off, None, &runner);
+        assert!(result.is_ok() && port.is_some());
+
+        let matches = build_app().get_matches_from([APPNAME, "--command-timeout", "100", "repl"]);
+        assert!(command_timeout(&matches, &Cmd::Repl).is_err());
+        let matches = build_app().get_matches_from([APPNAME, "--command-timeout", "100", "on"]);
+        assert_eq!(
+            command_timeout(&matches, &Cmd::On).unwrap(),
+            Some(Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities = Capabilities::new();
+        assert_eq!(capabilities.version, env!("CARGO_PKG_VERSION"));
+        let has = |name: &str| capabilities.commands.iter().any(|command| command == name);
+        assert!(has("status"));
+        assert!(has("capabilities"));
+        assert_eq!(has("serve"), cfg!(feature = "http"));
+        assert_eq!(capabilities.protocols, ["v1", "v2"]);
+        assert_eq!(capabilities.formats, ["human", "table", "json", "yaml"]);
+        assert_eq!(
+            capabilities.features.contains(&"mqtt"),
+            cfg!(feature = "mqtt")
+        );
+
+        let json = serde_json::to_value(&capabilities).unwrap();
+        assert!(json["commands"].is_array());
+    }
+
+    #[test]
+    fn test_parse_frame() {
+        let frame = [0xF0, 0xA0, 0x0C, 0x54];
+        assert_eq!(parse_frame("F0 A0 0C 54"), Ok(frame));
+        assert_eq!(parse_frame("F0A00C54"), Ok(frame));
+        assert_eq!(parse_frame("f0a0 0c54"), Ok(frame));
+        assert_eq!(parse_frame("0xF0,0xA0,0x0C,0x54"), Ok(frame));
+        assert!(parse_frame("F0 A0 0C").is_err());
+        assert!(parse_frame("F0 A0 0C 54 00").is_err());
+        assert!(parse_frame("F0 A0 C 054").is_err());
+        assert!(parse_frame("F0 A0 0C ZZ").is_err());
+        assert!(parse_frame("F0 A0 0C é").is_err());
+        assert_eq!(parse_frame("0XF0 0Xa0 0x0C 0X54"), Ok(frame));
+        assert!(parse_frame("a€a€").is_err());
+        assert!(parse_frame("F0A0€€").is_err());
+    }
+
+    #[test]
+    fn test_baud_autodetect() {
+        let result = build_app().try_get_matches_from([APPNAME, "--baud-autodetect", "probe"]);
+        assert!(result.is_ok());
+        let result = build_app().try_get_matches_from([
+            APPNAME,
+            "--baud-autodetect",
+            "--baud",
+            "9600",
+            "on",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_link_timing() {
+        let ms = Duration::from_millis;
+        let timing = |args: &[&str], profile: &Profile, tty| {
+            let matches = build_app().get_matches_from([&[APPNAME], args, &["on"]].concat());
+            link_timing(&matches, profile, tty).unwrap()
+        };
+        let none = Profile::default();
+        let hc05 = Config::default().profile(Some("hc-05")).unwrap();
+
+        assert_eq!(timing(&[], &none, Some("/dev/ttyUSB0")), (ms(10), ms(50)));
+        assert_eq!(timing(&[], &none, Some("/dev/rfcomm0")), (ms(100), ms(150)));
+        assert_eq!(timing(&[], &none, Some("rfcomm1")), (ms(100), ms(150)));
+        assert_eq!(timing(&[], &hc05, None), (ms(100), ms(150)));
+
+        // explicit flags win over the profile and the rfcomm defaults
+        let args = ["--timeout", "30", "--frame-delay", "80"];
+        assert_eq!(timing(&args, &hc05, Some("/dev/rfcomm0")), (ms(30), ms(80)));
+        assert_eq!(
+            timing(&args[..2], &none, Some("/dev/rfcomm0")),
+            (ms(30), ms(150))
+        );
+
+        assert!(build_app()
+            .try_get_matches_from([APPNAME, "--tty", "/dev/rfcomm9", "on"])
+            .is_ok());
+        for tty in [
+            "rfcomm",
+            "/dev/rfcommX",
+            "rfcomm0/../../etc/passwd",
+            "/tmp/rfcomm0",
+        ] {
+            assert!(!is_rfcomm(tty), "{}", tty);
+            assert!(build_app()
+                .try_get_matches_from([APPNAME, "--tty", tty, "on"])
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_no_sleep() {
+        let frame_delay = |matches| link_timing(matches, &Profile::default(), None).unwrap().1;
+        let matches = build_app().get_matches_from([APPNAME, "--no-sleep", "on"]);
+        assert_eq!(frame_delay(&matches), Duration::ZERO);
+
+        let matches = build_app().get_matches_from([APPNAME, "on"]);
+        assert_eq!(frame_delay(&matches), Duration::from_millis(50));
+
+        let result =
+            build_app().try_get_matches_from([APPNAME, "--no-sleep", "--frame-delay", "10", "on"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_timer() {
+        assert_eq!(parse_timer("5400"), Ok(5400));
+        assert_eq!(parse_timer("90m"), Ok(5400));
+        assert_eq!(parse_timer("1h"), Ok(3600));
+        assert_eq!(parse_timer("1h30m"), Ok(5400));
+        assert_eq!(parse_timer("45s"), Ok(45));
+        assert!(parse_timer("20h").unwrap_err().contains("exceeds"));
+        assert!(parse_timer("1.5h").unwrap_err().contains("fractional"));
+        assert!(parse_timer("70000").is_err());
+        assert!(parse_timer("90x").is_err());
+    }
+
+    #[test]
+    fn test_is_seconds() {
+        assert!(is_seconds("0").is_ok());
+        assert!(is_seconds("65535").is_ok());
+        assert_eq!(
+            is_seconds("65536"),
+            Err("seconds must be between 0 and 65535".to_string())
+        );
+        assert!(is_seconds("-5").is_err());
+    }
+
+    #[test]
+    fn test_is_baud() {
+        assert!(is_baud("9600").is_ok());
+        assert!(is_baud("115200").is_ok());
+        assert!(is_baud("0").is_err());
+        assert!(is_baud("-9600").is_err());
+    }
+
+    #[test]
+    fn test_parse_usb_id() {
+        assert_eq!(parse_usb_id("1a86"), Ok(0x1a86));
+        assert_eq!(parse_usb_id("0x7523"), Ok(0x7523));
+        assert_eq!(parse_usb_id("0X10C4"), Ok(0x10c4));
+        assert!(parse_usb_id("0x10000").is_err());
+        assert!(parse_usb_id("xyz").is_err());
+
+        assert_eq!(parse_prefix("0xA0"), Ok(0xA0));
+        assert_eq!(parse_prefix("f0"), Ok(0xF0));
+        assert!(parse_prefix("0x100").is_err());
+    }
+
+    #[test]
+    fn test_usb_id_args() {
+        let matches = build_app()
+            .try_get_matches_from([APPNAME, "--vid", "0x0403", "--pid", "6001", "--print-port"])
+            .unwrap();
+        assert_eq!(parse_usb_id(matches.value_of("vid").unwrap()), Ok(0x0403));
+
+        let err = build_app()
+            .try_get_matches_from([APPNAME, "--vid", "0x10000", "on"])
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("0x10000 is not a 16-bit hex number"));
     }
 }
@@ -0,0 +1,135 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// relay control via MQTT messages
+use crate::daemon::SharedPort;
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS, RecvTimeoutError};
+use std::process;
+use std::thread;
+use std::time::Duration;
+use tty_relay::Port;
+
+const DEFAULT_PORT: u16 = 1883;
+
+fn parse_broker(url: &str) -> Result<(String, u16)> {
+    let address = url
+        .strip_prefix("mqtt://")
+        .or_else(|| url.strip_prefix("tcp://"))
+        .unwrap_or(url);
+
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .with_context(|| format!("invalid broker port in {}", url))?,
+        ),
+        None => (address, DEFAULT_PORT),
+    };
+
+    if host.is_empty() {
+        bail!("invalid broker url {}", url);
+    }
+
+    Ok((host.to_string(), port))
+}
+
+/// subscribe to `topic` and drive the relay until the process is stopped
+///
+/// the port stays open for the lifetime of the daemon, it is reopened via
+/// `open` when a command fails; an idle port gets a keepalive frame, see
+/// [`LazyPort::keepalive`](crate::daemon::LazyPort::keepalive)
+pub fn run<F>(broker: &str, topic: &str, state_topic: &str, port: SharedPort<F>) -> Result<()>
+where
+    F: Fn() -> Result<Port>,
+{
+    let (host, broker_port) = parse_broker(broker)?;
+    let client_id = format!("{}-{}", crate::APPNAME, process::id());
+
+    let mut options = MqttOptions::new(client_id, host, broker_port);
+    options.set_keep_alive(Duration::from_secs(30));
+
+    let (client, mut connection) = Client::new(options, 16);
+    port.run(|_| Ok(()))?;
+
+    loop {
+        port.tick();
+        let notification = match port.idle_timeout() {
+            Some(timeout) => match connection.recv_timeout(timeout) {
+                Ok(notification) => notification,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match connection.recv() {
+                Ok(notification) => notification,
+                Err(_) => break,
+            },
+        };
+
+        let publish = match notification {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("connected to {}, subscribe to {}", broker, topic);
+                client.try_subscribe(topic, QoS::AtLeastOnce)?;
+                continue;
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => publish,
+            Ok(_) => continue,
+            Err(e) => {
+                warn!("mqtt connection error: {}", e);
+                thread::sleep(Duration::from_secs(1));
+                continue;
+            }
+        };
+
+        let payload = String::from_utf8_lossy(&publish.payload);
+        let payload = payload.trim();
+        debug!("received {:?} on {}", payload, publish.topic);
+
+        match port.command(payload) {
+            Ok(Some(state)) => {
+                client.try_publish(state_topic, QoS::AtLeastOnce, true, state.to_string())?
+            }
+            Ok(None) => {}
+            Err(e) => warn!("command {:?} failed: {:#}", payload, e),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_broker() {
+        assert_eq!(
+            parse_broker("mqtt://broker.local:1884").unwrap(),
+            ("broker.local".to_string(), 1884)
+        );
+        assert_eq!(
+            parse_broker("tcp://10.0.0.1").unwrap(),
+            ("10.0.0.1".to_string(), 1883)
+        );
+        assert_eq!(
+            parse_broker("localhost").unwrap(),
+            ("localhost".to_string(), 1883)
+        );
+        assert!(parse_broker("mqtt://broker:port").is_err());
+        assert!(parse_broker("mqtt://").is_err());
+    }
+}
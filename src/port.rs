@@ -15,328 +15,3259 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 /// power management via tty relay
-use anyhow::{Context, Result};
-use log::debug;
-use serialport::SerialPortType::UsbPort;
-use std::io::{Read, Write};
+#[cfg(feature = "async")]
+use crate::async_port::AsyncPort;
+use crate::cache;
+use crate::clock::{Clock, SystemClock};
+use crate::device::{available_devices, is_com_port, sysfs_device, DeviceInfo};
+use crate::error::RelayError;
+use crate::frame::{Action, Frame, Framer};
+use crate::lock;
+use crate::protocol::Protocol;
+use crate::recorder::Recorder;
+use anyhow::{bail, Context, Result};
+use chrono::{Local, SecondsFormat};
+use log::{debug, info, warn};
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Write};
+use std::net::TcpStream;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-trait ReadWrite: Read + Write {}
-impl<T> ReadWrite for T where T: Read + Write {}
+/// transport of a [`Port`], implemented for every `Read + Write + Send`
+///
+/// `Send` lets a port be moved to, or shared behind a mutex with, another thread
+pub trait ReadWrite: Read + Write + Send {}
+impl<T> ReadWrite for T where T: Read + Write + Send {}
 
 /// tty port wrapper
 pub struct Port {
     port: Box<dyn ReadWrite>,
     path: String,
+    protocol: Protocol,
+    channel: u8,
+    dry_run: bool,
+    verify: bool,
+    /// discard stale input before frames which expect a response
+    drain: bool,
+    wiring: Wiring,
+    interrupt: Option<Arc<AtomicBool>>,
+    timeout: Duration,
+    frame_delay: Duration,
+    recorder: Option<Recorder>,
+    clamp: bool,
+    clock: Box<dyn Clock>,
+    capture: Option<Box<dyn Write + Send>>,
+    write_retries: u32,
+    /// the last mode frame sent was jog mode
+    jog: bool,
+    invert: bool,
+    /// held while the port is open, see [`OpenOptions::lock_wait`]
+    lock: Option<File>,
+    /// a network connection, read returns no bytes only once it is closed
+    remote: bool,
 }
 
-enum Action {
-    Connect,
-    Disconnect,
+/// relay state reported by the board
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayState {
+    /// power is enabled
+    On,
+    /// power is disabled
+    Off,
+}
+
+impl fmt::Display for RelayState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelayState::On => write!(f, "on"),
+            RelayState::Off => write!(f, "off"),
+        }
+    }
+}
+
+/// firmware identity reported by the board, fields are `None` if unknown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Identity {
+    /// firmware revision byte
+    pub firmware: Option<u8>,
+    /// number of relay channels
+    pub channels: Option<u8>,
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let known = |value: Option<u8>| value.map_or("unknown".to_string(), |v| v.to_string());
+        write!(
+            f,
+            "firmware: {}\nchannels: {}",
+            known(self.firmware),
+            known(self.channels)
+        )
+    }
+}
+
+/// relay contact the load is wired to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wiring {
+    /// NO (normally open) contact
+    NormallyOpen,
+    /// NC (normally closed) contact
+    NormallyClosed,
+}
+
+impl Wiring {
+    fn other(self) -> Wiring {
+        match self {
+            Wiring::NormallyOpen => Wiring::NormallyClosed,
+            Wiring::NormallyClosed => Wiring::NormallyOpen,
+        }
+    }
+}
+
+impl Default for Wiring {
+    /// normally open, unless built with the `nc-connected` feature
+    fn default() -> Self {
+        if cfg!(feature = "nc-connected") {
+            Wiring::NormallyClosed
+        } else {
+            Wiring::NormallyOpen
+        }
+    }
+}
+
+fn usb_id(vid: Option<u16>, pid: Option<u16>) -> String {
+    let id = |id: Option<u16>| id.map_or("*".to_string(), |id| format!("{:04x}", id));
+    format!("{}:{}", id(vid), id(pid))
 }
 
 impl Port {
-    fn find_tty(vid: u16, pid: u16) -> Option<String> {
-        let ports = serialport::available_ports().ok()?;
-        for port in ports {
-            if let UsbPort(usb_port) = port.port_type {
-                if usb_port.vid == vid && usb_port.pid == pid {
-                    return Some(port.port_name);
+    /// port on top of an already open transport, `label` is used as its path in logs
+    pub fn from_stream(stream: Box<dyn ReadWrite>, label: impl Into<String>) -> Port {
+        Port {
+            port: stream,
+            path: label.into(),
+            protocol: Protocol::default(),
+            channel: 1,
+            dry_run: false,
+            verify: false,
+            drain: false,
+            wiring: Wiring::default(),
+            interrupt: None,
+            timeout: Self::TIMEOUT,
+            frame_delay: Self::FRAME_DELAY,
+            recorder: None,
+            clamp: false,
+            clock: Box::new(SystemClock::new()),
+            capture: None,
+            write_retries: 0,
+            jog: false,
+            invert: false,
+            lock: None,
+            remote: false,
+        }
+    }
+
+    fn write(&mut self, frame: [u8; 4]) -> Result<()> {
+        let command = self.protocol.seal(frame);
+        self.write_command(frame, command)
+    }
+
+    /// write `command`, the sealed `frame`
+    fn write_command(&mut self, frame: [u8; 4], command: [u8; 4]) -> Result<()> {
+        if self.interrupted() {
+            bail!(RelayError::Interrupted(format!(
+                "{}: interrupted before writing {:02X?}",
+                self.path, command
+            )));
+        }
+
+        if self.dry_run {
+            info!("{}: would write {:02X?}", self.path, command);
+            return Ok(());
+        }
+
+        if self.recorder.is_some() {
+            info!("{}: write {:02X?}", self.path, command);
+        } else {
+            debug!("{}: write {:02X?}", self.path, command);
+        }
+        if self.drain && (self.verify || self.framer().query().is_ok_and(|query| query == frame)) {
+            self.drain_input()?;
+        }
+        self.send(&command)?;
+        self.capture(&command);
+        self.track_mode(frame);
+
+        if self.verify {
+            match self.read() {
+                Ok(ack) if ack == command => {}
+                Ok(ack) => bail!(RelayError::NoAcknowledgement(format!(
+                    "{}: no acknowledgement from relay for {:02X?}, got {:02X?}",
+                    self.path, command, ack
+                ))),
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!(
+                            "{}: no acknowledgement from relay for {:02X?}",
+                            self.path, command
+                        )
+                    })
+                }
+            }
+        }
+
+        // an interrupt stops the next frame
+        self.wait(self.frame_delay);
+        Ok(())
+    }
+
+    /// remember whether the board was left in jog or control mode
+    fn track_mode(&mut self, frame: Frame) {
+        if self.framer().jog_mode().is_ok_and(|jog| jog == frame) {
+            self.jog = true;
+        } else if self
+            .framer()
+            .control_mode()
+            .is_ok_and(|control| control == frame)
+        {
+            self.jog = false;
+        }
+    }
+
+    /// write the frame, sending the unwritten rest again after transient errors,
+    /// see [`Port::set_write_retries`]
+    ///
+    /// resending the bytes which already went out would double them on the wire
+    fn send(&mut self, frame: &[u8]) -> Result<()> {
+        let mut attempt = 0;
+        let mut written = 0;
+
+        while written < frame.len() {
+            let result = match self.port.write(&frame[written..]) {
+                Ok(0) => Err(io::Error::from(io::ErrorKind::WriteZero)),
+                result => result,
+            };
+
+            match result {
+                Ok(n) => written += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if attempt < self.write_retries => {
+                    attempt += 1;
+                    warn!(
+                        "{}: failed to write {:02X?}: {}, retry {}/{}",
+                        self.path,
+                        &frame[written..],
+                        e,
+                        attempt,
+                        self.write_retries
+                    );
+                    if !self.wait(self.frame_delay) {
+                        bail!(RelayError::Interrupted(format!(
+                            "{}: interrupted while retrying {:02X?}",
+                            self.path, frame
+                        )));
+                    }
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!(
+                            "{}: failed to write {:02X?} after {} attempt(s)",
+                            self.path,
+                            frame,
+                            attempt + 1
+                        )
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// append the sent frame to the capture, failures only warn to not block power control
+    fn capture(&mut self, frame: &[u8]) {
+        let Some(capture) = self.capture.as_mut() else {
+            return;
+        };
+
+        let hex: Vec<String> = frame.iter().map(|b| format!("{:02X}", b)).collect();
+        let line = format!(
+            "{} {} {}\n",
+            Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+            self.path,
+            hex.join(" ")
+        );
+        // a single write keeps the lines of several ports sharing the file whole
+        if let Err(e) = capture
+            .write_all(line.as_bytes())
+            .and_then(|_| capture.flush())
+        {
+            warn!("{}: failed to capture frame: {}", self.path, e);
+        }
+    }
+
+    /// read and discard until a read times out, so a stale answer isn't taken for the next one
+    fn drain_input(&mut self) -> Result<()> {
+        let mut buf = [0; 64];
+        let mut discarded = Vec::new();
+
+        while discarded.len() < Self::DRAIN_LIMIT {
+            match self.port.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => discarded.extend_from_slice(&buf[..n]),
+                Err(e) if is_timeout(&e) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e).with_context(|| format!("failed to drain {}", self.path)),
+            }
+        }
+
+        if discarded.len() >= Self::DRAIN_LIMIT {
+            warn!(
+                "{}: still receiving after discarding {} bytes",
+                self.path,
+                discarded.len()
+            );
+        } else if !discarded.is_empty() {
+            debug!("{}: discarded {:02X?}", self.path, discarded);
+        }
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<[u8; 4]> {
+        let mut response = [0; 4];
+        let mut received = 0;
+        let deadline = Instant::now() + self.timeout;
+
+        while received < response.len() {
+            if Instant::now() >= deadline {
+                bail!(RelayError::Timeout(format!(
+                    "{}: no response from relay (received {} of {} bytes)",
+                    self.path,
+                    received,
+                    response.len()
+                )));
+            }
+
+            match self.port.read(&mut response[received..]) {
+                Ok(0) if self.remote => {
+                    bail!(RelayError::Io(format!(
+                        "{}: connection closed (received {} of {} bytes)",
+                        self.path,
+                        received,
+                        response.len()
+                    )));
+                }
+                Ok(n) => received += n,
+                // a TcpStream with a read timeout reports WouldBlock on unix
+                Err(e) if is_timeout(&e) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e).with_context(|| format!("failed to read {}", self.path)),
+            }
+        }
+
+        debug!("{}: read {:02X?}", self.path, response);
+
+        if !self.protocol.is_valid(&response) {
+            bail!(RelayError::Io(format!(
+                "{}: checksum mismatch in response {:02X?}",
+                self.path, response
+            )));
+        }
+
+        Ok(response)
+    }
+
+    fn interrupted(&self) -> bool {
+        self.interrupt
+            .as_ref()
+            .is_some_and(|interrupt| interrupt.load(Ordering::SeqCst))
+    }
+
+    /// sleep for the duration, returns false if interrupted meanwhile
+    fn wait(&self, duration: Duration) -> bool {
+        let deadline = self.clock.now() + duration;
+
+        while !self.interrupted() {
+            let now = self.clock.now();
+            if now >= deadline {
+                return true;
+            }
+            self.clock.sleep((deadline - now).min(Self::TIMEOUT));
+        }
+
+        false
+    }
+
+    fn framer(&self) -> Framer<'_> {
+        Framer {
+            protocol: &self.protocol,
+            channel: self.channel,
+            wiring: self.switched_contact(),
+        }
+    }
+
+    /// the contact which `on` connects, the wiring unless inverted
+    fn switched_contact(&self) -> Wiring {
+        match self.invert {
+            true => self.wiring.other(),
+            false => self.wiring,
+        }
+    }
+
+    fn write_frames(&mut self, frames: &[Frame]) -> Result<()> {
+        for frame in frames {
+            self.write(*frame)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn control_mode(&mut self) -> Result<()> {
+        let control_mode = self.framer().control_mode()?;
+        self.write(control_mode)
+    }
+
+    #[cfg(test)]
+    fn jog_mode(&mut self) -> Result<()> {
+        let jog_mode = self.framer().jog_mode()?;
+        self.write(jog_mode)
+    }
+
+    #[cfg(test)]
+    fn send_timer(&mut self, timeout: u16) -> Result<()> {
+        let timer = self.framer().timer(timeout);
+        self.write(timer)
+    }
+
+    fn send_action(&mut self, action: Action) -> Result<()> {
+        let toggle = self.framer().action(action)?;
+        self.write(toggle)
+    }
+
+    fn send_query(&mut self) -> Result<()> {
+        let query = self.framer().query()?;
+        self.write(query)
+    }
+
+    fn send_disconnect(&mut self) -> Result<()> {
+        self.send_action(Action::Disconnect)
+    }
+
+    fn send_connect(&mut self) -> Result<()> {
+        self.send_action(Action::Connect)
+    }
+}
+
+/// pick the only device matching vid/pid and serial
+fn select_device(
+    devices: Vec<DeviceInfo>,
+    vid: Option<u16>,
+    pid: Option<u16>,
+    serial: Option<&str>,
+) -> Result<String> {
+    let (mut matching, others): (Vec<_>, Vec<_>) = devices
+        .into_iter()
+        .filter(|device| device.has_ids(vid, pid))
+        .partition(|device| serial.is_none_or(|sn| device.serial.as_deref() == Some(sn)));
+
+    let serial_of = |device: &DeviceInfo| {
+        device
+            .serial
+            .clone()
+            .unwrap_or_else(|| "<none>".to_string())
+    };
+
+    match matching.len() {
+        1 => return Ok(matching.remove(0).port),
+        0 => {}
+        _ => {
+            let candidates: Vec<String> = matching
+                .iter()
+                .map(|device| format!("{} (serial {})", device.display_name(), serial_of(device)))
+                .collect();
+            bail!(RelayError::AmbiguousDevice(format!(
+                "Several TTY devices match vid:pid {}: {}, select one by serial number or tty path",
+                usb_id(vid, pid),
+                candidates.join(", ")
+            )));
+        }
+    }
+
+    if let Some(sn) = serial {
+        let found = if others.is_empty() {
+            "none".to_string()
+        } else {
+            others.iter().map(serial_of).collect::<Vec<_>>().join(", ")
+        };
+        bail!(RelayError::DeviceNotFound(format!(
+            "No TTY device with serial number {} (with vid:pid {}), found serial numbers: {}",
+            sn,
+            usb_id(vid, pid),
+            found
+        )));
+    }
+
+    bail!(RelayError::DeviceNotFound(format!(
+        "Compatible TTY devices is not found (with vid:pid {})",
+        usb_id(vid, pid)
+    )))
+}
+
+/// resolve udev style aliases to the real device, bare names are looked up in /dev
+///
+/// windows COM ports are passed through as is
+fn resolve_tty(path: &str) -> String {
+    if is_com_port(path) {
+        return path.to_string();
+    }
+
+    let mut candidate = PathBuf::from(path);
+    if !candidate.exists() && !path.contains('/') {
+        let dev = Path::new("/dev").join(path);
+        if dev.exists() {
+            candidate = dev;
+        }
+    }
+
+    match fs::canonicalize(&candidate) {
+        Ok(real) => {
+            let real = real.to_string_lossy().into_owned();
+            if real != path {
+                debug!("tty {} resolves to {}", path, real);
+            }
+            real
+        }
+        Err(_) => candidate.to_string_lossy().into_owned(),
+    }
+}
+
+/// a read which ended at the timeout without data
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+/// keep the serialport error, adding a hint for missing permissions
+fn open_error(path: &str, e: &serialport::Error) -> RelayError {
+    let hint = match e.kind() {
+        serialport::ErrorKind::Io(io::ErrorKind::PermissionDenied) => {
+            " (add your user to the dialout or uucp group and log in again)"
+        }
+        _ => "",
+    };
+    RelayError::OpenFailed(format!("failed to open tty {}: {}{}", path, e, hint))
+}
+
+/// options used to locate and open the tty port
+#[derive(Debug, Clone, Default)]
+pub struct OpenOptions {
+    tty: Option<String>,
+    serial: Option<String>,
+    vid: Option<u16>,
+    pid: Option<u16>,
+    default_ids: (Option<u16>, Option<u16>),
+    baud: Option<u32>,
+    dry_run: bool,
+    retries: u32,
+    retry_delay: Duration,
+    timeout: Option<Duration>,
+    frame_delay: Option<Duration>,
+    remote: Option<String>,
+    cache: Option<PathBuf>,
+    wait_for_device: Option<(Duration, Option<Duration>)>,
+    baud_cache: Option<PathBuf>,
+    no_lock: bool,
+    lock_wait: Duration,
+    /// devices listed instead of the enumeration
+    #[cfg(test)]
+    devices: Option<Vec<DeviceInfo>>,
+    /// sysfs tty class directory used to check the cached port
+    #[cfg(test)]
+    sysfs: Option<PathBuf>,
+    /// value used instead of the `TTY_RELAY_PORT` environment variable
+    #[cfg(test)]
+    env_port: Option<String>,
+}
+
+impl OpenOptions {
+    /// environment variable with the default tty port path
+    pub const PORT_ENV: &'static str = "TTY_RELAY_PORT";
+
+    /// autodetect the port with the default settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// open the port by path instead of autodetection
+    ///
+    /// symlinks are resolved and bare names are looked up in /dev
+    pub fn tty(mut self, path: impl Into<String>) -> Self {
+        self.tty = Some(path.into());
+        self
+    }
+
+    /// autodetect the device with the given USB serial number
+    pub fn serial(mut self, serial: impl Into<String>) -> Self {
+        self.serial = Some(serial.into());
+        self
+    }
+
+    /// autodetect the device by USB vendor id
+    ///
+    /// when only one of vid/pid is set the other one is not matched,
+    /// when none is set the default CH340 ids are used
+    pub fn vid(mut self, vid: u16) -> Self {
+        self.vid = Some(vid);
+        self
+    }
+
+    /// autodetect the device by USB product id
+    pub fn pid(mut self, pid: u16) -> Self {
+        self.pid = Some(pid);
+        self
+    }
+
+    /// USB ids for autodetection which [`OpenOptions::vid`] and
+    /// [`OpenOptions::pid`] override, like the ones of a board profile; unlike
+    /// [`OpenOptions::vid`] and [`OpenOptions::pid`] they don't take
+    /// precedence over `TTY_RELAY_PORT`
+    pub fn default_ids(mut self, vid: Option<u16>, pid: Option<u16>) -> Self {
+        self.default_ids = (vid, pid);
+        self
+    }
+
+    /// serial baud rate, 9600 by default
+    pub fn baud(mut self, baud: u32) -> Self {
+        self.baud = Some(baud);
+        self
+    }
+
+    /// serial read timeout, 10ms by default
+    ///
+    /// should stay above 1ms, slow links (like bluetooth) need a larger value
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// delay after every frame, 50ms by default
+    ///
+    /// most boards drop frames below ~20ms, slow clones need ~120ms
+    pub fn frame_delay(mut self, frame_delay: Duration) -> Self {
+        self.frame_delay = Some(frame_delay);
+        self
+    }
+
+    fn configure(&self, mut port: Port) -> Port {
+        port.timeout = self.timeout.unwrap_or(Port::TIMEOUT);
+        port.frame_delay = self.frame_delay.unwrap_or(Port::FRAME_DELAY);
+        port
+    }
+
+    /// connect to a network serial port (like ser2net) instead of a local tty
+    pub fn remote(mut self, address: impl Into<String>) -> Self {
+        self.remote = Some(address.into());
+        self
+    }
+
+    fn open_remote(&self, address: &str) -> Result<Port> {
+        debug!("try to connect to remote serial port {}", address);
+        if let Some(baud) = self.baud {
+            warn!(
+                "{}: baud rate {} is ignored, the server sets the rate of a remote port",
+                address, baud
+            );
+        }
+
+        let stream = TcpStream::connect(address).map_err(|e| {
+            RelayError::OpenFailed(format!("failed to connect to {}: {}", address, e))
+        })?;
+        let timeout = self.timeout.unwrap_or(Port::TIMEOUT);
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        debug!("remote serial port was connected");
+
+        let mut port = self.configure(Port::from_stream(Box::new(stream), address.to_string()));
+        port.remote = true;
+        Ok(port)
+    }
+
+    /// retry the autodetection and opening that many times, none by default
+    pub fn retries(mut self, retries: u32, delay: Duration) -> Self {
+        self.retries = retries;
+        self.retry_delay = delay;
+        self
+    }
+
+    /// only log the frames instead of sending them, the device is not opened
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// remember the autodetected port in the file
+    ///
+    /// the cached path is opened without enumeration while its sysfs USB ids
+    /// (and serial) still match, otherwise the devices are enumerated again
+    pub fn cache(mut self, path: impl Into<PathBuf>) -> Self {
+        self.cache = Some(path.into());
+        self
+    }
+
+    /// remember the rate found by [`OpenOptions::detect_baud`] in the file, keyed by the port
+    pub fn baud_cache(mut self, path: impl Into<PathBuf>) -> Self {
+        self.baud_cache = Some(path.into());
+        self
+    }
+
+    /// lock the tty while it is open, on by default
+    ///
+    /// the lock is advisory: it keeps other processes using this crate (a daemon
+    /// next to ad-hoc calls) from interleaving their frames. remote ports are not locked
+    pub fn lock(mut self, lock: bool) -> Self {
+        self.no_lock = !lock;
+        self
+    }
+
+    /// wait up to `wait` for another process to release the tty, instead of
+    /// failing with [`RelayError::Busy`] at once
+    pub fn lock_wait(mut self, wait: Duration) -> Self {
+        self.lock_wait = wait;
+        self
+    }
+
+    /// poll the enumeration every `interval` until a matching device shows
+    /// up, for `timeout` or forever; only autodetection waits, not a given path
+    pub fn wait_for_device(mut self, interval: Duration, timeout: Option<Duration>) -> Self {
+        self.wait_for_device = Some((interval, timeout));
+        self
+    }
+
+    /// list these devices instead of enumerating the host
+    #[cfg(test)]
+    fn devices(mut self, devices: Vec<DeviceInfo>) -> Self {
+        self.devices = Some(devices);
+        self
+    }
+
+    fn available_devices(&self) -> Result<Vec<DeviceInfo>> {
+        #[cfg(test)]
+        if let Some(devices) = &self.devices {
+            return Ok(devices.clone());
+        }
+
+        available_devices()
+    }
+
+    /// read the ttys from this sysfs class directory
+    #[cfg(test)]
+    fn sysfs(mut self, class_dir: impl Into<PathBuf>) -> Self {
+        self.sysfs = Some(class_dir.into());
+        self
+    }
+
+    /// whether the tty at `path` is still a device with these ids and serial
+    fn is_device(&self, path: &str, vid: Option<u16>, pid: Option<u16>) -> bool {
+        // tests never look at the host sysfs
+        #[cfg(test)]
+        let class_dir = match &self.sysfs {
+            Some(class_dir) => class_dir.as_path(),
+            None => return false,
+        };
+        #[cfg(not(test))]
+        let class_dir = Path::new(crate::device::SYS_CLASS_TTY);
+
+        sysfs_device(class_dir, path).is_some_and(|device| {
+            device.has_ids(vid, pid)
+                && self
+                    .serial
+                    .as_ref()
+                    .is_none_or(|serial| device.serial.as_ref() == Some(serial))
+        })
+    }
+
+    /// use this `TTY_RELAY_PORT` value instead of the environment
+    #[cfg(test)]
+    fn env_port(mut self, port: &str) -> Self {
+        self.env_port = Some(port.to_string());
+        self
+    }
+
+    fn port_env(&self) -> Option<String> {
+        #[cfg(test)]
+        if let Some(port) = &self.env_port {
+            return Some(port.clone());
+        }
+
+        env::var(Self::PORT_ENV).ok()
+    }
+
+    fn select_device(&self, vid: Option<u16>, pid: Option<u16>) -> Result<String> {
+        select_device(self.available_devices()?, vid, pid, self.serial.as_deref())
+    }
+
+    fn find_device(&self, vid: Option<u16>, pid: Option<u16>) -> Result<String> {
+        let (interval, timeout) = match self.wait_for_device {
+            Some(wait) => wait,
+            None => return self.select_device(vid, pid),
+        };
+        let started = Instant::now();
+
+        loop {
+            match self.select_device(vid, pid) {
+                Err(e) if matches!(RelayError::find(&e), Some(RelayError::DeviceNotFound(_))) => {
+                    if let Some(timeout) = timeout.filter(|timeout| started.elapsed() >= *timeout) {
+                        bail!(RelayError::DeviceNotFound(format!(
+                            "no matching device appeared within {:?}",
+                            timeout
+                        )));
+                    }
+                    debug!("{:#}, poll again in {:?}", e, interval);
+                    thread::sleep(interval);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn autodetect(&self) -> bool {
+        self.serial.is_some() || self.vid.is_some() || self.pid.is_some()
+    }
+
+    /// resolve the port path, also tells whether it came from the cache
+    fn resolve_path(&self, use_cache: bool) -> Result<(String, bool)> {
+        if let Some(p) = &self.tty {
+            debug!("try to open serial port by path {}", p);
+            return Ok((resolve_tty(p), false));
+        }
+
+        if !self.autodetect() {
+            if let Some(p) = self.port_env() {
+                debug!(
+                    "try to open serial port by path {} from {}",
+                    p,
+                    Self::PORT_ENV
+                );
+                // like --tty, COM ports are not filesystem paths
+                let path = resolve_tty(&p);
+                if !is_com_port(&path) && !Path::new(&path).exists() {
+                    bail!(RelayError::DeviceNotFound(format!(
+                        "{} points to missing path {}",
+                        Self::PORT_ENV,
+                        p
+                    )));
+                }
+                return Ok((path, false));
+            }
+        }
+
+        let (default_vid, default_pid) = self.default_ids;
+        let (vid, pid) = match (self.vid.or(default_vid), self.pid.or(default_pid)) {
+            (None, None) => (Some(Port::VID), Some(Port::PID)),
+            ids => ids,
+        };
+        debug!(
+            "try to find serial port with vid:pid={} serial={:?}",
+            usb_id(vid, pid),
+            self.serial
+        );
+        let key = format!(
+            "{} {}",
+            usb_id(vid, pid),
+            self.serial.as_deref().unwrap_or("*")
+        );
+
+        let cached = match &self.cache {
+            Some(cache) if use_cache => cache::load(cache, &key),
+            _ => None,
+        };
+
+        // the cached port is opened without enumeration while it still has
+        // the ids, a second matching board plugged in meanwhile goes unnoticed
+        if let Some(path) = cached
+            .as_ref()
+            .filter(|path| self.is_device(path, vid, pid))
+        {
+            debug!("serial port {} taken from cache", path);
+            return Ok((path.clone(), true));
+        }
+
+        let path = self.find_device(vid, pid)?;
+        debug!("serial port found in path {}", path);
+
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return Ok((path, false)),
+        };
+
+        if cached.as_deref() == Some(path.as_str()) {
+            debug!("serial port {} confirmed by cache", path);
+            return Ok((path, true));
+        }
+
+        if let Err(e) = cache::store(cache, &key, &path) {
+            debug!("failed to write port cache {}: {}", cache.display(), e);
+        }
+
+        Ok((path, false))
+    }
+
+    /// open the tty port
+    ///
+    /// the port path is taken from [`OpenOptions::tty`], then from the
+    /// `TTY_RELAY_PORT` environment variable (unless serial/vid/pid are set)
+    /// and autodetected otherwise
+    pub fn open(&self) -> Result<Port, RelayError> {
+        if self.dry_run {
+            let path = self
+                .tty
+                .clone()
+                .or_else(|| self.remote.clone())
+                .unwrap_or_else(|| "dry-run".to_string());
+            debug!("dry-run, serial port {} is not opened", path);
+
+            let mut port =
+                self.configure(Port::from_stream(Box::new(Cursor::new(Vec::new())), path));
+            port.dry_run = true;
+            return Ok(port);
+        }
+
+        let mut attempt = 0;
+
+        loop {
+            match self.try_open() {
+                Ok(port) => return Ok(port),
+                Err(e) if attempt < self.retries => {
+                    attempt += 1;
+                    debug!(
+                        "failed to open serial port: {:#}, retry {}/{} in {:?}",
+                        e, attempt, self.retries, self.retry_delay
+                    );
+                    thread::sleep(self.retry_delay);
                 }
+                Err(e) => return Err(e.into()),
             }
         }
+    }
+
+    /// the path [`OpenOptions::open`] would open (or the remote address), the
+    /// device is neither opened nor checked to accept connections
+    ///
+    /// autodetection always enumerates the devices, skipping the cache, so
+    /// several matching boards fail with [`RelayError::AmbiguousDevice`]
+    pub fn resolve(&self) -> Result<String, RelayError> {
+        if let Some(address) = &self.remote {
+            return Ok(address.clone());
+        }
+
+        Ok(self.resolve_path(false).map(|(path, _)| path)?)
+    }
+
+    fn try_open(&self) -> Result<Port> {
+        if let Some(address) = &self.remote {
+            return self.open_remote(address);
+        }
+
+        let (path, cached) = self.resolve_path(true)?;
+
+        let result = self.open_tty(&path);
+        if result.is_err() && cached {
+            debug!("cached serial port {} failed to open", path);
+            if let Some(cache) = &self.cache {
+                cache::invalidate(cache);
+            }
+        }
+
+        result
+    }
+
+    /// open the tty port for async use, has to be called inside a tokio runtime
+    ///
+    /// the tty is locked like by [`OpenOptions::open`], waiting for the lock
+    /// blocks the thread. remote ports and dry-run are not supported
+    #[cfg(feature = "async")]
+    pub fn open_async(&self) -> Result<AsyncPort, RelayError> {
+        use tokio_serial::SerialPortBuilderExt;
+
+        if self.remote.is_some() || self.dry_run {
+            return Err(RelayError::InvalidArgument(
+                "remote and dry-run ports can not be opened for async use".to_string(),
+            ));
+        }
+
+        let (path, _) = self.resolve_path(true)?;
+        let lock = match self.no_lock {
+            true => None,
+            false => Some(lock::acquire(&path, self.lock_wait)?),
+        };
+        let baud = self.baud.unwrap_or(Port::BAUD);
+        let port = tokio_serial::new(&path, baud)
+            .open_native_async()
+            .map_err(|e| open_error(&path, &e))?;
+
+        debug!("serial port was opened at {} baud", baud);
+
+        Ok(AsyncPort::new(
+            Box::new(port),
+            path,
+            self.timeout.unwrap_or(Port::TIMEOUT),
+            self.frame_delay.unwrap_or(Port::FRAME_DELAY),
+            lock,
+        ))
+    }
+
+    /// open the port at the first of [`Port::BAUD_RATES`] at which `probe` succeeds
+    ///
+    /// `probe` gets the port as [`OpenOptions::open`] returns it, typically it
+    /// configures the frame layout and calls [`Port::probe`] with an acknowledgement.
+    /// a rate remembered by [`OpenOptions::baud_cache`] is tried first, a baud
+    /// set by [`OpenOptions::baud`] is ignored. remote ports have no baud rate
+    pub fn detect_baud(
+        &self,
+        probe: impl FnMut(&mut Port) -> Result<()>,
+    ) -> Result<(Port, u32), RelayError> {
+        if self.remote.is_some() {
+            return Err(RelayError::InvalidArgument(
+                "remote ports have no baud rate to detect".to_string(),
+            ));
+        }
+        if self.dry_run {
+            return Ok((self.open()?, self.baud.unwrap_or(Port::BAUD)));
+        }
+
+        // every rate is opened like by open(), with the retries; the path is
+        // resolved once, so --wait-for-device only waits for the first one
+        let (path, _) = self.resolve_path(true)?;
+        let options = self.clone().tty(path.as_str());
+        self.detect_baud_with(&path, |baud| Ok(options.clone().baud(baud).open()?), probe)
+    }
+
+    /// the rate loop of [`OpenOptions::detect_baud`], `open` opens the port at a rate
+    fn detect_baud_with(
+        &self,
+        path: &str,
+        mut open: impl FnMut(u32) -> Result<Port>,
+        mut probe: impl FnMut(&mut Port) -> Result<()>,
+    ) -> Result<(Port, u32), RelayError> {
+        let cached = self
+            .baud_cache
+            .as_deref()
+            .and_then(|cache| cache::load_value(cache, path))
+            .and_then(|baud| baud.parse().ok());
+        let rates = cached.into_iter().chain(
+            Port::BAUD_RATES
+                .into_iter()
+                .filter(|&baud| Some(baud) != cached),
+        );
+
+        for baud in rates {
+            let mut port = open(baud)?;
+            match probe(&mut port) {
+                Ok(()) => {
+                    if let Some(cache) = &self.baud_cache {
+                        if let Err(e) = cache::store(cache, path, &baud.to_string()) {
+                            debug!("failed to write baud cache {}: {}", cache.display(), e);
+                        }
+                    }
+                    return Ok((port, baud));
+                }
+                Err(e) if matches!(RelayError::find(&e), Some(RelayError::Interrupted(_))) => {
+                    return Err(e.into())
+                }
+                Err(e) => debug!("{}: no answer at {} baud: {:#}", path, baud, e),
+            }
+        }
+
+        Err(RelayError::NoAcknowledgement(format!(
+            "{}: no answer at any of {:?} baud",
+            path,
+            Port::BAUD_RATES
+        )))
+    }
+
+    fn open_tty(&self, path: &str) -> Result<Port> {
+        self.open_tty_at(path, self.baud.unwrap_or(Port::BAUD))
+    }
+
+    fn open_tty_at(&self, path: &str, baud: u32) -> Result<Port> {
+        let lock = match self.no_lock {
+            true => None,
+            false => Some(lock::acquire(path, self.lock_wait)?),
+        };
+        let port = serialport::new(path, baud)
+            .timeout(self.timeout.unwrap_or(Port::TIMEOUT))
+            .open()
+            .map_err(|e| open_error(path, &e))?;
+
+        debug!("serial port was opened at {} baud", baud);
+
+        let mut port = self.configure(Port::from_stream(Box::new(port), path.to_string()));
+        port.lock = lock;
+        Ok(port)
+    }
+}
+
+impl Port {
+    const VID: u16 = DeviceInfo::VID;
+    const PID: u16 = DeviceInfo::PID;
+    pub(crate) const BAUD: u32 = 9600;
+    /// rates tried by [`OpenOptions::detect_baud`], most common first
+    pub const BAUD_RATES: [u32; 5] = [9600, 19200, 38400, 57600, 115200];
+    pub(crate) const TIMEOUT: Duration = Duration::from_millis(10);
+    pub(crate) const FRAME_DELAY: Duration = Duration::from_millis(50);
+    /// a chattering device must not keep the drain going forever
+    const DRAIN_LIMIT: usize = 256;
+
+    /// list the USB serial devices present on the host
+    pub fn list_devices() -> Result<Vec<DeviceInfo>, RelayError> {
+        Ok(available_devices()?)
+    }
+
+    /// port which writes into the recorder instead of a device, frames are not delayed
+    ///
+    /// the frames are logged at info level like in dry-run
+    pub fn recording(recorder: &Recorder) -> Port {
+        let mut port = Port::from_stream(Box::new(recorder.clone()), "memory".to_string());
+        port.frame_delay = Duration::ZERO;
+        port.recorder = Some(recorder.clone());
+        port
+    }
+
+    /// in-memory port for testing without hardware, see [`Port::recorder`]
+    pub fn open_mock() -> Port {
+        let mut port = Port::recording(&Recorder::new());
+        port.path = "mock".to_string();
+        port
+    }
+
+    /// recorder of a port made by [`Port::recording`] or [`Port::open_mock`]
+    pub fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_ref()
+    }
+
+    /// open the tty port
+    pub fn open(tty_path: Option<&str>) -> Result<Port, RelayError> {
+        let mut options = OpenOptions::new();
+
+        if let Some(p) = tty_path {
+            options = options.tty(p);
+        }
+
+        options.open()
+    }
+
+    /// path of the opened tty port
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// whether the frames don't reach a relay, with dry-run or a recording port
+    /// like [`Port::open_mock`]
+    pub fn is_simulated(&self) -> bool {
+        self.dry_run || self.recorder.is_some()
+    }
+
+    /// set the board protocol, the channel selection is reset to the first relay
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+        self.channel = 1;
+    }
+
+    /// select the relay contact the load is wired to
+    pub fn set_wiring(&mut self, wiring: Wiring) {
+        self.wiring = wiring;
+    }
+
+    /// swap the meaning of on and off, for thinking in terms of the load
+    ///
+    /// `on` then disconnects, `off` connects, the timed commands and the status are
+    /// swapped alike. it composes with the wiring: inverted NC wiring switches like NO
+    pub fn set_invert(&mut self, invert: bool) {
+        self.invert = invert;
+    }
+
+    /// the relay contact the load is wired to
+    pub fn wiring(&self) -> Wiring {
+        self.wiring
+    }
+
+    /// check that the relay echoes every frame back
+    ///
+    /// not all clones echo the frames, so this is disabled by default
+    pub fn set_verify(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    /// discard the pending input before a status query or a verified frame
+    ///
+    /// the drain stops at the first read which times out, after 10ms unless
+    /// set by [`OpenOptions::timeout`]
+    pub fn set_drain(&mut self, drain: bool) {
+        self.drain = drain;
+    }
+
+    /// delay after every written frame, 50ms unless set by [`OpenOptions::frame_delay`]
+    pub fn set_frame_delay(&mut self, frame_delay: Duration) {
+        self.frame_delay = frame_delay;
+    }
+
+    /// how long a response may take, 10ms unless set by [`OpenOptions::timeout`]
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// time source of all delays, a [`VirtualClock`](crate::VirtualClock) makes
+    /// timed commands return at once
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// append every frame sent to the device to `capture`, one
+    /// `<timestamp> <port> <hex>` line per frame flushed right away
+    ///
+    /// frames of a dry-run are not sent, so they are not captured
+    pub fn set_capture(&mut self, capture: Box<dyn Write + Send>) {
+        self.capture = Some(capture);
+    }
+
+    /// send a frame again up to `retries` times when writing it fails, none by
+    /// default; the frame delay is waited between the attempts
+    ///
+    /// after a write which sent a part of the frame only the rest is sent again
+    pub fn set_write_retries(&mut self, retries: u32) {
+        self.write_retries = retries;
+    }
+
+    /// cap timers above [`Protocol::max_timer`] instead of rejecting them
+    pub fn set_clamp(&mut self, clamp: bool) {
+        self.clamp = clamp;
+    }
+
+    /// flag which interrupts commands once set
+    ///
+    /// no further frames are written (failing with [`RelayError::Interrupted`]) and
+    /// long running commands (like [`Port::blink`]) stop waiting, see [`Port::force_off`]
+    pub fn set_interrupt(&mut self, interrupt: Arc<AtomicBool>) {
+        self.interrupt = Some(interrupt);
+    }
+
+    /// select the relay (1-based) addressed by the following commands
+    pub fn set_channel(&mut self, channel: u8) -> Result<(), RelayError> {
+        if channel == 0 || channel > self.protocol.channels {
+            return Err(RelayError::InvalidArgument(format!(
+                "invalid channel {}, board has {} channel(s)",
+                channel, self.protocol.channels
+            )));
+        }
+
+        debug!("{}: select channel {}", self.path, channel);
+        self.channel = channel;
+        Ok(())
+    }
+
+    /// switch several relays (1-based) with a single bitmask frame
+    ///
+    /// for boards which take the relay mask in the second frame byte, like the
+    /// 8 channel ones; the selected channel is not changed
+    pub fn switch(&mut self, channels: &[u8], state: RelayState) -> Result<(), RelayError> {
+        debug!("switch channels {:?} {}", channels, state);
+        let limit = self.protocol.channels.min(8);
+        let mut mask = 0u8;
+
+        for &channel in channels {
+            if channel == 0 || channel > limit {
+                return Err(RelayError::InvalidArgument(format!(
+                    "invalid channel {}, the mask addresses {} channel(s)",
+                    channel, limit
+                )));
+            }
+            mask |= 1 << (channel - 1);
+        }
+        if mask == 0 {
+            return Err(RelayError::InvalidArgument(
+                "no channels to switch".to_string(),
+            ));
+        }
+
+        let action = match state {
+            RelayState::On => Action::Connect,
+            RelayState::Off => Action::Disconnect,
+        };
+        let frames = [
+            self.framer().control_mode()?,
+            self.framer().mask_action(mask, action),
+        ];
+        Ok(self.write_frames(&frames)?)
+    }
+
+    /// start immediately
+    pub fn on(&mut self) -> Result<(), RelayError> {
+        debug!("on command");
+        let frames = self.framer().on()?;
+        Ok(self.write_frames(&frames)?)
+    }
+
+    /// stop immediately
+    pub fn off(&mut self) -> Result<(), RelayError> {
+        debug!("off command");
+        let frames = self.framer().off()?;
+        Ok(self.write_frames(&frames)?)
+    }
+
+    /// stop immediately, also after an interrupt
+    ///
+    /// leaves a safe state once a command was interrupted
+    pub fn force_off(&mut self) -> Result<(), RelayError> {
+        let interrupt = self.interrupt.take();
+        let result = self.off();
+        self.interrupt = interrupt;
+        result
+    }
+
+    /// start after n seconds
+    pub fn timed_on(&mut self, timeout: u16) -> Result<(), RelayError> {
+        debug!("on after {} seconds", timeout);
+        let timer = self.protocol.timer(timeout, self.clamp)?;
+        let frames = self.framer().timed_on(timer)?;
+        Ok(self.write_frames(&frames)?)
+    }
+
+    /// stop after n seconds
+    pub fn timed_off(&mut self, timeout: u16) -> Result<(), RelayError> {
+        debug!("off after {} seconds", timeout);
+        let timer = self.protocol.timer(timeout, self.clamp)?;
+        let frames = self.framer().timed_off(timer)?;
+        Ok(self.write_frames(&frames)?)
+    }
+
+    /// enable power in a state which falls back to disconnected
+    ///
+    /// the coil is energized to connect the load and the firmware timer is armed
+    /// to release it after n seconds: power drops when the timer expires without
+    /// being re-armed (call again, or see [`Port::watchdog`]) and when the board
+    /// loses its USB power. only a load on the NO contact becomes disconnected by
+    /// a released coil, so NC wiring is rejected
+    pub fn arm_failsafe(&mut self, timeout: u16) -> Result<(), RelayError> {
+        debug!("fail-safe, off after {} seconds", timeout);
+        if self.switched_contact() == Wiring::NormallyClosed {
+            return Err(RelayError::InvalidArgument(
+                "fail-safe needs the load on the NO contact, an unpowered board connects NC"
+                    .to_string(),
+            ));
+        }
+        if timeout == 0 {
+            return Err(RelayError::InvalidArgument(
+                "fail-safe timer must be positive".to_string(),
+            ));
+        }
+
+        let timer = self.protocol.timer(timeout, self.clamp)?;
+        let frames = self.framer().timed_off(timer)?;
+        Ok(self.write_frames(&frames)?)
+    }
+
+    /// arm the firmware timer, the relay toggles power once it expires
+    ///
+    /// after [`Port::on`] or [`Port::jog`] this disconnects power after n seconds.
+    /// the frame carries no channel, the firmware times the relay of the last
+    /// mode frame
+    pub fn arm_timer(&mut self, timeout: u16) -> Result<(), RelayError> {
+        // `--off-after N jog` relies on the jog mode timer, so no warning here
+        debug!(
+            "timer {} seconds in {} mode",
+            timeout,
+            if self.jog { "jog" } else { "control" }
+        );
+        let timer = self.protocol.timer(timeout, self.clamp)?;
+        let frame = self.framer().timer(timer);
+        Ok(self.write(frame)?)
+    }
+
+    /// send the control mode frame, which keeps an idle link busy without
+    /// changing the relay state
+    pub fn keepalive(&mut self) -> Result<(), RelayError> {
+        let frame = self.framer().control_mode()?;
+        Ok(self.write(frame)?)
+    }
+
+    /// toggle power
+    pub fn toggle(&mut self) -> Result<(), RelayError> {
+        debug!("toggle command");
+        let frames = self.framer().toggle()?;
+        Ok(self.write_frames(&frames)?)
+    }
+
+    /// switch to `state` unless the relay already reports it, returns whether frames were sent
+    ///
+    /// unlike [`Port::toggle`] repeating it keeps the state; boards which can't
+    /// report their state get the on/off frames anyway
+    pub fn set(&mut self, state: RelayState) -> Result<bool, RelayError> {
+        debug!("set {} command", state);
+        match self.status() {
+            Ok(current) if current == state => {
+                debug!("{}: already {}", self.path, state);
+                return Ok(false);
+            }
+            Ok(_) => {}
+            Err(e @ RelayError::Interrupted(_)) => return Err(e),
+            Err(e) => debug!("{}: state unknown, switch anyway: {:#}", self.path, e),
+        }
+
+        match state {
+            RelayState::On => self.on()?,
+            RelayState::Off => self.off()?,
+        }
+        Ok(true)
+    }
+
+    /// quick toggle power
+    pub fn jog(&mut self) -> Result<(), RelayError> {
+        debug!("jog command");
+        let frames = self.framer().jog()?;
+        Ok(self.write_frames(&frames)?)
+    }
+
+    /// momentary jog timed by the host: jog mode, connect, wait `hold`, disconnect
+    ///
+    /// unlike [`Port::jog`] the firmware doesn't time it, the call blocks for the
+    /// whole hold, which is stretched to the frame delay like [`Port::pulse`]
+    pub fn jog_hold(&mut self, hold: Duration) -> Result<(), RelayError> {
+        debug!("jog for {:?}", hold);
+        if hold.is_zero() {
+            return Err(RelayError::InvalidArgument(
+                "jog hold must be positive".to_string(),
+            ));
+        }
+
+        let frames = self.framer().jog()?;
+        self.write_frames(&frames)?;
+        if !self.wait(hold.saturating_sub(self.frame_delay)) {
+            self.force_off()?;
+            return Err(RelayError::Interrupted("jog hold interrupted".to_string()));
+        }
+        Ok(self.send_disconnect()?)
+    }
+
+    /// leave jog mode: switch to control mode and disconnect power
+    ///
+    /// `timed_on`/`timed_off` already re-assert control mode before arming the
+    /// timer, this gives a deterministic state without arming anything
+    pub fn reset(&mut self) -> Result<(), RelayError> {
+        debug!("reset command");
+        let frames = self.framer().off()?;
+        Ok(self.write_frames(&frames)?)
+    }
+
+    /// wait for the duration, fails with [`RelayError::Interrupted`] once the
+    /// interrupt is set, see [`Port::set_interrupt`]
+    pub fn sleep(&self, duration: Duration) -> Result<(), RelayError> {
+        if !self.wait(duration) {
+            return Err(RelayError::Interrupted(format!(
+                "{}: interrupted while waiting",
+                self.path
+            )));
+        }
+        Ok(())
+    }
+
+    /// software restart
+    ///
+    /// an interrupt during the pause leaves power disabled
+    pub fn restart(&mut self) -> Result<(), RelayError> {
+        debug!("restart command");
+        self.off()?;
+        if !self.wait(Duration::from_secs(1)) {
+            return Err(RelayError::Interrupted("restart interrupted".to_string()));
+        }
+        self.on()
+    }
+
+    /// enable power for the given duration
+    ///
+    /// the pulse is timed by the host, so the call blocks for the whole duration;
+    /// the frame delay after enabling is part of the pulse, so pulses shorter than
+    /// 50ms are stretched to it
+    pub fn pulse(&mut self, duration: Duration) -> Result<(), RelayError> {
+        debug!("pulse for {:?}", duration);
+        self.on()?;
+        if !self.wait(duration.saturating_sub(self.frame_delay)) {
+            self.force_off()?;
+            return Err(RelayError::Interrupted("pulse interrupted".to_string()));
+        }
+        Ok(self.send_disconnect()?)
+    }
+
+    /// disable power for the given duration, see [`Port::pulse`]
+    pub fn inverted_pulse(&mut self, duration: Duration) -> Result<(), RelayError> {
+        debug!("inverted pulse for {:?}", duration);
+        self.off()?;
+        if !self.wait(duration.saturating_sub(self.frame_delay)) {
+            // disabled power is the safe state
+            return Err(RelayError::Interrupted("pulse interrupted".to_string()));
+        }
+        Ok(self.send_connect()?)
+    }
+
+    /// toggle power on and off `count` times
+    ///
+    /// the power is left disabled, also when interrupted
+    pub fn blink(&mut self, count: u32, on: Duration, off: Duration) -> Result<(), RelayError> {
+        for i in 1..=count {
+            debug!("blink {}/{}", i, count);
+            self.on()?;
+            let completed = self.wait(on);
+            self.force_off()?;
+
+            if !completed || (i != count && !self.wait(off)) {
+                return Err(RelayError::Interrupted(format!(
+                    "blink interrupted after {} of {} cycles",
+                    i, count
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// keep power enabled while the process is alive
+    ///
+    /// every `interval` seconds the firmware timer is re-armed with
+    /// `interval + margin` seconds, so the relay disconnects on its own once
+    /// the refreshes stop; the refresh period must stay shorter than the timer.
+    /// when interrupted the last timer is left armed and Ok is returned
+    pub fn watchdog(&mut self, interval: u16, margin: u16) -> Result<(), RelayError> {
+        self.watchdog_with(interval, margin, |_| {})
+    }
+
+    /// [`Port::watchdog`] calling `refreshed` with the armed seconds after every refresh
+    pub fn watchdog_with(
+        &mut self,
+        interval: u16,
+        margin: u16,
+        mut refreshed: impl FnMut(u16),
+    ) -> Result<(), RelayError> {
+        let timeout = interval.checked_add(margin).ok_or_else(|| {
+            RelayError::InvalidArgument(format!(
+                "watchdog timer must not exceed {} seconds",
+                u16::MAX
+            ))
+        })?;
+
+        if margin == 0 {
+            return Err(RelayError::InvalidArgument(
+                "watchdog margin must be positive".to_string(),
+            ));
+        }
+
+        loop {
+            debug!("watchdog refresh, off after {} seconds", timeout);
+            self.timed_off(timeout)?;
+            refreshed(timeout);
+
+            if !self.wait(Duration::from_secs(interval.into())) {
+                debug!("watchdog interrupted, timer stays armed");
+                return Ok(());
+            }
+        }
+    }
+
+    /// check that the board is reachable without switching anything, returns the latency
+    ///
+    /// sends the control mode frame, which leaves the contact alone; with `ack` the
+    /// board must echo it back like with [`Port::set_verify`], otherwise a written
+    /// frame counts as reachable
+    pub fn probe(&mut self, ack: bool) -> Result<Duration, RelayError> {
+        debug!("probe command");
+        let frame = self.framer().control_mode()?;
+        let verify = self.verify;
+        // nothing follows the frame, its delay would only inflate the latency
+        let frame_delay = std::mem::take(&mut self.frame_delay);
+        self.verify |= ack;
+
+        let start = Instant::now();
+        let result = self.write(frame);
+        let latency = start.elapsed();
+
+        self.verify = verify;
+        self.frame_delay = frame_delay;
+        result.with_context(|| format!("{}: unreachable", self.path))?;
+        Ok(latency)
+    }
+
+    /// ask the board for its firmware revision and channel count
+    ///
+    /// not all boards answer, silence or an unexpected answer gives an unknown identity
+    pub fn identify(&mut self) -> Result<Identity, RelayError> {
+        debug!("identify command");
+        let frame = self.framer().identify();
+        self.write(frame)?;
+
+        let identity = match self.read() {
+            Ok(response) => self.framer().identity(response),
+            Err(e) => {
+                debug!("{}: no identity: {:#}", self.path, e);
+                None
+            }
+        };
+        Ok(identity.unwrap_or_default())
+    }
+
+    /// send a hand-crafted frame, for experimenting with the firmware
+    ///
+    /// it goes through dry-run, capture and verification like every other frame
+    /// but is sent as is, the v2 checksum is not substituted
+    pub fn write_raw(&mut self, frame: [u8; 4]) -> Result<(), RelayError> {
+        debug!("raw frame {:02X?}", frame);
+        Ok(self.write_command(frame, frame)?)
+    }
+
+    /// read one 4-byte frame within the read timeout, see [`Port::write_raw`]
+    pub fn read_raw(&mut self) -> Result<[u8; 4], RelayError> {
+        Ok(self.read()?)
+    }
+
+    /// read the current relay state
+    pub fn status(&mut self) -> Result<RelayState, RelayError> {
+        debug!("status command");
+        self.send_query()?;
+
+        let response = self.read()?;
+        match self.framer().state(response) {
+            Some(state) => Ok(state),
+            None => Err(RelayError::Io(format!(
+                "{}: unexpected response from relay {:02X?}",
+                self.path, response
+            ))),
+        }
+    }
+}
+
+impl Port {
+    /// guard which disables power when dropped before [`PowerGuard::disarm`]
+    ///
+    /// wrap commands which energize the relay, so a failing command can't
+    /// leave the equipment powered
+    pub fn power_guard(&mut self) -> PowerGuard<'_> {
+        PowerGuard {
+            port: self,
+            armed: true,
+        }
+    }
+}
+
+/// best-effort `off` on drop, see [`Port::power_guard`]
+pub struct PowerGuard<'a> {
+    port: &'a mut Port,
+    armed: bool,
+}
+
+impl PowerGuard<'_> {
+    /// the command succeeded, keep the power state
+    pub fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Deref for PowerGuard<'_> {
+    type Target = Port;
+
+    fn deref(&self) -> &Port {
+        self.port
+    }
+}
+
+impl DerefMut for PowerGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Port {
+        self.port
+    }
+}
+
+impl Drop for PowerGuard<'_> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+
+        warn!("{}: command failed, disabling power", self.port.path);
+        if let Err(e) = self.port.force_off() {
+            warn!("{}: failed to disable power: {:#}", self.port.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+    use std::collections::VecDeque;
+
+    fn create_stub_port() -> Port {
+        let mut port = Port::recording(&Recorder::new());
+        port.path = "stub".to_string();
+        port.set_wiring(Wiring::NormallyOpen);
+        port
+    }
+
+    /// what the stub device queues for reading after every write
+    #[derive(Debug, Clone, Copy, Default)]
+    enum Answer {
+        #[default]
+        Nothing,
+        /// the written bytes
+        Echo,
+        Frame([u8; 4]),
+    }
+
+    /// scripted outcome of a write, the unscripted ones take the whole buffer
+    enum Outcome {
+        Fail(io::ErrorKind),
+        /// only the first n bytes are taken
+        Short(usize),
+    }
+
+    #[derive(Default)]
+    struct Script {
+        pending: VecDeque<u8>,
+        answer: Answer,
+        writes: VecDeque<Outcome>,
+        /// set once this many frames were written
+        interrupt: Option<(usize, Arc<AtomicBool>)>,
+        clock: Option<VirtualClock>,
+        /// virtual time of every write in ms
+        times: Vec<u128>,
+    }
+
+    /// scriptable stub device: the written bytes go to a [`Recorder`], reads
+    /// return the scripted bytes and answers, writes can fail or stop short and
+    /// are stamped with the time of a virtual clock
+    #[derive(Clone, Default)]
+    struct Stub {
+        recorder: Recorder,
+        script: Arc<std::sync::Mutex<Script>>,
+    }
+
+    impl Stub {
+        /// bytes pending before the first write, like a stale answer
+        fn reading(self, bytes: &[u8]) -> Self {
+            self.script.lock().unwrap().pending.extend(bytes);
+            self
+        }
+
+        fn answering(self, answer: Answer) -> Self {
+            self.script.lock().unwrap().answer = answer;
+            self
+        }
+
+        /// fail the next `writes` writes with a transient error
+        fn failing(self, writes: usize) -> Self {
+            let mut script = self.script.lock().unwrap();
+            for _ in 0..writes {
+                script
+                    .writes
+                    .push_back(Outcome::Fail(io::ErrorKind::BrokenPipe));
+            }
+            drop(script);
+            self
+        }
+
+        /// take only `len` bytes of the next write
+        fn short_write(self, len: usize) -> Self {
+            self.script
+                .lock()
+                .unwrap()
+                .writes
+                .push_back(Outcome::Short(len));
+            self
+        }
+
+        /// set the interrupt flag of the port once `frames` frames were written
+        fn interrupt_after(self, frames: usize) -> Self {
+            let interrupt = Arc::new(AtomicBool::new(false));
+            self.script.lock().unwrap().interrupt = Some((frames, interrupt));
+            self
+        }
+
+        /// run the port on a virtual clock, see [`Stub::timeline`]
+        fn timed(self) -> Self {
+            self.script.lock().unwrap().clock = Some(VirtualClock::new());
+            self
+        }
+
+        fn port(&self) -> Port {
+            let mut port = Port::from_stream(Box::new(self.clone()), "stub".to_string());
+            port.set_wiring(Wiring::NormallyOpen);
+            let script = self.script.lock().unwrap();
+            if let Some((_, interrupt)) = &script.interrupt {
+                port.set_interrupt(interrupt.clone());
+            }
+            if let Some(clock) = &script.clock {
+                port.set_clock(Box::new(clock.clone()));
+            }
+            port
+        }
+
+        fn written(&self) -> Vec<u8> {
+            self.recorder.written()
+        }
+
+        fn frames(&self) -> Vec<[u8; 4]> {
+            self.recorder.frames()
+        }
+
+        /// the state byte of every frame with the time it was written at in ms
+        fn timeline(&self) -> Vec<(u128, u8)> {
+            let times = self.script.lock().unwrap().times.clone();
+            times
+                .into_iter()
+                .zip(self.frames())
+                .map(|(at, frame)| (at, frame[2]))
+                .collect()
+        }
+    }
+
+    impl Read for Stub {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.script.lock().unwrap().pending.read(buf)
+        }
+    }
+
+    impl Write for Stub {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut script = self.script.lock().unwrap();
+            let len = match script.writes.pop_front() {
+                Some(Outcome::Fail(kind)) => return Err(io::Error::new(kind, "usb hiccup")),
+                Some(Outcome::Short(len)) => len.min(buf.len()),
+                None => buf.len(),
+            };
+            let written = &buf[..len];
+            self.recorder.write_all(written)?;
+
+            if let Some(clock) = &script.clock {
+                let now = clock.now().as_millis();
+                script.times.push(now);
+            }
+            match script.answer {
+                Answer::Nothing => {}
+                Answer::Echo => script.pending.extend(written),
+                Answer::Frame(frame) => script.pending.extend(frame),
+            }
+            if let Some((frames, interrupt)) = &script.interrupt {
+                if self.recorder.frames().len() >= *frames {
+                    interrupt.store(true, Ordering::SeqCst);
+                }
+            }
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// stub device answering the reads with `response` once
+    fn create_responder_port(response: &[u8]) -> Port {
+        Stub::default().reading(response).port()
+    }
+
+    /// stub device which echoes every written byte back
+    fn create_echo_port() -> Port {
+        Stub::default().answering(Answer::Echo).port()
+    }
+
+    fn create_v2_port() -> Port {
+        let mut port = create_stub_port();
+        port.set_protocol(Protocol {
+            checksum: true,
+            ..Protocol::default()
+        });
+        port
+    }
+
+    fn create_multichannel_port(channel: u8) -> Port {
+        let mut port = create_stub_port();
+        port.set_protocol(Protocol {
+            channels: 4,
+            ..Protocol::default()
+        });
+        port.set_channel(channel).unwrap();
+        port
+    }
+
+    fn assert_buf(port: Port, expected: &[u8]) {
+        let recorder = port.recorder().expect("stub port records the frames");
+        assert_eq!(recorder.written(), expected);
+    }
+
+    #[test]
+    fn test_control_mode() {
+        let mut port = create_stub_port();
+
+        port.control_mode().unwrap();
+
+        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x54]);
+    }
+
+    #[test]
+    fn test_jog_mode() {
+        let mut port = create_stub_port();
+
+        port.jog_mode().unwrap();
+
+        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x55]);
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut port = create_stub_port();
+
+        port.send_timer(0).unwrap();
+
+        assert_buf(port, &[0xF0, 0x00, 0x00, 0x57]);
+
+        let mut port = create_stub_port();
+
+        port.send_timer(u16::MAX).unwrap();
+
+        assert_buf(port, &[0xF0, 0xFF, 0xFF, 0x57]);
+    }
+
+    #[test]
+    fn test_connect() {
+        let mut port = create_stub_port();
+
+        port.send_connect().unwrap();
+
+        assert_buf(port, &[0xF0, 0xA0, 0x01, 0x53]);
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let mut port = create_stub_port();
+
+        port.send_disconnect().unwrap();
+
+        assert_buf(port, &[0xF0, 0xA0, 0x00, 0x53]);
+    }
+
+    #[test]
+    fn test_normally_closed_connect() {
+        let mut port = create_stub_port();
+        port.set_wiring(Wiring::NormallyClosed);
+
+        port.send_connect().unwrap();
+
+        assert_buf(port, &[0xF0, 0xA0, 0x00, 0x53]);
+    }
+
+    #[test]
+    fn test_normally_closed_disconnect() {
+        let mut port = create_stub_port();
+        port.set_wiring(Wiring::NormallyClosed);
+
+        port.send_disconnect().unwrap();
+
+        assert_buf(port, &[0xF0, 0xA0, 0x01, 0x53]);
+    }
+
+    #[test]
+    fn test_on() {
+        let mut port = create_stub_port();
+
+        port.on().unwrap();
+
+        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x01, 0x53]);
+    }
+
+    #[test]
+    fn test_off() {
+        let mut port = create_stub_port();
+
+        port.off().unwrap();
+
+        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x00, 0x53]);
+    }
+
+    #[test]
+    fn test_v2_frames() {
+        let mut port = create_v2_port();
+        port.on().unwrap();
+        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x9C, 0xF0, 0xA0, 0x01, 0x91]);
+
+        let mut port = create_v2_port();
+        port.off().unwrap();
+        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x9C, 0xF0, 0xA0, 0x00, 0x90]);
+
+        let mut port = create_v2_port();
+        port.send_timer(0x0102).unwrap();
+        assert_buf(port, &[0xF0, 0x01, 0x02, 0xF3]);
+    }
+
+    #[test]
+    fn test_timed_on() {
+        let mut port = create_stub_port();
+
+        port.timed_on(1).unwrap();
+
+        assert_buf(
+            port,
+            &[
+                0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x00, 0x53, 0xF0, 0x00, 0x01, 0x57,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_timed_off() {
+        let mut port = create_stub_port();
+
+        port.timed_off(1).unwrap();
+
+        assert_buf(
+            port,
+            &[
+                0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x01, 0x53, 0xF0, 0x00, 0x01, 0x57,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_toggle() {
+        let mut port = create_stub_port();
+
+        port.toggle().unwrap();
+
+        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0x00, 0x00, 0x57]);
+    }
+
+    #[test]
+    fn test_jog() {
+        let mut port = create_stub_port();
+
+        port.jog().unwrap();
+
+        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x55, 0xF0, 0xA0, 0x01, 0x53]);
+    }
+
+    #[test]
+    fn test_timed_after_jog() {
+        let mut port = create_stub_port();
+
+        port.jog().unwrap();
+        assert!(port.jog);
+        port.timed_on(5).unwrap();
+        assert!(!port.jog);
+
+        assert_buf(
+            port,
+            &[
+                0xF0, 0xA0, 0x0C, 0x55, 0xF0, 0xA0, 0x01, 0x53, 0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0,
+                0x00, 0x53, 0xF0, 0x00, 0x05, 0x57,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_timed_off_after_jog() {
+        let recorder = Recorder::new();
+        let mut port = Port::recording(&recorder);
+        port.set_wiring(Wiring::NormallyOpen);
+
+        port.jog().unwrap();
+        port.timed_off(1).unwrap();
+        assert!(!port.jog);
+
+        // back in control mode before connecting and arming the timer
+        assert_eq!(
+            recorder.frames(),
+            [
+                [0xF0, 0xA0, 0x0C, 0x55],
+                [0xF0, 0xA0, 0x01, 0x53],
+                [0xF0, 0xA0, 0x0C, 0x54],
+                [0xF0, 0xA0, 0x01, 0x53],
+                [0xF0, 0x00, 0x01, 0x57],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_switch() {
+        let mask = |channels: &[u8], state| {
+            let recorder = Recorder::new();
+            let mut port = Port::recording(&recorder);
+            port.set_wiring(Wiring::NormallyOpen);
+            port.set_protocol(Protocol {
+                channels: 8,
+                ..Protocol::default()
+            });
+            port.switch(channels, state).map(|_| recorder.frames())
+        };
+
+        assert_eq!(
+            mask(&[1, 3, 5], RelayState::On).unwrap(),
+            [[0xF0, 0xA0, 0x0C, 0x54], [0xF0, 0x15, 0x01, 0x53]]
+        );
+        assert_eq!(
+            mask(&[8], RelayState::Off).unwrap()[1],
+            [0xF0, 0x80, 0x00, 0x53]
+        );
+        assert_eq!(
+            mask(&[1, 2, 3, 4, 5, 6, 7, 8], RelayState::On).unwrap()[1],
+            [0xF0, 0xFF, 0x01, 0x53]
+        );
+        assert_eq!(mask(&[2, 2], RelayState::On).unwrap()[1][1], 0x02);
+        assert!(mask(&[9], RelayState::On).is_err());
+        assert!(mask(&[0], RelayState::On).is_err());
+        assert!(mask(&[], RelayState::On).is_err());
+
+        // the board's channel count limits the mask
+        let mut port = create_stub_port();
+        assert!(port.switch(&[2], RelayState::On).is_err());
+    }
+
+    #[test]
+    fn test_restart() {
+        let mut port = create_stub_port();
+
+        port.restart().unwrap();
+
+        assert_buf(
+            port,
+            &[
+                0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x00, 0x53, 0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0,
+                0x01, 0x53,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_pulse() {
+        let mut port = create_stub_port();
+
+        port.pulse(Duration::from_millis(100)).unwrap();
+
+        assert_buf(
+            port,
+            &[
+                0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x01, 0x53, 0xF0, 0xA0, 0x00, 0x53,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_capture() {
+        let capture = Recorder::new();
+        let mut port = create_stub_port();
+        port.set_capture(Box::new(capture.clone()));
+
+        port.on().unwrap();
+
+        let written = String::from_utf8(capture.written()).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let (at, frame) = lines[1].split_once(' ').unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(at).is_ok());
+        assert_eq!(frame, "stub F0 A0 01 53");
+    }
+
+    #[test]
+    fn test_arm_failsafe() {
+        let mut port = create_stub_port();
+
+        port.arm_failsafe(30).unwrap();
+
+        assert_buf(
+            port,
+            &[
+                0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x01, 0x53, 0xF0, 0x00, 0x1E, 0x57,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_arm_failsafe_rejected() {
+        let mut port = create_stub_port();
+        assert!(port.arm_failsafe(0).is_err());
+
+        port.set_wiring(Wiring::NormallyClosed);
+        assert!(port.arm_failsafe(30).is_err());
+
+        assert_buf(port, &[]);
+    }
+
+    #[test]
+    fn test_write_retries() {
+        let stub = Stub::default().failing(2);
+        let mut port = stub.port();
+        port.set_write_retries(2);
+
+        port.on().unwrap();
+
+        assert_eq!(
+            stub.frames(),
+            [[0xF0, 0xA0, 0x0C, 0x54], [0xF0, 0xA0, 0x01, 0x53]]
+        );
+    }
+
+    #[test]
+    fn test_write_retries_partial() {
+        let stub = Stub::default().short_write(2).failing(1);
+        let mut port = stub.port();
+        port.set_write_retries(1);
+
+        port.on().unwrap();
+
+        // only the rest of the cut frame is sent again
+        assert_eq!(
+            stub.written(),
+            [0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x01, 0x53]
+        );
+    }
+
+    #[test]
+    fn test_write_retries_exhausted() {
+        let stub = Stub::default().failing(2);
+        let mut port = stub.port();
+        port.set_write_retries(1);
+
+        let err = port.on().unwrap_err();
+
+        assert!(format!("{:#}", err).contains("[F0, A0, 0C, 54] after 2 attempt(s)"));
+        assert!(matches!(err, RelayError::Io(_)));
+        assert!(stub.frames().is_empty());
+    }
+
+    #[test]
+    fn test_keepalive() {
+        let mut port = create_stub_port();
+
+        port.keepalive().unwrap();
+
+        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x54]);
+    }
+
+    #[test]
+    fn test_pulse_timing() {
+        let stub = Stub::default().timed();
+        let mut port = stub.port();
+
+        port.pulse(Duration::from_secs(60)).unwrap();
+
+        assert_eq!(stub.timeline(), [(0, 0x0C), (50, 0x01), (60_050, 0x00)]);
+    }
+
+    #[test]
+    fn test_jog_hold() {
+        let stub = Stub::default().timed();
+        let mut port = stub.port();
+
+        port.jog_hold(Duration::from_millis(500)).unwrap();
+
+        assert_eq!(stub.timeline(), [(0, 0x0C), (50, 0x01), (550, 0x00)]);
+
+        let recorder = Recorder::new();
+        let mut port = Port::recording(&recorder);
+        port.set_wiring(Wiring::NormallyOpen);
+        port.jog_hold(Duration::from_millis(1)).unwrap();
+        assert_eq!(
+            recorder.frames(),
+            [
+                [0xF0, 0xA0, 0x0C, 0x55],
+                [0xF0, 0xA0, 0x01, 0x53],
+                [0xF0, 0xA0, 0x00, 0x53]
+            ]
+        );
+        assert!(port.jog_hold(Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn test_inverted_pulse() {
+        let mut port = create_stub_port();
+
+        port.inverted_pulse(Duration::from_millis(100)).unwrap();
+
+        assert_buf(
+            port,
+            &[
+                0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x00, 0x53, 0xF0, 0xA0, 0x01, 0x53,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_blink() {
+        let mut port = create_stub_port();
+
+        port.blink(2, Duration::ZERO, Duration::ZERO).unwrap();
+
+        assert_buf(
+            port,
+            &[
+                0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x01, 0x53, 0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0,
+                0x00, 0x53, 0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x01, 0x53, 0xF0, 0xA0, 0x0C, 0x54,
+                0xF0, 0xA0, 0x00, 0x53,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_blink_timing() {
+        let stub = Stub::default().timed();
+        let mut port = stub.port();
+
+        port.blink(2, Duration::from_secs(1), Duration::from_secs(2))
+            .unwrap();
+
+        assert_eq!(
+            stub.timeline(),
+            [
+                (0, 0x0C),
+                (50, 0x01),
+                (1_100, 0x0C),
+                (1_150, 0x00),
+                (3_200, 0x0C),
+                (3_250, 0x01),
+                (4_300, 0x0C),
+                (4_350, 0x00)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_timed_frame_spacing() {
+        let stub = Stub::default().timed();
+        let mut port = stub.port();
+
+        port.timed_on(5).unwrap();
+
+        let times: Vec<u128> = stub.timeline().into_iter().map(|(at, _)| at).collect();
+        assert_eq!(times, [0, 50, 100]);
+    }
+
+    #[test]
+    fn test_blink_interrupted() {
+        let stub = Stub::default().interrupt_after(2);
+        let mut port = stub.port();
+
+        assert!(port
+            .blink(5, Duration::from_secs(60), Duration::from_secs(60))
+            .is_err());
+
+        assert_eq!(
+            stub.frames(),
+            [
+                [0xF0, 0xA0, 0x0C, 0x54],
+                [0xF0, 0xA0, 0x01, 0x53],
+                [0xF0, 0xA0, 0x0C, 0x54],
+                [0xF0, 0xA0, 0x00, 0x53]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interrupted() {
+        let stub = Stub::default().interrupt_after(1);
+        let mut port = stub.port();
+
+        let err = port.on().unwrap_err();
+        assert!(matches!(err, RelayError::Interrupted(_)));
+        assert_eq!(stub.frames().len(), 1);
+
+        port.force_off().unwrap();
+        assert_eq!(stub.frames().len(), 3);
+        assert!(port.off().is_err());
+
+        let stub = Stub::default().interrupt_after(2);
+        let mut port = stub.port();
+        assert!(port.pulse(Duration::from_secs(60)).is_err());
+        assert_eq!(stub.frames().len(), 4);
+        assert_eq!(stub.frames()[3], [0xF0, 0xA0, 0x00, 0x53]);
+
+        let stub = Stub::default().interrupt_after(2);
+        let mut port = stub.port();
+        let err = port.restart().unwrap_err();
+        assert!(matches!(err, RelayError::Interrupted(_)));
+        assert_eq!(stub.frames().len(), 2, "power stays disabled");
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let stub = Stub::default().interrupt_after(3);
+        let mut port = stub.port();
+
+        port.watchdog(10, 5).unwrap();
+
+        assert_eq!(
+            stub.frames(),
+            [
+                [0xF0, 0xA0, 0x0C, 0x54],
+                [0xF0, 0xA0, 0x01, 0x53],
+                [0xF0, 0x00, 0x0F, 0x57]
+            ]
+        );
+
+        let mut port = Stub::default().interrupt_after(3).port();
+        let mut refreshes = Vec::new();
+        port.watchdog_with(10, 5, |secs| refreshes.push(secs))
+            .unwrap();
+        assert_eq!(refreshes, [15]);
+    }
+
+    #[test]
+    fn test_watchdog_invalid() {
+        let mut port = create_stub_port();
+
+        assert!(port.watchdog(u16::MAX, 1).is_err());
+        assert!(port.watchdog(10, 0).is_err());
+    }
+
+    #[test]
+    fn test_channel_modes() {
+        for channel in 1..=4 {
+            let address = 0xA0 + channel - 1;
+
+            let mut port = create_multichannel_port(channel);
+            port.control_mode().unwrap();
+            assert_buf(port, &[0xF0, address, 0x0C, 0x54]);
+
+            let mut port = create_multichannel_port(channel);
+            port.jog_mode().unwrap();
+            assert_buf(port, &[0xF0, address, 0x0C, 0x55]);
+
+            // the timer is not addressed, it follows the mode frame of the channel
+            let mut port = create_multichannel_port(channel);
+            port.send_timer(5).unwrap();
+            assert_buf(port, &[0xF0, 0x00, 0x05, 0x57]);
+        }
+    }
+
+    #[test]
+    fn test_channel_on() {
+        let mut port = create_multichannel_port(2);
+
+        port.on().unwrap();
+
+        assert_buf(port, &[0xF0, 0xA1, 0x0C, 0x54, 0xF0, 0xA1, 0x01, 0x53]);
+    }
+
+    #[test]
+    fn test_channel_off() {
+        let mut port = create_multichannel_port(4);
+
+        port.off().unwrap();
+
+        assert_buf(port, &[0xF0, 0xA3, 0x0C, 0x54, 0xF0, 0xA3, 0x00, 0x53]);
+    }
+
+    #[test]
+    fn test_channel_toggle() {
+        let mut port = create_multichannel_port(3);
+
+        port.toggle().unwrap();
+
+        assert_buf(port, &[0xF0, 0xA2, 0x0C, 0x54, 0xF0, 0x00, 0x00, 0x57]);
+    }
+
+    /// the unaddressed timer follows the mode frame of the selected channel
+    #[test]
+    fn test_channel_timer() {
+        let mut port = create_multichannel_port(3);
+
+        port.timed_on(5).unwrap();
+
+        assert_buf(
+            port,
+            &[
+                0xF0, 0xA2, 0x0C, 0x54, 0xF0, 0xA2, 0x00, 0x53, 0xF0, 0x00, 0x05, 0x57,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_invalid_channel() {
+        let mut port = create_multichannel_port(1);
+
+        assert!(port.set_channel(0).is_err());
+        assert!(port.set_channel(5).is_err());
+        assert!(create_stub_port().set_channel(2).is_err());
+
+        // past the address byte, F0 35 0C 54 must not be sent
+        let mut port = create_stub_port();
+        port.set_protocol(Protocol {
+            channels: 200,
+            ..Protocol::default()
+        });
+        port.set_channel(150).unwrap();
+        assert!(matches!(port.on(), Err(RelayError::InvalidArgument(_))));
+        assert_buf(port, &[]);
+    }
+
+    #[test]
+    fn test_query() {
+        let mut port = create_stub_port();
+
+        port.send_query().unwrap();
+
+        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x56]);
+    }
+
+    #[test]
+    fn test_status() {
+        let mut port = create_responder_port(&[0xF0, 0xA0, 0x01, 0x53]);
+        assert_eq!(port.status().unwrap(), RelayState::On);
+
+        let mut port = create_responder_port(&[0xF0, 0xA0, 0x00, 0x53]);
+        assert_eq!(port.status().unwrap(), RelayState::Off);
+    }
+
+    #[test]
+    fn test_set() {
+        const QUERY: [u8; 4] = [0xF0, 0xA0, 0x0C, 0x56];
+
+        let stub = Stub::default().reading(&[0xF0, 0xA0, 0x01, 0x53]);
+        let mut port = stub.port();
+        assert!(!port.set(RelayState::On).unwrap());
+        assert_eq!(stub.written(), QUERY);
+
+        let stub = Stub::default().reading(&[0xF0, 0xA0, 0x01, 0x53]);
+        let mut port = stub.port();
+        assert!(port.set(RelayState::Off).unwrap());
+        assert_eq!(
+            stub.written()[4..],
+            [0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x00, 0x53]
+        );
 
-        None
+        // no answer, the frames are sent anyway
+        let stub = Stub::default().reading(&[]);
+        let mut port = stub.port();
+        assert!(port.set(RelayState::On).unwrap());
+        assert_eq!(
+            stub.written()[4..],
+            [0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x01, 0x53]
+        );
     }
 
-    fn write(&mut self, command: [u8; 4]) -> Result<()> {
-        debug!("{}: write {:02X?}", self.path, command);
-        self.port.write_all(&command)?;
-        thread::sleep(Duration::from_millis(50));
-        Ok(())
-    }
+    #[test]
+    fn test_invert() {
+        let inverted = || {
+            let recorder = Recorder::new();
+            let mut port = Port::recording(&recorder);
+            port.set_wiring(Wiring::NormallyOpen);
+            port.set_invert(true);
+            (port, recorder)
+        };
 
-    fn control_mode(&mut self) -> Result<()> {
-        let control_mode = [0xF0, 0xA0, 0x0C, 0x54];
-        self.write(control_mode)
-    }
+        let (mut port, recorder) = inverted();
+        port.on().unwrap();
+        port.off().unwrap();
+        assert_eq!(
+            recorder.frames(),
+            [
+                [0xF0, 0xA0, 0x0C, 0x54],
+                [0xF0, 0xA0, 0x00, 0x53],
+                [0xF0, 0xA0, 0x0C, 0x54],
+                [0xF0, 0xA0, 0x01, 0x53]
+            ]
+        );
 
-    fn jog_mode(&mut self) -> Result<()> {
-        let jog_mode = [0xF0, 0xA0, 0x0C, 0x55];
-        self.write(jog_mode)
-    }
+        let (mut port, recorder) = inverted();
+        port.timed_on(5).unwrap();
+        port.timed_off(5).unwrap();
+        assert_eq!(
+            recorder.frames(),
+            [
+                [0xF0, 0xA0, 0x0C, 0x54],
+                [0xF0, 0xA0, 0x01, 0x53],
+                [0xF0, 0x00, 0x05, 0x57],
+                [0xF0, 0xA0, 0x0C, 0x54],
+                [0xF0, 0xA0, 0x00, 0x53],
+                [0xF0, 0x00, 0x05, 0x57]
+            ]
+        );
 
-    fn send_timer(&mut self, timeout: u16) -> Result<()> {
-        let timeout = timeout.to_ne_bytes();
-        let timer = [0xF0, timeout[1], timeout[0], 0x57];
-        self.write(timer)
+        // inverted NC wiring switches like NO
+        let (mut port, recorder) = inverted();
+        port.set_wiring(Wiring::NormallyClosed);
+        port.on().unwrap();
+        assert_eq!(recorder.frames()[1], [0xF0, 0xA0, 0x01, 0x53]);
+        assert!(port.arm_failsafe(5).is_ok());
+
+        let (mut port, _) = inverted();
+        assert!(port.arm_failsafe(5).is_err());
+
+        let mut port = create_responder_port(&[0xF0, 0xA0, 0x01, 0x53]);
+        port.set_invert(true);
+        assert_eq!(port.status().unwrap(), RelayState::Off);
     }
 
-    fn send_action(&mut self, action: Action) -> Result<()> {
-        #[cfg(feature = "no-connected")]
-        let enable = match action {
-            Action::Connect => 0x01,
-            Action::Disconnect => 0x00,
-        };
-        #[cfg(feature = "nc-connected")]
-        let enable = match action {
-            Action::Connect => 0x00,
-            Action::Disconnect => 0x01,
+    #[test]
+    fn test_drain() {
+        let stale = || {
+            Stub::default()
+                .reading(&[0xF0, 0xA0, 0x01, 0x51])
+                .answering(Answer::Echo)
         };
-        let toggle = [0xF0, 0xA0, enable, 0x53];
-        self.write(toggle)
-    }
 
-    fn send_disconnect(&mut self) -> Result<()> {
-        self.send_action(Action::Disconnect)
+        let mut port = stale().port();
+        port.set_verify(true);
+        assert!(port.control_mode().is_err());
+
+        let mut port = stale().port();
+        port.set_verify(true);
+        port.set_drain(true);
+        port.control_mode().unwrap();
     }
 
-    fn send_connect(&mut self) -> Result<()> {
-        self.send_action(Action::Connect)
+    #[test]
+    fn test_drain_status() {
+        let stale = || {
+            Stub::default()
+                .reading(&[0xF0, 0xA0, 0x01, 0x53])
+                .answering(Answer::Frame([0xF0, 0xA0, 0x00, 0x53]))
+        };
+
+        let mut port = stale().port();
+        port.set_wiring(Wiring::NormallyOpen);
+        assert_eq!(port.status().unwrap(), RelayState::On);
+
+        let mut port = stale().port();
+        port.set_wiring(Wiring::NormallyOpen);
+        port.set_drain(true);
+        assert_eq!(port.status().unwrap(), RelayState::Off);
     }
-}
 
-impl Port {
-    const VID: u16 = 0x1a86;
-    const PID: u16 = 0x7523;
+    #[test]
+    fn test_drain_remote() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let mut port = OpenOptions::new().remote(&address).open().unwrap();
+        port.set_wiring(Wiring::NormallyOpen);
+        port.set_drain(true);
+
+        let (mut device, _) = listener.accept().unwrap();
+        device.write_all(&[0xF0, 0xA0, 0x01, 0x53]).unwrap();
+        let device = std::thread::spawn(move || {
+            let mut query = [0; 4];
+            device.read_exact(&mut query).unwrap();
+            device.write_all(&[0xF0, 0xA0, 0x00, 0x53]).unwrap();
+        });
+
+        assert_eq!(port.status().unwrap(), RelayState::Off);
+        device.join().unwrap();
+    }
 
-    /// open the tty port
-    pub fn open(tty_path: Option<&str>) -> Result<Port> {
-        let path;
+    #[test]
+    fn test_remote_closed() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
 
-        if let Some(p) = tty_path {
-            debug!("try to open serial port by path {}", p);
-            path = p.to_string();
-        } else {
-            debug!(
-                "try to find serial port with vid={} pid={}",
-                Self::VID,
-                Self::PID
-            );
-            path = Port::find_tty(Self::VID, Self::PID).with_context(|| {
-                format!(
-                    "Compatible TTY devices is not found (with vid:pid {:04x}:{:04x})",
-                    Self::VID,
-                    Self::PID
-                )
-            })?;
-            debug!("serial port found in path {}", path);
-        }
-
-        let port = serialport::new(&path, 9600)
-            .timeout(Duration::from_millis(10))
+        let mut port = OpenOptions::new()
+            .remote(&address)
+            .timeout(Duration::from_secs(60))
             .open()
-            .ok()
-            .with_context(|| format!("failed to open tty {}", path))?;
+            .unwrap();
+        port.set_wiring(Wiring::NormallyOpen);
+        drop(listener.accept().unwrap());
+
+        let started = Instant::now();
+        let err = port.status().unwrap_err();
+        assert!(matches!(err, RelayError::Io(_)));
+        assert!(err.to_string().contains("connection closed"));
+        assert!(started.elapsed() < Duration::from_secs(60));
+    }
 
-        debug!("serial port was opened");
+    #[test]
+    fn test_probe() {
+        let recorder = Recorder::new();
+        let mut port = Port::recording(&recorder);
+        port.set_frame_delay(Duration::from_millis(20));
+        port.probe(false).unwrap();
+        assert_eq!(recorder.frames(), [[0xF0, 0xA0, 0x0C, 0x54]]);
+
+        // the recorder never answers
+        let err = port.probe(true).unwrap_err();
+        assert!(
+            err.to_string().starts_with("memory: unreachable: "),
+            "{}",
+            err
+        );
+        assert!(!port.verify);
+        assert_eq!(port.frame_delay, Duration::from_millis(20));
 
-        Ok(Port {
-            port: Box::new(port),
-            path,
-        })
+        let mut port = create_echo_port();
+        port.probe(true).unwrap();
     }
 
-    /// start immediately
-    pub fn on(&mut self) -> Result<()> {
-        debug!("on command");
-        self.control_mode()?;
-        self.send_connect()
+    #[test]
+    fn test_raw() {
+        let stub = Stub::default().reading(&[0xF0, 0x01, 0x02, 0x58]);
+        let mut port = stub.port();
+        port.write_raw([0xF0, 0xA0, 0x0C, 0x58]).unwrap();
+        assert_eq!(stub.written(), [0xF0, 0xA0, 0x0C, 0x58]);
+        assert_eq!(port.read_raw().unwrap(), [0xF0, 0x01, 0x02, 0x58]);
+        assert!(port.read_raw().is_err());
+
+        // a wrong checksum is sent as given
+        let recorder = Recorder::new();
+        let mut port = Port::recording(&recorder);
+        port.set_protocol(Protocol {
+            checksum: true,
+            ..Protocol::default()
+        });
+        port.write_raw([0xF0, 0xA0, 0x0C, 0x54]).unwrap();
+        assert_eq!(recorder.frames(), [[0xF0, 0xA0, 0x0C, 0x54]]);
     }
 
-    /// stop immediately
-    pub fn off(&mut self) -> Result<()> {
-        debug!("off command");
-        self.control_mode()?;
-        self.send_disconnect()
-    }
+    #[test]
+    fn test_status_normally_closed() {
+        let mut port = create_responder_port(&[0xF0, 0xA0, 0x00, 0x53]);
+        port.set_wiring(Wiring::NormallyClosed);
 
-    /// start after n seconds
-    pub fn timed_on(&mut self, timeout: u16) -> Result<()> {
-        debug!("on after {} seconds", timeout);
-        self.off()?;
-        self.send_timer(timeout)
+        assert_eq!(port.status().unwrap(), RelayState::On);
     }
 
-    /// stop after n seconds
-    pub fn timed_off(&mut self, timeout: u16) -> Result<()> {
-        debug!("off after {} seconds", timeout);
-        self.on()?;
-        self.send_timer(timeout)
+    #[test]
+    fn test_status_short_response() {
+        let mut port = create_responder_port(&[0xF0, 0xA0]);
+
+        assert!(port.status().is_err());
     }
 
-    /// toggle power
-    pub fn toggle(&mut self) -> Result<()> {
-        debug!("toggle command");
-        self.control_mode()?;
-        self.send_timer(0)
+    #[test]
+    fn test_status_no_response() {
+        let mut port = create_responder_port(&[]);
+
+        let started = Instant::now();
+        let err = port.status().unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(matches!(err, RelayError::Timeout(_)));
+        assert!(err.to_string().contains("received 0 of 4 bytes"), "{}", err);
     }
 
-    /// quick toggle power
-    pub fn jog(&mut self) -> Result<()> {
-        debug!("jog command");
-        self.jog_mode()?;
-        self.send_connect()
+    #[test]
+    fn test_status_garbage() {
+        let mut port = create_responder_port(&[0xDE, 0xAD, 0xBE, 0xEF]);
+
+        assert!(port.status().is_err());
     }
 
-    /// software restart
-    pub fn restart(&mut self) -> Result<()> {
-        debug!("restart command");
-        self.off()?;
-        std::thread::sleep(Duration::from_secs(1));
-        self.on()
+    #[test]
+    fn test_status_v2() {
+        let v2 = Protocol {
+            checksum: true,
+            ..Protocol::default()
+        };
+
+        let mut port = create_responder_port(&[0xF0, 0xA0, 0x01, 0x91]);
+        port.set_protocol(v2.clone());
+        assert_eq!(port.status().unwrap(), RelayState::On);
+
+        let mut port = create_responder_port(&[0xF0, 0xA0, 0x01, 0x53]);
+        port.set_protocol(v2);
+        let err = port.status().unwrap_err();
+        assert!(format!("{}", err).contains("checksum mismatch"));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Cursor;
+    #[test]
+    fn test_verify_v2() {
+        let mut port = create_echo_port();
+        port.set_protocol(Protocol {
+            checksum: true,
+            ..Protocol::default()
+        });
+        port.set_verify(true);
+
+        assert!(port.on().is_ok());
+    }
 
-    fn create_stub_port() -> Port {
-        let buffer: Vec<u8> = Vec::with_capacity(8);
-        let cursor = Cursor::new(buffer);
-        let port = Box::new(cursor);
-        Port {
-            port,
-            path: "stub".to_string(),
-        }
+    #[test]
+    fn test_recording() {
+        let recorder = Recorder::new();
+        let mut port = Port::recording(&recorder);
+        port.set_wiring(Wiring::NormallyOpen);
+
+        port.timed_on(5).unwrap();
+
+        assert_eq!(
+            recorder.frames(),
+            [
+                [0xF0, 0xA0, 0x0C, 0x54],
+                [0xF0, 0xA0, 0x00, 0x53],
+                [0xF0, 0x00, 0x05, 0x57]
+            ]
+        );
     }
 
-    fn assert_buf(port: Port, expected: &[u8]) {
-        let ptr: Box<Cursor<Vec<u8>>> = unsafe { transmute::transmute(port.port) };
-        assert_eq!(ptr.get_ref().as_slice(), expected);
+    #[test]
+    fn test_from_stream() {
+        let stream = Recorder::new();
+        let mut port = Port::from_stream(Box::new(stream.clone()), "embedded");
+        port.set_wiring(Wiring::NormallyOpen);
+        assert_eq!(port.path(), "embedded");
+        assert!(port.recorder().is_none());
+
+        port.off().unwrap();
+
+        assert_eq!(
+            stream.written(),
+            [0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x00, 0x53]
+        );
     }
 
     #[test]
-    fn test_control_mode() {
+    fn test_custom_prefix() {
         let mut port = create_stub_port();
+        port.set_protocol(Protocol {
+            prefix: 0xA0,
+            ..Protocol::default()
+        });
 
-        port.control_mode().unwrap();
+        port.on().unwrap();
+        port.send_timer(5).unwrap();
 
-        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x54]);
+        assert_buf(
+            port,
+            &[
+                0xA0, 0xA0, 0x0C, 0x54, 0xA0, 0xA0, 0x01, 0x53, 0xA0, 0x00, 0x05, 0x57,
+            ],
+        );
     }
 
     #[test]
-    fn test_jog_mode() {
+    fn test_reset_after_jog() {
         let mut port = create_stub_port();
 
-        port.jog_mode().unwrap();
+        port.jog().unwrap();
+        port.reset().unwrap();
+        port.timed_off(1).unwrap();
 
-        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x55]);
+        assert_buf(
+            port,
+            &[
+                0xF0, 0xA0, 0x0C, 0x55, 0xF0, 0xA0, 0x01, 0x53, // jog
+                0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x00, 0x53, // reset
+                0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x01, 0x53, 0xF0, 0x00, 0x01, 0x57,
+            ],
+        );
     }
 
     #[test]
-    fn test_timer() {
+    fn test_power_guard() {
         let mut port = create_stub_port();
+        {
+            let mut guard = port.power_guard();
+            guard.on().unwrap();
+        }
+        assert_buf(
+            port,
+            &[
+                0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x01, 0x53, // on
+                0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x00, 0x53, // off on drop
+            ],
+        );
 
-        port.send_timer(0).unwrap();
+        let mut port = create_stub_port();
+        let mut guard = port.power_guard();
+        guard.on().unwrap();
+        guard.disarm();
+        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x01, 0x53]);
+    }
 
-        assert_buf(port, &[0xF0, 0x00, 0x00, 0x57]);
+    #[test]
+    fn test_identify() {
+        let mut port = create_responder_port(&[0xF0, 0x03, 0x04, 0x58]);
+        let identity = port.identify().unwrap();
+        assert_eq!(
+            identity,
+            Identity {
+                firmware: Some(3),
+                channels: Some(4)
+            }
+        );
+        assert_eq!(identity.to_string(), "firmware: 3\nchannels: 4");
 
-        let mut port = create_stub_port();
+        // a status answer is not an identity
+        let mut port = create_responder_port(&[0xF0, 0xA0, 0x01, 0x53]);
+        assert_eq!(port.identify().unwrap(), Identity::default());
 
-        port.send_timer(u16::MAX).unwrap();
+        let mut silent = Port::open_mock();
+        let identity = silent.identify().unwrap();
+        assert_eq!(identity.to_string(), "firmware: unknown\nchannels: unknown");
+    }
 
-        assert_buf(port, &[0xF0, 0xFF, 0xFF, 0x57]);
+    #[test]
+    fn test_open_error() {
+        let denied = serialport::Error::new(
+            serialport::ErrorKind::Io(io::ErrorKind::PermissionDenied),
+            "Permission denied",
+        );
+        assert_eq!(
+            open_error("/dev/ttyUSB0", &denied).to_string(),
+            "failed to open tty /dev/ttyUSB0: Permission denied \
+             (add your user to the dialout or uucp group and log in again)"
+        );
+
+        let busy = serialport::Error::new(serialport::ErrorKind::NoDevice, "Device busy");
+        assert_eq!(
+            open_error("/dev/ttyUSB0", &busy).to_string(),
+            "failed to open tty /dev/ttyUSB0: Device busy"
+        );
     }
 
     #[test]
-    fn test_connect() {
-        let mut port = create_stub_port();
+    fn test_open_mock() {
+        let mut port = Port::open_mock();
+        port.set_wiring(Wiring::NormallyOpen);
 
-        port.send_connect().unwrap();
+        port.off().unwrap();
 
-        assert_buf(port, &[0xF0, 0xA0, 0x01, 0x53]);
+        assert_eq!(port.path(), "mock");
+        assert_eq!(
+            port.recorder().unwrap().frames(),
+            [[0xF0, 0xA0, 0x0C, 0x54], [0xF0, 0xA0, 0x00, 0x53]]
+        );
     }
 
     #[test]
-    fn test_disconnect() {
+    fn test_dry_run() {
+        let mut port = OpenOptions::new().dry_run(true).open().unwrap();
+        assert!(port.dry_run);
+        port.timed_on(1).unwrap();
+
         let mut port = create_stub_port();
+        port.dry_run = true;
+        port.timed_on(1).unwrap();
 
-        port.send_disconnect().unwrap();
+        assert_buf(port, &[]);
+    }
 
-        assert_buf(port, &[0xF0, 0xA0, 0x00, 0x53]);
+    #[test]
+    fn test_detect_baud() {
+        let (port, baud) = OpenOptions::new()
+            .dry_run(true)
+            .detect_baud(|_| unreachable!("dry-run is not probed"))
+            .unwrap();
+        assert!(port.dry_run);
+        assert_eq!(baud, Port::BAUD);
+
+        let err = OpenOptions::new()
+            .remote("relay:2000")
+            .detect_baud(|port| Ok(port.probe(true).map(drop)?))
+            .err()
+            .unwrap();
+        assert!(matches!(err, RelayError::InvalidArgument(_)));
     }
 
     #[test]
-    fn test_on() {
-        let mut port = create_stub_port();
+    fn test_detect_baud_rates() {
+        let cache = env::temp_dir().join(format!("tty_relay_baud_{}", std::process::id()));
+        let options = OpenOptions::new().baud_cache(&cache);
+
+        // the board only echoes at `answering`
+        let detect = |answering: u32| {
+            let mut opened = Vec::new();
+            let result = options.detect_baud_with(
+                "stub",
+                |baud| {
+                    opened.push(baud);
+                    let mut port = match baud == answering {
+                        true => create_echo_port(),
+                        false => Port::recording(&Recorder::new()),
+                    };
+                    port.set_frame_delay(Duration::ZERO);
+                    Ok(port)
+                },
+                |port| Ok(port.probe(true).map(drop)?),
+            );
+            (result.map(|(_, baud)| baud), opened)
+        };
 
-        port.on().unwrap();
+        let (baud, opened) = detect(38400);
+        assert_eq!(baud.unwrap(), 38400);
+        assert_eq!(opened, [9600, 19200, 38400]);
+        assert_eq!(cache::load_value(&cache, "stub"), Some("38400".to_string()));
+
+        // the remembered rate is tried first
+        let (baud, opened) = detect(38400);
+        assert_eq!(baud.unwrap(), 38400);
+        assert_eq!(opened, [38400]);
+
+        let (baud, opened) = detect(115200);
+        assert_eq!(baud.unwrap(), 115200);
+        assert_eq!(opened, [38400, 9600, 19200, 57600, 115200]);
+
+        let (baud, opened) = detect(1200);
+        assert!(matches!(baud, Err(RelayError::NoAcknowledgement(_))));
+        assert_eq!(opened.len(), Port::BAUD_RATES.len());
+        assert_eq!(
+            cache::load_value(&cache, "stub"),
+            Some("115200".to_string())
+        );
 
-        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x01, 0x53]);
+        fs::remove_file(cache).unwrap();
     }
 
     #[test]
-    fn test_off() {
-        let mut port = create_stub_port();
+    fn test_wait_for_device() {
+        let started = Instant::now();
+        let err = OpenOptions::new()
+            .vid(0xdead)
+            .pid(0xbeef)
+            .wait_for_device(Duration::from_millis(5), Some(Duration::from_millis(30)))
+            .resolve()
+            .unwrap_err();
+
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        assert!(matches!(err, RelayError::DeviceNotFound(_)));
+        assert!(err.to_string().contains("within"));
+    }
 
-        port.off().unwrap();
+    #[test]
+    fn test_port_env() {
+        let missing = || OpenOptions::new().env_port("/dev/NOT_FOUND");
+
+        let path = missing().resolve_path(true);
+        let tty = missing().tty("/dev/tty_flag").resolve_path(true);
+        let profile = missing()
+            .default_ids(Some(0x0403), Some(0x6001))
+            .resolve_path(true);
+
+        for path in [path, profile] {
+            assert!(path
+                .unwrap_err()
+                .to_string()
+                .contains(OpenOptions::PORT_ENV));
+        }
+        assert_eq!(tty.unwrap(), ("/dev/tty_flag".to_string(), false));
+
+        let com = OpenOptions::new().env_port("COM3").resolve_path(true);
+        assert_eq!(com.unwrap(), ("COM3".to_string(), false));
+        #[cfg(unix)]
+        {
+            let bare = OpenOptions::new().env_port("null").resolve_path(true);
+            assert_eq!(bare.unwrap(), ("/dev/null".to_string(), false));
+        }
+    }
 
-        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x00, 0x53]);
+    #[test]
+    fn test_resolve_tty() {
+        assert_eq!(resolve_tty("COM3"), "COM3");
+        assert_eq!(resolve_tty(r"\\.\COM10"), r"\\.\COM10");
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_timed_on() {
-        let mut port = create_stub_port();
+    fn test_resolve_tty_unix() {
+        assert_eq!(resolve_tty("null"), "/dev/null");
+        assert_eq!(resolve_tty("/dev/NOT_FOUND"), "/dev/NOT_FOUND");
+
+        let alias = env::temp_dir().join(format!("tty_relay_alias_{}", std::process::id()));
+        std::os::unix::fs::symlink("/dev/null", &alias).unwrap();
+        assert_eq!(resolve_tty(alias.to_str().unwrap()), "/dev/null");
+        fs::remove_file(alias).unwrap();
+    }
 
-        port.timed_on(1).unwrap();
+    #[test]
+    fn test_port_cache() {
+        let dir = env::temp_dir().join(format!("tty_relay_port_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cache = dir.join("port");
+        let other = dir.join("ttyUSB1").display().to_string();
+        fs::write(&other, "").unwrap();
+        cache::store(&cache, "1a86:7523 *", &other).unwrap();
+
+        let board = dir.join("ttyUSB0").display().to_string();
+        fs::write(&board, "").unwrap();
+        let options = OpenOptions::new()
+            .vid(0x1a86)
+            .pid(0x7523)
+            .cache(&cache)
+            .devices(vec![device(&board, Some("A5"))]);
+
+        // the cached path is another device now
+        assert_eq!(options.resolve_path(true).unwrap(), (board.clone(), false));
+        assert_eq!(cache::load(&cache, "1a86:7523 *"), Some(board.clone()));
+        assert_eq!(options.resolve_path(true).unwrap(), (board.clone(), true));
+        assert_eq!(options.resolve_path(false).unwrap(), (board.clone(), false));
+
+        // the board is not a tty, the cache is dropped
+        assert!(options.open().is_err());
+        assert!(!cache.exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
 
-        assert_buf(
-            port,
-            &[
-                0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x00, 0x53, 0xF0, 0x00, 0x01, 0x57,
-            ],
-        );
+    #[cfg(unix)]
+    #[test]
+    fn test_port_cache_sysfs() {
+        let dir = env::temp_dir().join(format!("tty_relay_sysfs_port_{}", std::process::id()));
+        let usb = dir.join("devices/1-1");
+        let class = dir.join("class/tty");
+        fs::create_dir_all(usb.join("1-1:1.0")).unwrap();
+        fs::create_dir_all(class.join("ttyUSB0")).unwrap();
+        fs::write(usb.join("idVendor"), "1a86\n").unwrap();
+        fs::write(usb.join("idProduct"), "7523\n").unwrap();
+        fs::write(usb.join("serial"), "A5\n").unwrap();
+        std::os::unix::fs::symlink(usb.join("1-1:1.0"), class.join("ttyUSB0/device")).unwrap();
+
+        let board = dir.join("ttyUSB0").display().to_string();
+        fs::write(&board, "").unwrap();
+        let cache = dir.join("port");
+        cache::store(&cache, "1a86:7523 *", &board).unwrap();
+
+        // nothing is enumerated while the cached tty has the ids
+        let options = OpenOptions::new()
+            .vid(Port::VID)
+            .pid(Port::PID)
+            .cache(&cache)
+            .sysfs(&class)
+            .devices(Vec::new());
+        assert_eq!(options.resolve_path(true).unwrap(), (board.clone(), true));
+        assert!(matches!(
+            options.resolve(),
+            Err(RelayError::DeviceNotFound(_))
+        ));
+
+        // another serial is looked up by the enumeration
+        cache::store(&cache, "1a86:7523 B7", &board).unwrap();
+        let err = options.serial("B7").resolve_path(true).unwrap_err();
+        assert!(matches!(
+            RelayError::find(&err),
+            Some(RelayError::DeviceNotFound(_))
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
     }
 
     #[test]
-    fn test_timed_off() {
-        let mut port = create_stub_port();
+    fn test_port_cache_ambiguous() {
+        let cache = env::temp_dir().join(format!("tty_relay_ambiguous_{}", std::process::id()));
+        cache::store(&cache, "1a86:7523 *", "/").unwrap();
+
+        // the cached path has no USB ids, the enumeration finds a second board
+        let options = OpenOptions::new()
+            .vid(Port::VID)
+            .cache(&cache)
+            .devices(vec![
+                device("/", Some("A5")),
+                device("/dev/ttyUSB1", Some("B7")),
+            ]);
+        let err = options.resolve_path(true).unwrap_err();
+        assert!(matches!(
+            RelayError::find(&err),
+            Some(RelayError::AmbiguousDevice(_))
+        ));
+
+        fs::remove_file(cache).unwrap();
+    }
 
-        port.timed_off(1).unwrap();
+    #[test]
+    fn test_resolve_enumerates() {
+        let cache = env::temp_dir().join(format!("tty_relay_resolve_{}", std::process::id()));
+        cache::store(&cache, "1a86:7523 *", "/").unwrap();
+
+        let options = OpenOptions::new()
+            .vid(Port::VID)
+            .cache(&cache)
+            .devices(vec![device("/dev/ttyUSB1", Some("B7"))]);
+        assert_eq!(options.resolve().unwrap(), "/dev/ttyUSB1");
+
+        let err = options
+            .devices(vec![
+                device("/dev/ttyUSB0", Some("A5")),
+                device("/dev/ttyUSB1", Some("B7")),
+            ])
+            .resolve()
+            .unwrap_err();
+        assert!(matches!(err, RelayError::AmbiguousDevice(_)));
+
+        fs::remove_file(cache).unwrap();
+    }
 
-        assert_buf(
-            port,
-            &[
-                0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x01, 0x53, 0xF0, 0x00, 0x01, 0x57,
-            ],
-        );
+    #[test]
+    fn test_open_retries() {
+        let port = OpenOptions::new()
+            .tty("/dev/NOT_FOUND")
+            .retries(2, Duration::ZERO)
+            .open();
+
+        assert!(port.is_err());
     }
 
     #[test]
-    fn test_toggle() {
+    fn test_verify() {
+        let mut port = create_echo_port();
+        port.set_verify(true);
+
+        port.on().unwrap();
+    }
+
+    #[test]
+    fn test_verify_no_acknowledgement() {
         let mut port = create_stub_port();
+        port.set_verify(true);
 
-        port.toggle().unwrap();
+        let err = port.on().unwrap_err();
 
-        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0x00, 0x00, 0x57]);
+        assert!(err.to_string().contains("no acknowledgement from relay"));
     }
 
     #[test]
-    fn test_jog() {
-        let mut port = create_stub_port();
+    fn test_open_remote() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
 
-        port.jog().unwrap();
+        let mut port = OpenOptions::new().remote(&address).open().unwrap();
+        port.set_wiring(Wiring::NormallyOpen);
+        let (mut device, _) = listener.accept().unwrap();
 
-        assert_buf(port, &[0xF0, 0xA0, 0x0C, 0x55, 0xF0, 0xA0, 0x01, 0x53]);
+        port.on().unwrap();
+
+        let mut frames = [0; 8];
+        device.read_exact(&mut frames).unwrap();
+        assert_eq!(frames, [0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x01, 0x53]);
     }
 
     #[test]
-    fn test_restart() {
-        let mut port = create_stub_port();
+    fn test_remote_no_response() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
 
-        port.restart().unwrap();
+        let mut port = OpenOptions::new().remote(&address).open().unwrap();
+        let _device = listener.accept().unwrap();
 
-        assert_buf(
-            port,
-            &[
-                0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0, 0x00, 0x53, 0xF0, 0xA0, 0x0C, 0x54, 0xF0, 0xA0,
-                0x01, 0x53,
-            ],
-        );
+        let err = port.status().unwrap_err();
+        assert!(matches!(err, RelayError::Timeout(_)));
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let err = create_stub_port().set_channel(2).unwrap_err();
+        assert!(matches!(err, RelayError::InvalidArgument(_)));
+
+        let err = OpenOptions::new()
+            .tty("/dev/NOT_FOUND")
+            .open()
+            .err()
+            .unwrap();
+        assert!(matches!(err, RelayError::OpenFailed(_)));
+
+        let err = OpenOptions::new()
+            .select_device(Some(666), Some(666))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(RelayError::DeviceNotFound(_))
+        ));
     }
 
     #[test]
@@ -346,10 +3277,118 @@ mod tests {
         assert!(port.is_err());
     }
 
+    #[test]
+    fn test_usb_id() {
+        assert_eq!(usb_id(Some(0x1a86), Some(0x7523)), "1a86:7523");
+        assert_eq!(usb_id(Some(0x10c4), None), "10c4:*");
+        assert_eq!(usb_id(None, Some(0xea60)), "*:ea60");
+    }
+
     #[test]
     fn test_find() {
-        let port = Port::find_tty(666, 666);
+        let port = OpenOptions::new().select_device(Some(666), Some(666));
+
+        assert!(port.is_err());
+
+        let port = OpenOptions::new()
+            .serial("NOT_FOUND")
+            .select_device(Some(666), Some(666));
+
+        assert!(port.is_err());
+    }
+
+    fn device(port: &str, serial: Option<&str>) -> DeviceInfo {
+        DeviceInfo {
+            port: port.to_string(),
+            vid: Port::VID,
+            pid: Port::PID,
+            serial: serial.map(str::to_string),
+            manufacturer: None,
+            product: None,
+        }
+    }
+
+    #[test]
+    fn test_select_device() {
+        let ids = (Some(Port::VID), Some(Port::PID));
+        let one = vec![device("/dev/ttyUSB0", None)];
+        assert_eq!(
+            select_device(one, ids.0, ids.1, None).unwrap(),
+            "/dev/ttyUSB0"
+        );
+
+        let two = vec![
+            device("/dev/ttyUSB0", Some("A")),
+            device("/dev/ttyUSB1", Some("B")),
+        ];
+        let err = select_device(two.clone(), ids.0, ids.1, None).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(RelayError::AmbiguousDevice(_))
+        ));
+        assert!(err
+            .to_string()
+            .contains("/dev/ttyUSB0 (serial A), /dev/ttyUSB1 (serial B)"));
+
+        assert_eq!(
+            select_device(two.clone(), ids.0, ids.1, Some("B")).unwrap(),
+            "/dev/ttyUSB1"
+        );
+
+        let err = select_device(two, ids.0, ids.1, Some("C")).unwrap_err();
+        assert!(err.to_string().ends_with("found serial numbers: A, B"));
+    }
+
+    #[test]
+    fn test_select_device_ambiguous() {
+        let ids = (Some(Port::VID), Some(Port::PID));
+
+        // many CH340 clones carry no serial number
+        let unnamed = vec![device("/dev/ttyUSB0", None), device("/dev/ttyUSB1", None)];
+        let err = select_device(unnamed, ids.0, ids.1, None).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("/dev/ttyUSB0 (serial <none>), /dev/ttyUSB1 (serial <none>)"));
+
+        // and some share a fixed one, the serial number doesn't tell them apart
+        let twins = vec![
+            device("/dev/ttyUSB0", Some("A")),
+            device("/dev/ttyUSB1", Some("A")),
+            device("/dev/ttyUSB2", Some("B")),
+        ];
+        let err = select_device(twins, ids.0, ids.1, Some("A")).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(RelayError::AmbiguousDevice(_))
+        ));
+        assert!(err
+            .to_string()
+            .ends_with("select one by serial number or tty path"));
+    }
 
-        assert!(port.is_none());
+    #[test]
+    fn test_select_device_usb_ids() {
+        let ftdi = DeviceInfo {
+            vid: 0x0403,
+            pid: 0x6001,
+            ..device("/dev/ttyUSB1", None)
+        };
+        let devices = vec![device("/dev/ttyUSB0", None), ftdi];
+
+        let select = |vid, pid| select_device(devices.clone(), vid, pid, None);
+        assert_eq!(select(Some(0x0403), Some(0x6001)).unwrap(), "/dev/ttyUSB1");
+        assert_eq!(
+            select(Some(Port::VID), Some(Port::PID)).unwrap(),
+            "/dev/ttyUSB0"
+        );
+        // only the given id is matched
+        assert_eq!(select(None, Some(0x6001)).unwrap(), "/dev/ttyUSB1");
+
+        let err = select(Some(0x10c4), Some(0xea60)).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(RelayError::DeviceNotFound(_))
+        ));
+        assert!(err.to_string().contains("10c4:ea60"), "{}", err);
     }
 }
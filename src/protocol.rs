@@ -0,0 +1,183 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use crate::error::RelayError;
+use anyhow::{bail, Result};
+use log::warn;
+
+/// longest timer the stock firmware handles, larger values roll over
+pub const MAX_TIMER_SECONDS: u16 = 18 * 60 * 60;
+
+/// unit of the timer value in the frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimerUnit {
+    /// whole seconds (stock firmware)
+    #[default]
+    Seconds,
+    /// tenths of a second
+    Deciseconds,
+}
+
+impl TimerUnit {
+    fn per_second(self) -> u16 {
+        match self {
+            TimerUnit::Seconds => 1,
+            TimerUnit::Deciseconds => 10,
+        }
+    }
+}
+
+/// most relays on a board, like the 8 of the bitmask frame
+pub const MAX_CHANNELS: u8 = 8;
+
+/// leading byte of every frame on the stock firmware
+pub const PREFIX: u8 = 0xF0;
+
+/// frame layout of a relay board
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Protocol {
+    /// leading byte of every frame
+    pub prefix: u8,
+    /// number of relays on the board
+    pub channels: u8,
+    /// index of the frame byte which carries the channel address
+    pub channel_byte: usize,
+    /// v2 firmware, the last frame byte is a checksum instead of the command code
+    pub checksum: bool,
+    /// longest timer in seconds accepted by the firmware
+    pub max_timer: u16,
+    /// unit the firmware counts the timer in
+    pub timer_unit: TimerUnit,
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Protocol {
+            prefix: PREFIX,
+            channels: 1,
+            channel_byte: 1,
+            checksum: false,
+            max_timer: MAX_TIMER_SECONDS,
+            timer_unit: TimerUnit::Seconds,
+        }
+    }
+}
+
+impl Protocol {
+    /// sum of the first three bytes
+    pub(crate) fn checksum(frame: &[u8; 4]) -> u8 {
+        frame[..3].iter().fold(0, |sum, b| sum.wrapping_add(*b))
+    }
+
+    /// substitute the checksum when enabled
+    pub(crate) fn seal(&self, mut frame: [u8; 4]) -> [u8; 4] {
+        if self.checksum {
+            frame[3] = Self::checksum(&frame);
+        }
+        frame
+    }
+
+    /// timer value sent to the firmware, checked against the firmware maximum
+    ///
+    /// `clamp` caps too long timers instead of failing
+    pub(crate) fn timer(&self, seconds: u16, clamp: bool) -> Result<u16> {
+        let scale = self.timer_unit.per_second();
+        let limit = self.max_timer.min(u16::MAX / scale);
+
+        let seconds = if seconds <= limit {
+            seconds
+        } else if clamp {
+            warn!("timer of {} seconds clamped to {} seconds", seconds, limit);
+            limit
+        } else {
+            bail!(RelayError::InvalidArgument(format!(
+                "timer of {} seconds exceeds the firmware maximum of {} seconds, use --clamp to cap it",
+                seconds, limit
+            )));
+        };
+
+        Ok(seconds * scale)
+    }
+
+    /// check the checksum of a received frame when enabled
+    pub(crate) fn is_valid(&self, frame: &[u8; 4]) -> bool {
+        !self.checksum || frame[3] == Self::checksum(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(Protocol::checksum(&[0xF0, 0xA0, 0x0C, 0x00]), 0x9C);
+        assert_eq!(Protocol::checksum(&[0x01, 0x02, 0x03, 0xFF]), 0x06);
+    }
+
+    #[test]
+    fn test_seal() {
+        let frame = [0xF0, 0xA0, 0x0C, 0x54];
+        assert_eq!(Protocol::default().seal(frame), frame);
+
+        let v2 = Protocol {
+            checksum: true,
+            ..Protocol::default()
+        };
+        assert_eq!(v2.seal(frame), [0xF0, 0xA0, 0x0C, 0x9C]);
+        assert!(v2.is_valid(&[0xF0, 0xA0, 0x0C, 0x9C]));
+        assert!(!v2.is_valid(&frame));
+    }
+
+    #[test]
+    fn test_timer_limit() {
+        let protocol = Protocol::default();
+        assert_eq!(protocol.timer(0, false).unwrap(), 0);
+        assert_eq!(
+            protocol.timer(MAX_TIMER_SECONDS, false).unwrap(),
+            MAX_TIMER_SECONDS
+        );
+        assert!(protocol.timer(MAX_TIMER_SECONDS + 1, false).is_err());
+        assert_eq!(
+            protocol.timer(MAX_TIMER_SECONDS + 1, true).unwrap(),
+            MAX_TIMER_SECONDS
+        );
+        assert_eq!(protocol.timer(u16::MAX, true).unwrap(), MAX_TIMER_SECONDS);
+
+        let short = Protocol {
+            max_timer: 10,
+            ..Protocol::default()
+        };
+        assert_eq!(short.timer(10, false).unwrap(), 10);
+        assert!(short.timer(11, false).is_err());
+        assert_eq!(short.timer(1000, true).unwrap(), 10);
+    }
+
+    #[test]
+    fn test_timer_unit() {
+        let tenths = Protocol {
+            timer_unit: TimerUnit::Deciseconds,
+            ..Protocol::default()
+        };
+        assert_eq!(Protocol::default().timer(5, false).unwrap(), 5);
+        assert_eq!(tenths.timer(5, false).unwrap(), 50);
+
+        // the scaled value has to fit in the frame
+        assert_eq!(tenths.timer(6553, false).unwrap(), 65530);
+        assert!(tenths.timer(6554, false).is_err());
+        assert_eq!(tenths.timer(7000, true).unwrap(), 65530);
+    }
+}
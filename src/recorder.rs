@@ -0,0 +1,81 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// in-memory device which records the written frames
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+/// records everything written to a [`Port`](crate::Port) opened with
+/// [`Port::recording`](crate::Port::recording), reads never return data
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    written: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Recorder {
+    /// empty recorder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// all written bytes
+    pub fn written(&self) -> Vec<u8> {
+        self.written.lock().unwrap().clone()
+    }
+
+    /// written bytes split into 4 byte frames, a trailing partial frame is dropped
+    pub fn frames(&self) -> Vec<[u8; 4]> {
+        self.written
+            .lock()
+            .unwrap()
+            .chunks_exact(4)
+            .map(|chunk| [chunk[0], chunk[1], chunk[2], chunk[3]])
+            .collect()
+    }
+}
+
+impl Read for Recorder {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for Recorder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorder() {
+        let recorder = Recorder::new();
+        let mut writer = recorder.clone();
+
+        writer.write_all(&[0xF0, 0xA0, 0x0C, 0x54, 0xF0]).unwrap();
+
+        assert_eq!(recorder.written(), [0xF0, 0xA0, 0x0C, 0x54, 0xF0]);
+        assert_eq!(recorder.frames(), [[0xF0, 0xA0, 0x0C, 0x54]]);
+    }
+}
@@ -0,0 +1,127 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// interactive mode which keeps the port open between commands
+use crate::{dispatch, parse_timer, Cmd};
+use anyhow::{bail, Result};
+use std::io::{BufRead, Write};
+use tty_relay::Port;
+
+const HELP: &str =
+    "commands: on, off, toggle, jog, reset, restart, status, timed_start <s>, timed_stop <s>, quit";
+
+/// parse one input line, `None` ends the session
+pub fn parse_line(words: &[&str]) -> Result<Option<Cmd>> {
+    let seconds = || -> Result<u16> {
+        match words {
+            [_, secs] => parse_timer(secs).map_err(anyhow::Error::msg),
+            _ => bail!("{} takes the number of seconds", words[0]),
+        }
+    };
+
+    let cmd = match words[0] {
+        "quit" | "exit" => return Ok(None),
+        "timed_start" => return Ok(Some(Cmd::TimedOn(seconds()?))),
+        "timed_stop" => return Ok(Some(Cmd::TimedOff(seconds()?))),
+        _ if words.len() > 1 => bail!("{} takes no arguments", words[0]),
+        "on" => Cmd::On,
+        "off" => Cmd::Off,
+        "toggle" => Cmd::Toggle { report: false },
+        "jog" => Cmd::Jog,
+        "reset" => Cmd::Reset,
+        "restart" => Cmd::Restart,
+        "status" => Cmd::Status,
+        other => bail!("unknown command {}, {}", other, HELP),
+    };
+
+    Ok(Some(cmd))
+}
+
+/// read commands from `input` until EOF or quit, the relay is left as is
+pub fn run(
+    port: &mut Port,
+    input: impl BufRead,
+    mut output: impl Write,
+    prompt: bool,
+) -> Result<()> {
+    let mut lines = input.lines();
+
+    loop {
+        if prompt {
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+
+        let echo = words.join(" ");
+        match parse_line(&words) {
+            Ok(Some(cmd)) => match dispatch(port, cmd) {
+                Ok(Some(state)) => writeln!(output, "{}: {}", echo, state)?,
+                Ok(None) => writeln!(output, "{}: ok", echo)?,
+                Err(e) => writeln!(output, "{}: error: {:#}", echo, e)?,
+            },
+            Ok(None) => break,
+            Err(e) => writeln!(output, "{}: error: {:#}", echo, e)?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tty_relay::OpenOptions;
+
+    #[test]
+    fn test_parse_line() {
+        assert!(matches!(parse_line(&["on"]), Ok(Some(Cmd::On))));
+        assert!(matches!(
+            parse_line(&["timed_start", "5"]),
+            Ok(Some(Cmd::TimedOn(5)))
+        ));
+        assert!(matches!(parse_line(&["quit"]), Ok(None)));
+        assert!(parse_line(&["timed_stop"]).is_err());
+        assert!(parse_line(&["on", "5"]).is_err());
+        assert!(parse_line(&["dance"]).is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        let mut port = OpenOptions::new().dry_run(true).open().unwrap();
+        let input = Cursor::new("on\n\n  timed_start   5 \ndance\nquit\noff\n");
+        let mut output = Vec::new();
+
+        run(&mut port, input, &mut output, false).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "on: ok");
+        assert_eq!(lines[1], "timed_start 5: ok");
+        assert!(lines[2].starts_with("dance: error: unknown command"));
+        assert_eq!(lines.len(), 3);
+    }
+}
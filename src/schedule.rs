@@ -0,0 +1,124 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// wall-clock time and duration parsing
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use std::time::Duration;
+
+/// parse a duration like `90s`, `30m`, `1h30m` or `2d`
+pub fn parse_duration(val: &str) -> Result<Duration> {
+    let mut total: u64 = 0;
+    let mut number = String::new();
+
+    for c in val.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => bail!("invalid character {:?} in duration {}", c, val),
+        };
+        if number.is_empty() {
+            bail!("missing number before {:?} in duration {}", c, val);
+        }
+
+        let value: u64 = number.parse()?;
+        total = value
+            .checked_mul(unit)
+            .and_then(|value| total.checked_add(value))
+            .with_context(|| format!("duration {} is too large", val))?;
+        number.clear();
+    }
+
+    if !number.is_empty() || val.is_empty() {
+        bail!("missing unit (s, m, h or d) in duration {}", val);
+    }
+
+    Ok(Duration::from_secs(total))
+}
+
+/// parse an RFC3339 timestamp, a local `YYYY-MM-DDTHH:MM:SS` time or a `+30m` offset from `now`
+pub fn parse_time(val: &str, now: DateTime<Local>) -> Result<DateTime<Local>> {
+    if let Some(offset) = val.strip_prefix('+') {
+        let offset = chrono::Duration::from_std(parse_duration(offset)?)?;
+        return now
+            .checked_add_signed(offset)
+            .with_context(|| format!("time {} is out of range", val));
+    }
+
+    if let Ok(time) = DateTime::parse_from_rfc3339(val) {
+        return Ok(time.with_timezone(&Local));
+    }
+
+    let naive = NaiveDateTime::parse_from_str(val, "%Y-%m-%dT%H:%M:%S")
+        .with_context(|| format!("invalid time {}, expected RFC3339 or +<duration>", val))?;
+
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .with_context(|| format!("time {} does not exist in the local timezone", val))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(
+            parse_duration("1h30m").unwrap(),
+            Duration::from_secs(90 * 60)
+        );
+        assert_eq!(
+            parse_duration("2d").unwrap(),
+            Duration::from_secs(2 * 86400)
+        );
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("1.5h").is_err());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let now = Local::now();
+
+        assert_eq!(
+            parse_time("+30m", now).unwrap(),
+            now + chrono::Duration::minutes(30)
+        );
+        assert_eq!(
+            parse_time("2024-06-01T08:00:00Z", now).unwrap(),
+            DateTime::parse_from_rfc3339("2024-06-01T08:00:00+00:00").unwrap()
+        );
+        assert_eq!(
+            parse_time("2024-06-01T08:00:00", now)
+                .unwrap()
+                .naive_local()
+                .to_string(),
+            "2024-06-01 08:00:00"
+        );
+        assert!(parse_time("tomorrow", now).is_err());
+    }
+}
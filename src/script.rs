@@ -0,0 +1,184 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// semicolon separated command scripts run against one open port
+use crate::{dispatch, repl, Cmd};
+use anyhow::{bail, Context, Result};
+use std::io::BufRead;
+use std::time::Duration;
+use tty_relay::Port;
+
+#[derive(Clone)]
+pub enum Step {
+    Cmd(Cmd),
+    Sleep(Duration),
+}
+
+fn parse_step(words: &[&str]) -> Result<Step> {
+    if words[0] == "sleep" {
+        let secs = match words {
+            [_, secs] => secs.parse::<f64>().ok(),
+            _ => None,
+        };
+        let duration = secs
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .context("sleep takes a non-negative number of seconds")?;
+        return Ok(Step::Sleep(duration));
+    }
+
+    match repl::parse_line(words)? {
+        Some(cmd) => Ok(Step::Cmd(cmd)),
+        None => bail!("{} is not allowed in scripts", words[0]),
+    }
+}
+
+/// parse the whole script, steps are separated by `;`
+pub fn parse(script: &str) -> Result<Vec<(String, Step)>> {
+    let mut steps = Vec::new();
+
+    for text in script.split(';') {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+
+        let text = words.join(" ");
+        let step = parse_step(&words)
+            .with_context(|| format!("step {} ({}) is invalid", steps.len() + 1, text))?;
+        steps.push((text, step));
+    }
+
+    if steps.is_empty() {
+        bail!("script has no steps");
+    }
+
+    Ok(steps)
+}
+
+fn run_step(port: &mut Port, step: Step) -> Result<()> {
+    match step {
+        Step::Sleep(duration) => port.sleep(duration)?,
+        Step::Cmd(cmd) => {
+            dispatch(port, cmd)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// run the steps in order, stopping at the first failure
+pub fn run(port: &mut Port, steps: Vec<(String, Step)>) -> Result<()> {
+    for (i, (text, step)) in steps.into_iter().enumerate() {
+        log::debug!("step {}: {}", i + 1, text);
+        run_step(port, step).with_context(|| format!("step {} ({}) failed", i + 1, text))?;
+    }
+
+    Ok(())
+}
+
+/// run one step per line as the lines arrive, blank lines and `#` comments are skipped
+pub fn run_stream(port: &mut Port, input: impl BufRead) -> Result<()> {
+    for (i, line) in input.lines().enumerate() {
+        let line = line.context("failed to read the command stream")?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() || words[0].starts_with('#') {
+            continue;
+        }
+
+        let text = words.join(" ");
+        log::debug!("line {}: {}", i + 1, text);
+        let step =
+            parse_step(&words).with_context(|| format!("line {} ({}) is invalid", i + 1, text))?;
+        run_step(port, step).with_context(|| format!("line {} ({}) failed", i + 1, text))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use tty_relay::{Recorder, RelayError};
+
+    #[test]
+    fn test_parse() {
+        let steps = parse("on; sleep 0.5 ;off;; jog ;").unwrap();
+        let texts: Vec<&str> = steps.iter().map(|(text, _)| text.as_str()).collect();
+        assert_eq!(texts, ["on", "sleep 0.5", "off", "jog"]);
+        assert!(matches!(steps[1].1, Step::Sleep(d) if d == Duration::from_millis(500)));
+
+        let err = parse("on; dance; off").err().unwrap();
+        assert_eq!(err.to_string(), "step 2 (dance) is invalid");
+        assert!(parse("sleep -1").is_err());
+        assert!(parse("on; quit").is_err());
+        assert!(parse(" ; ").is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        let recorder = Recorder::new();
+        let mut port = Port::recording(&recorder);
+
+        run(&mut port, parse("on; sleep 0; toggle").unwrap()).unwrap();
+        assert_eq!(recorder.frames().len(), 4);
+
+        let err = run(&mut port, parse("off; status; on").unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), "step 2 (status) failed");
+        assert_eq!(recorder.frames().len(), 7);
+    }
+
+    #[test]
+    fn test_run_interrupted() {
+        let recorder = Recorder::new();
+        let mut port = Port::recording(&recorder);
+        let interrupt = Arc::new(AtomicBool::new(false));
+        port.set_interrupt(interrupt.clone());
+
+        let ctrlc = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            interrupt.store(true, Ordering::SeqCst);
+        });
+        let err = run(&mut port, parse("on; sleep 3600; off").unwrap()).unwrap_err();
+        ctrlc.join().unwrap();
+
+        assert_eq!(err.to_string(), "step 2 (sleep 3600) failed");
+        assert!(matches!(
+            RelayError::find(&err),
+            Some(RelayError::Interrupted(_))
+        ));
+        assert_eq!(recorder.frames().len(), 2);
+    }
+
+    #[test]
+    fn test_run_stream() {
+        let recorder = Recorder::new();
+        let mut port = Port::recording(&recorder);
+
+        let input = "# bench sequence\non\n\n  sleep 0\ntoggle\n";
+        run_stream(&mut port, input.as_bytes()).unwrap();
+        assert_eq!(recorder.frames().len(), 4);
+
+        let err = run_stream(&mut port, "off\n# probe\nstatus\non\n".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "line 3 (status) failed");
+        assert_eq!(recorder.frames().len(), 7);
+
+        let err = run_stream(&mut port, "dance".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "line 1 (dance) is invalid");
+    }
+}
@@ -0,0 +1,139 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// last known relay state, kept in a file across reboots
+use anyhow::Result;
+use log::{debug, warn};
+use std::fs;
+use std::io;
+use std::path::Path;
+use tty_relay::{Port, RelayState};
+
+/// saved state, a missing file is unknown and a corrupt one is ignored
+pub fn load(path: &Path) -> Option<RelayState> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            debug!("no saved state in {}", path.display());
+            return None;
+        }
+        Err(e) => {
+            warn!("failed to read state file {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    match content.trim() {
+        "on" => Some(RelayState::On),
+        "off" => Some(RelayState::Off),
+        other => {
+            warn!(
+                "ignoring corrupt state file {}: {:?}",
+                path.display(),
+                other
+            );
+            None
+        }
+    }
+}
+
+/// save the state, an unknown state removes the file
+///
+/// the command already succeeded, so failures are only logged
+pub fn update(path: &Path, state: Option<RelayState>) {
+    let result = match state {
+        Some(state) => path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(path, format!("{}\n", state))),
+        None => match fs::remove_file(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        },
+    };
+
+    match result {
+        Ok(()) => debug!("saved state {:?} to {}", state, path.display()),
+        Err(e) => warn!("failed to update state file {}: {}", path.display(), e),
+    }
+}
+
+pub fn inverted(state: RelayState) -> RelayState {
+    match state {
+        RelayState::On => RelayState::Off,
+        RelayState::Off => RelayState::On,
+    }
+}
+
+/// re-apply the saved state, nothing is sent when it is unknown
+pub fn restore(port: &mut Port, path: &Path) -> Result<Option<RelayState>> {
+    let state = match load(path) {
+        Some(state) => state,
+        None => {
+            warn!("relay state is unknown, nothing to restore");
+            return Ok(None);
+        }
+    };
+
+    match state {
+        RelayState::On => port.on()?,
+        RelayState::Off => port.off()?,
+    }
+
+    Ok(Some(state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_state_file() {
+        let dir = env::temp_dir().join(format!("tty_relay_state_{}", std::process::id()));
+        let path = dir.join("state");
+
+        assert_eq!(load(&path), None);
+
+        update(&path, Some(RelayState::On));
+        assert_eq!(load(&path), Some(RelayState::On));
+
+        fs::write(&path, "maybe").unwrap();
+        assert_eq!(load(&path), None);
+
+        update(&path, None);
+        assert!(!path.exists());
+        update(&path, None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_restore() {
+        let dir = env::temp_dir().join(format!("tty_relay_restore_{}", std::process::id()));
+        let path = dir.join("state");
+        let mut port = Port::open_mock();
+
+        assert_eq!(restore(&mut port, &path).unwrap(), None);
+        assert!(port.recorder().unwrap().frames().is_empty());
+
+        update(&path, Some(RelayState::Off));
+        assert_eq!(restore(&mut port, &path).unwrap(), Some(RelayState::Off));
+        assert_eq!(port.recorder().unwrap().frames().len(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// guided check of the NO/NC wiring, see the `verify-wiring` command
+use crate::ask;
+use anyhow::Result;
+use std::io::{BufRead, Write};
+use tty_relay::{Port, Wiring};
+
+/// what the answers say about the configured wiring
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// power followed on and off
+    Matches,
+    /// power did the opposite, the other contact is used
+    Inverted,
+    /// power did not follow at all, or stayed in one state
+    Inconclusive,
+}
+
+/// switch the relay on and off, asking after each step what the load did
+///
+/// the relay is left off
+pub fn run(port: &mut Port, mut input: impl BufRead, mut output: impl Write) -> Result<Verdict> {
+    writeln!(output, "the relay will be switched on and then off again")?;
+
+    port.on()?;
+    let on = ask(&mut input, &mut output, "Did power turn ON?")?;
+    port.off()?;
+    let off = ask(&mut input, &mut output, "Did power turn OFF?")?;
+
+    let verdict = match (on, off) {
+        (true, true) => Verdict::Matches,
+        (false, false) => Verdict::Inverted,
+        _ => Verdict::Inconclusive,
+    };
+    writeln!(output, "{}", report(&verdict, port.wiring()))?;
+
+    Ok(verdict)
+}
+
+fn report(verdict: &Verdict, wiring: Wiring) -> String {
+    let (current, suggested) = match wiring {
+        Wiring::NormallyOpen => ("normally open", "pass --normally-closed"),
+        Wiring::NormallyClosed => ("normally closed", "drop --normally-closed"),
+    };
+
+    match verdict {
+        Verdict::Matches => format!("the wiring matches the configured {} mode", current),
+        Verdict::Inverted => format!(
+            "the load is wired to the other contact than the configured {} mode, {} (or normally_closed in the config)",
+            current, suggested
+        ),
+        Verdict::Inconclusive => {
+            "power did not follow the relay, check the load wiring and that the board clicks"
+                .to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tty_relay::Recorder;
+
+    fn verify(wiring: Wiring, answers: &str) -> (Verdict, String, Vec<[u8; 4]>) {
+        let recorder = Recorder::new();
+        let mut port = Port::recording(&recorder);
+        port.set_wiring(wiring);
+
+        let mut output = Vec::new();
+        let verdict = run(&mut port, answers.as_bytes(), &mut output).unwrap();
+        (
+            verdict,
+            String::from_utf8(output).unwrap(),
+            recorder.frames(),
+        )
+    }
+
+    #[test]
+    fn test_verify_wiring() {
+        let (verdict, output, frames) = verify(Wiring::NormallyOpen, "y\ny\n");
+        assert_eq!(verdict, Verdict::Matches);
+        assert!(output.contains("Did power turn ON? [y/N] Did power turn OFF?"));
+        assert!(output.contains("matches the configured normally open mode"));
+        assert_eq!(frames[1], [0xF0, 0xA0, 0x01, 0x53]);
+        assert_eq!(frames[3], [0xF0, 0xA0, 0x00, 0x53]);
+
+        let (verdict, output, _) = verify(Wiring::NormallyOpen, "n\nn\n");
+        assert_eq!(verdict, Verdict::Inverted);
+        assert!(output.contains("pass --normally-closed"));
+
+        let (verdict, output, _) = verify(Wiring::NormallyClosed, "no\n\n");
+        assert_eq!(verdict, Verdict::Inverted);
+        assert!(output.contains("drop --normally-closed"));
+
+        let (verdict, _, _) = verify(Wiring::NormallyOpen, "y\nn\n");
+        assert_eq!(verdict, Verdict::Inconclusive);
+    }
+}
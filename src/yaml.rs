@@ -0,0 +1,157 @@
+/*
+ * Copyright (C) 2020 Maxim Zhukov <mussitantesmortem@gmail.com>
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+/// block style YAML for `--format yaml`
+///
+/// serialized through the serde_json data model, so the YAML output carries
+/// exactly the fields of the JSON one. a small emitter instead of serde_yaml,
+/// which is unmaintained, since only block style output is needed; strings
+/// are quoted unless they are plainly words or paths
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+
+pub fn to_string(value: &impl Serialize) -> Result<String> {
+    let mut out = String::new();
+    block(&mut out, &serde_json::to_value(value)?, 0);
+    Ok(out)
+}
+
+/// write `value` at the current position, continuation lines are indented by `indent`
+fn block(out: &mut String, value: &Value, indent: usize) {
+    let pad = " ".repeat(indent);
+
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (i, (key, value)) in map.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(&pad);
+                }
+                out.push_str(&scalar(&Value::String(key.clone())));
+                out.push(':');
+                if is_collection(value) {
+                    out.push('\n');
+                    out.push_str(&pad);
+                    out.push_str("  ");
+                } else {
+                    out.push(' ');
+                }
+                block(out, value, indent + 2);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(&pad);
+                }
+                out.push_str("- ");
+                block(out, item, indent + 2);
+            }
+        }
+        value => {
+            out.push_str(&scalar(value));
+            out.push('\n');
+        }
+    }
+}
+
+fn is_collection(value: &Value) -> bool {
+    match value {
+        Value::Object(map) => !map.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        _ => false,
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Object(_) => "{}".to_string(),
+        Value::Array(_) => "[]".to_string(),
+        Value::String(s) if is_plain(s) => s.clone(),
+        // a JSON string is a valid double quoted YAML scalar
+        value => value.to_string(),
+    }
+}
+
+/// strings which every YAML version reads back as the same string without
+/// quotes: `[A-Za-z][A-Za-z0-9_./-]*` except the YAML 1.1 booleans and null,
+/// so numbers in any notation (`0x1A`, `1_000`, `.5`) are always quoted
+fn is_plain(s: &str) -> bool {
+    let reserved = ["true", "false", "yes", "no", "on", "off", "null", "y", "n"];
+
+    s.starts_with(|c: char| c.is_ascii_alphabetic())
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./".contains(c))
+        && !reserved.contains(&s.to_lowercase().as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_to_string() {
+        let report =
+            json!({"command": "status", "port": "/dev/ttyUSB0", "ok": true, "state": "on"});
+        assert_eq!(
+            to_string(&report).unwrap(),
+            "command: status\nport: \"/dev/ttyUSB0\"\nok: true\nstate: \"on\"\n"
+        );
+
+        let devices = json!([
+            {"port": "/dev/ttyUSB0", "vid": "1a86", "pid": "7523", "serial": null},
+            {"port": "COM3", "vid": "0403", "pid": "6001", "serial": "A1 B2"},
+        ]);
+        assert_eq!(
+            to_string(&devices).unwrap(),
+            "- port: \"/dev/ttyUSB0\"\n  vid: \"1a86\"\n  pid: \"7523\"\n  serial: null\n\
+             - port: COM3\n  vid: \"0403\"\n  pid: \"6001\"\n  serial: \"A1 B2\"\n"
+        );
+
+        let nested = json!({"frames": ["A0 01 01 A2"], "empty": [], "inner": {"a": {"b": 1}}});
+        assert_eq!(
+            to_string(&nested).unwrap(),
+            "frames:\n  - \"A0 01 01 A2\"\nempty: []\ninner:\n  a:\n    b: 1\n"
+        );
+        assert_eq!(to_string(&Vec::<u8>::new()).unwrap(), "[]\n");
+    }
+
+    #[test]
+    fn test_is_plain() {
+        for plain in ["status", "ttyUSB0", "relay/1", "hc-05", "a_b.c"] {
+            assert!(is_plain(plain), "{}", plain);
+        }
+        for quoted in [
+            "",
+            "0x1A",
+            "1_000",
+            "1e3",
+            ".5",
+            "-1",
+            "~",
+            "No",
+            "ON",
+            "/dev/ttyUSB0",
+            "A1 B2",
+            ":x",
+            "é",
+        ] {
+            assert!(!is_plain(quoted), "{}", quoted);
+        }
+    }
+}